@@ -0,0 +1,246 @@
+//! Local files attached to a prompt as context.
+//!
+//! A pattern names a file, a directory, or a glob (`*`, `?`, and `**` for any number of
+//! directories).  Each file is wrapped in a fenced block headed by its path.  Binary files are
+//! refused; when a directory or glob turns one up it is skipped instead.  Hidden files and
+//! directories are only attached when named explicitly.
+//!
+//! Attachments must fit within half the model's context, as set by the `num_ctx` option, so that
+//! the model has room for the question and its answer.  Each attachment carries the SHA-256 of its
+//! content so that a log records exactly what the model saw.
+
+use std::path::{Path, PathBuf};
+
+use sha2::Digest;
+
+use super::Error;
+
+/// The context length ollama uses when `num_ctx` is unset.
+pub const DEFAULT_CONTEXT: usize = 4096;
+
+/// A rough number of bytes per token, for budgeting before the model tokenizes anything.
+const BYTES_PER_TOKEN: usize = 4;
+
+/// The prefix of a file that is checked for NUL bytes.
+const BINARY_SNIFF: usize = 8192;
+
+//////////////////////////////////////////// Attachment ////////////////////////////////////////////
+
+/// A file attached to a prompt.
+#[derive(Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Attachment {
+    /// The path of the file as given or found.
+    pub path: String,
+    /// The size of the file in bytes.
+    pub bytes: usize,
+    /// The hex SHA-256 of the file's content.
+    pub sha256: String,
+    /// The content of the file.  Logs record the digest rather than the content.
+    #[serde(skip)]
+    pub content: String,
+}
+
+impl Attachment {
+    /// Read the file at `path`, refusing binaries.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        if bytes[..bytes.len().min(BINARY_SNIFF)].contains(&0) {
+            return Err(Error::Message(format!("{} is binary", path.display())));
+        }
+        let sha256 = sha256(&bytes);
+        let content = String::from_utf8(bytes)
+            .map_err(|_| Error::Message(format!("{} is not UTF-8 text", path.display())))?;
+        Ok(Self {
+            path: path.display().to_string(),
+            bytes: content.len(),
+            sha256,
+            content,
+        })
+    }
+
+    /// The estimated number of tokens the attachment will take.
+    pub fn tokens(&self) -> usize {
+        self.bytes.div_ceil(BYTES_PER_TOKEN)
+    }
+
+    /// Render the attachment as a fenced block headed by its path.
+    pub fn render(&self) -> String {
+        // NOTE(rescrv):  The fence must be longer than any run of backticks in the file.
+        let mut longest = 0;
+        let mut run = 0;
+        for c in self.content.chars() {
+            run = if c == '`' { run + 1 } else { 0 };
+            longest = longest.max(run);
+        }
+        let fence = "`".repeat(longest.max(2) + 1);
+        let lang = Path::new(&self.path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("");
+        let newline = if self.content.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        format!(
+            "File: {}\n{fence}{lang}\n{}{newline}{fence}\n",
+            self.path, self.content
+        )
+    }
+}
+
+////////////////////////////////////////////// attach //////////////////////////////////////////////
+
+/// Read the files named by `patterns`.
+///
+/// Binaries named explicitly are an error; binaries found in directories or by globs are skipped
+/// with a note on stderr.
+pub fn attach<S: AsRef<str>>(patterns: &[S]) -> Result<Vec<Attachment>, Error> {
+    let mut attachments: Vec<Attachment> = vec![];
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let explicit = !is_glob(pattern) && !Path::new(pattern).is_dir();
+        let paths = expand(pattern)?;
+        if paths.is_empty() {
+            return Err(Error::Message(format!("no files match {pattern}")));
+        }
+        for path in paths {
+            if attachments.iter().any(|a| Path::new(&a.path) == path) {
+                continue;
+            }
+            match Attachment::read(&path) {
+                Ok(attachment) => attachments.push(attachment),
+                Err(err) if !explicit => {
+                    eprintln!("skipping {}: {}", path.display(), err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok(attachments)
+}
+
+/// The number of tokens attachments may take given the model options.
+pub fn budget(options: Option<&serde_json::Value>) -> usize {
+    let context = options
+        .and_then(|o| o.get("num_ctx"))
+        .and_then(|n| n.as_u64())
+        .map(|n| n as usize)
+        .unwrap_or(DEFAULT_CONTEXT);
+    context / 2
+}
+
+/// Fail unless `attachments` fit within `budget` tokens.
+pub fn check_budget(attachments: &[Attachment], budget: usize) -> Result<(), Error> {
+    let tokens = attachments.iter().map(Attachment::tokens).sum::<usize>();
+    if tokens > budget {
+        let mut largest = attachments.iter().collect::<Vec<_>>();
+        largest.sort_by_key(|a| std::cmp::Reverse(a.bytes));
+        let largest = largest
+            .iter()
+            .take(3)
+            .map(|a| format!("{} (~{} tokens)", a.path, a.tokens()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(Error::Message(format!(
+            "attachments take ~{tokens} tokens, more than the budget of {budget}; raise num_ctx or attach less.  largest: {largest}"
+        )));
+    }
+    Ok(())
+}
+
+/// Prefix `prompt` with the rendered attachments.
+pub fn render(attachments: &[Attachment], prompt: &str) -> String {
+    let mut out = String::new();
+    for attachment in attachments {
+        out += &attachment.render();
+        out += "\n";
+    }
+    out += prompt;
+    out
+}
+
+////////////////////////////////////////////// expand //////////////////////////////////////////////
+
+/// The files named by `pattern`, in sorted order.
+pub fn expand(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+    if !is_glob(pattern) {
+        let path = PathBuf::from(pattern);
+        if path.is_dir() {
+            let mut files = vec![];
+            walk(&path, &mut files)?;
+            files.sort();
+            return Ok(files);
+        }
+        if !path.exists() {
+            return Err(Error::Message(format!("{pattern} does not exist")));
+        }
+        return Ok(vec![path]);
+    }
+    // NOTE(rescrv):  Walk from the longest prefix without wildcards and match the rest.
+    let components = pattern.split('/').collect::<Vec<_>>();
+    let literal = components.iter().take_while(|c| !is_glob(c)).count();
+    let base = components[..literal].join("/");
+    let root = match (base.as_str(), pattern.starts_with('/')) {
+        ("", true) => PathBuf::from("/"),
+        ("", false) => PathBuf::from("."),
+        (base, _) => PathBuf::from(base),
+    };
+    let rest = components[literal..].join("/");
+    let glob = globset::GlobBuilder::new(&rest)
+        .literal_separator(true)
+        .build()
+        .map_err(|err| Error::Message(format!("{pattern}: {err}")))?
+        .compile_matcher();
+    let mut files = vec![];
+    if root.is_dir() {
+        walk(&root, &mut files)?;
+    }
+    let mut matched = files
+        .into_iter()
+        .filter(|file| glob.is_match(file.strip_prefix(&root).unwrap_or(file)))
+        .map(|file| {
+            if base.is_empty() {
+                file.strip_prefix(".")
+                    .map(Path::to_path_buf)
+                    .unwrap_or(file)
+            } else {
+                file
+            }
+        })
+        .collect::<Vec<_>>();
+    matched.sort();
+    Ok(matched)
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Collect every regular file under `dir`, skipping hidden entries.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////// sha256 //////////////////////////////////////////////
+
+/// The hex SHA-256 of `data`, as printed by `sha256sum`.
+pub fn sha256(data: &[u8]) -> String {
+    sha2::Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
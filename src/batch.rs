@@ -0,0 +1,270 @@
+//! Batch execution of many generate and chat requests with bounded parallelism.
+//!
+//! The input is newline-delimited JSON where each line is either a [GenerateRequest] or a
+//! [ChatRequest] with an optional `id`.  Results are written as newline-delimited JSON in input
+//! order, each tagged with the `index` of its input line and its `id`.  A failed item records its
+//! error and the batch carries on.  Rerunning a batch against the same output skips every item
+//! that already succeeded and appends results for the rest.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+
+use super::{ChatRequest, Error, GenerateRequest, Request, RequestOptions, VecAccumulator};
+
+/////////////////////////////////////////// BatchOptions ///////////////////////////////////////////
+
+#[derive(Clone, Debug, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct BatchOptions {
+    #[arrrg(required, "Newline-delimited JSON file of generate or chat requests.")]
+    pub input: String,
+    #[arrrg(required, "Newline-delimited JSON file to append results to.")]
+    pub output: String,
+    #[arrrg(optional, "Maximum number of requests in flight at once.")]
+    pub concurrency: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            input: "".to_string(),
+            output: "".to_string(),
+            concurrency: 4,
+        }
+    }
+}
+
+//////////////////////////////////////////// BatchItem /////////////////////////////////////////////
+
+/// A request to run as part of a batch.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+pub enum BatchRequest {
+    Chat(ChatRequest),
+    Generate(GenerateRequest),
+}
+
+/// One line of batch input.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct BatchItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub request: BatchRequest,
+}
+
+/// Read batch items from newline-delimited JSON, skipping blank lines.
+pub fn read_items(path: impl AsRef<std::path::Path>) -> Result<Vec<BatchItem>, Error> {
+    let content = std::fs::read_to_string(path)?;
+    let mut items = vec![];
+    for (idx, line) in content.split_terminator('\n').enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let item = serde_json::from_str(line)
+            .map_err(|err| Error::Message(format!("line {}: {err}", idx + 1)))?;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/////////////////////////////////////////// BatchResult ////////////////////////////////////////////
+
+/// The outcome of one batch item.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct BatchResult {
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The final message of the stream, which carries the timing and token counts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<serde_json::Value>,
+}
+
+/// The indices of every item that succeeded according to a previously written output file.
+///
+/// A torn final line left by an interrupted run is cut from the file.
+pub fn completed(path: impl AsRef<std::path::Path>) -> Result<HashSet<usize>, Error> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    let mut content = std::fs::read_to_string(path)?;
+    // NOTE(rescrv):  A torn final line doesn't count, and results are appended after it, so cut
+    // it off lest the next result be written onto its end and never parse.
+    if !content.is_empty() && !content.ends_with('\n') {
+        let keep = content.rfind('\n').map_or(0, |idx| idx + 1);
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(keep as u64)?;
+        content.truncate(keep);
+    }
+    let mut done = HashSet::new();
+    for line in content.split_terminator('\n') {
+        let Ok(result) = serde_json::from_str::<BatchResult>(line.trim()) else {
+            continue;
+        };
+        if result.error.is_none() {
+            done.insert(result.index);
+        }
+    }
+    Ok(done)
+}
+
+/////////////////////////////////////////////// run ////////////////////////////////////////////////
+
+/// Run `items` with at most `concurrency` requests in flight, skipping the indices in `skip`.
+///
+/// Every request shares a single transport.  `sink` sees the results in input order.
+pub async fn run(
+    options: RequestOptions,
+    items: Vec<BatchItem>,
+    concurrency: usize,
+    skip: &HashSet<usize>,
+    mut sink: impl FnMut(BatchResult) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let transport = options.transport()?;
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(concurrency.max(1)));
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut expected = VecDeque::new();
+    let mut pending = BTreeMap::new();
+    for (index, item) in items.into_iter().enumerate() {
+        if skip.contains(&index) {
+            continue;
+        }
+        expected.push_back(index);
+        let permit = Arc::clone(&semaphore)
+            .acquire_owned()
+            .await
+            .map_err(|err| Error::Message(format!("semaphore closed: {err}")))?;
+        let options = options.clone();
+        let transport = transport.clone();
+        let tx = tx.clone();
+        let id = item.id.clone();
+        tokio::spawn(async move {
+            // NOTE(rescrv):  A panicking item must still produce a result, or the reorder queue
+            // waits on its index forever and nothing after it is ever written.
+            let result = match tokio::spawn(execute(options, transport, index, item)).await {
+                Ok(result) => result,
+                Err(err) => BatchResult {
+                    index,
+                    id,
+                    response: None,
+                    error: Some(format!("item panicked: {err}")),
+                    stats: None,
+                },
+            };
+            drop(permit);
+            let _ = tx.send(result);
+        });
+        // NOTE(rescrv):  Drain finished items while spawning so that output is written as the
+        // batch progresses rather than after the last item has been dispatched.
+        while let Ok(result) = rx.try_recv() {
+            reorder(&mut expected, &mut pending, result, &mut sink)?;
+        }
+    }
+    drop(tx);
+    while let Some(result) = rx.recv().await {
+        reorder(&mut expected, &mut pending, result, &mut sink)?;
+    }
+    Ok(())
+}
+
+fn reorder(
+    expected: &mut VecDeque<usize>,
+    pending: &mut BTreeMap<usize, BatchResult>,
+    result: BatchResult,
+    sink: &mut impl FnMut(BatchResult) -> Result<(), Error>,
+) -> Result<(), Error> {
+    pending.insert(result.index, result);
+    while let Some(next) = expected.front().copied() {
+        let Some(result) = pending.remove(&next) else {
+            break;
+        };
+        expected.pop_front();
+        sink(result)?;
+    }
+    Ok(())
+}
+
+/// Run one item over `transport`, which is shared with every other item of the batch.
+pub(crate) async fn execute(
+    options: RequestOptions,
+    transport: super::Transport,
+    index: usize,
+    item: BatchItem,
+) -> BatchResult {
+    let mut result = BatchResult {
+        index,
+        id: item.id,
+        response: None,
+        error: None,
+        stats: None,
+    };
+    let (req, field) = match item.request {
+        BatchRequest::Generate(generate) => (Request::generate(options, generate), "response"),
+        BatchRequest::Chat(chat) => (Request::chat(options, chat), "message"),
+    };
+    let req = match req {
+        Ok(req) => req.with_transport(transport),
+        Err(err) => {
+            result.error = Some(err.to_string());
+            return result;
+        }
+    };
+    let mut pieces = vec![];
+    if let Err(err) = req.accumulate(&mut VecAccumulator::new(&mut pieces)).await {
+        result.error = Some(err.to_string());
+        return result;
+    }
+    let response = pieces
+        .iter()
+        .flat_map(|piece| {
+            let value = piece.get(field)?;
+            let value = if field == "message" {
+                value.get("content")?
+            } else {
+                value
+            };
+            value.as_str().map(String::from)
+        })
+        .collect::<Vec<_>>()
+        .join("");
+    result.response = Some(response);
+    result.stats = pieces.pop();
+    result
+}
+
+////////////////////////////////////////////// batch ///////////////////////////////////////////////
+
+/// Run the batch described by `batch`, resuming from its output file if it exists.
+///
+/// Returns the number of items that failed.
+pub async fn batch(options: RequestOptions, batch: BatchOptions) -> Result<usize, Error> {
+    let items = read_items(&batch.input)?;
+    let skip = completed(&batch.output)?;
+    let output = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&batch.output)?;
+    let mut output = BufWriter::new(output);
+    let mut failed = 0;
+    run(options, items, batch.concurrency, &skip, |result| {
+        if let Some(err) = result.error.as_ref() {
+            eprintln!("item {} failed: {err}", result.index);
+            failed += 1;
+        }
+        writeln!(output, "{}", serde_json::to_string(&result)?)?;
+        output.flush()?;
+        Ok(())
+    })
+    .await?;
+    Ok(failed)
+}
@@ -0,0 +1,438 @@
+//! Throughput and latency benchmarking.
+//!
+//! [bench] loads each model with an empty request, records how long the load took, and then sends
+//! every prompt `runs` times with up to `concurrency` requests in flight.  Each request measures
+//! the time to its first token and its end-to-end latency on the client, and takes the generation
+//! and prompt processing rates from the `eval_count`, `eval_duration`, `prompt_eval_count`, and
+//! `prompt_eval_duration` that ollama reports in its final message.  Models run in sequence so
+//! that they do not compete for the hardware.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Accumulator, Error, GenerateRequest, Request, RequestOptions};
+
+/// The prompt to benchmark with when no prompts file is given.
+const DEFAULT_PROMPT: &str = "Why is the sky blue?";
+
+/////////////////////////////////////////// BenchOptions ///////////////////////////////////////////
+
+#[derive(Clone, Debug, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct BenchOptions {
+    #[arrrg(optional, "Comma-separated models to benchmark, one after another.")]
+    pub model: Option<String>,
+    #[arrrg(optional, "File of prompts, one per line.")]
+    pub prompts: Option<String>,
+    #[arrrg(optional, "Maximum number of requests in flight at once.")]
+    pub concurrency: usize,
+    #[arrrg(optional, "Number of times to send each prompt.")]
+    pub runs: usize,
+    #[arrrg(optional, "File to write the results to as JSON.")]
+    pub output: Option<String>,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            model: None,
+            prompts: None,
+            concurrency: 1,
+            runs: 3,
+            output: None,
+        }
+    }
+}
+
+impl BenchOptions {
+    /// The models named by `--model`, in order.
+    pub fn models(&self) -> Vec<String> {
+        self.model
+            .iter()
+            .flat_map(|m| m.split(','))
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// The prompts in `--prompts`, skipping blank lines, or a default prompt.
+    pub fn load_prompts(&self) -> Result<Vec<String>, Error> {
+        let Some(path) = self.prompts.as_ref() else {
+            return Ok(vec![DEFAULT_PROMPT.to_string()]);
+        };
+        let prompts = std::fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if prompts.is_empty() {
+            return Err(Error::Message(format!("{path}: no prompts")));
+        }
+        Ok(prompts)
+    }
+}
+
+///////////////////////////////////////// TimingAccumulator ////////////////////////////////////////
+
+/// TimingAccumulator notes when the first token arrives and keeps the final message.
+#[derive(Debug)]
+struct TimingAccumulator {
+    start: Instant,
+    first_token: Option<Duration>,
+    last: Option<serde_json::Value>,
+}
+
+impl TimingAccumulator {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            first_token: None,
+            last: None,
+        }
+    }
+}
+
+impl Accumulator for TimingAccumulator {
+    fn accumulate(&mut self, message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        let token = message
+            .get("response")
+            .and_then(|r| r.as_str())
+            .is_some_and(|r| !r.is_empty());
+        if token && self.first_token.is_none() {
+            self.first_token = Some(self.start.elapsed());
+        }
+        self.last = Some(message);
+        std::ops::ControlFlow::Continue(())
+    }
+}
+
+////////////////////////////////////////////// Sample //////////////////////////////////////////////
+
+/// The measurements of one request.  Times are in seconds.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Sample {
+    /// The index of the prompt in the prompts file.
+    pub prompt: usize,
+    pub run: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_first_token: Option<f64>,
+    pub latency: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_per_second: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_second: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Take the counts and durations ollama reports in its final message.
+fn stats(sample: &mut Sample, last: &serde_json::Value) {
+    let count = |key: &str| last.get(key).and_then(|c| c.as_u64());
+    let seconds = |key: &str| {
+        last.get(key)
+            .and_then(|d| d.as_f64())
+            .map(|ns| ns / 1_000_000_000.0)
+    };
+    let rate = |tokens: Option<u64>, secs: Option<f64>| match (tokens, secs) {
+        (Some(tokens), Some(secs)) if secs > 0.0 => Some(tokens as f64 / secs),
+        _ => None,
+    };
+    sample.load = seconds("load_duration");
+    sample.prompt_tokens = count("prompt_eval_count");
+    sample.prompt_tokens_per_second = rate(sample.prompt_tokens, seconds("prompt_eval_duration"));
+    sample.tokens = count("eval_count");
+    sample.tokens_per_second = rate(sample.tokens, seconds("eval_duration"));
+}
+
+async fn measure(
+    options: RequestOptions,
+    transport: super::Transport,
+    model: String,
+    prompt: String,
+) -> Sample {
+    let mut sample = Sample::default();
+    let request = GenerateRequest {
+        model,
+        prompt,
+        ..GenerateRequest::default()
+    };
+    let mut timing = TimingAccumulator::new();
+    let result = match Request::generate(options, request) {
+        Ok(req) => req.with_transport(transport).accumulate(&mut timing).await,
+        Err(err) => Err(err),
+    };
+    sample.latency = timing.start.elapsed().as_secs_f64();
+    sample.time_to_first_token = timing.first_token.map(|d| d.as_secs_f64());
+    if let Some(last) = timing.last.as_ref() {
+        stats(&mut sample, last);
+    }
+    if let Err(err) = result {
+        sample.error = Some(err.to_string());
+    }
+    sample
+}
+
+/////////////////////////////////////////// Distribution ///////////////////////////////////////////
+
+/// Summary statistics of a set of measurements.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Distribution {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    /// Summarize `values`, or None if there are none.
+    pub fn new(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        // NOTE(rescrv):  Nearest rank, so every percentile is a latency that was observed.
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+        Some(Self {
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+//////////////////////////////////////////// ModelResult ///////////////////////////////////////////
+
+/// The benchmark of one model.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ModelResult {
+    pub model: String,
+    pub concurrency: usize,
+    pub requests: usize,
+    pub errors: usize,
+    /// Seconds to load the model, as reported for the warm-up request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load: Option<f64>,
+    /// Seconds from the first request to the last response, excluding the warm-up.
+    pub wall: f64,
+    /// Generated tokens per second across all requests, counting concurrency.
+    pub aggregate_tokens_per_second: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_first_token: Option<Distribution>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<Distribution>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_second: Option<Distribution>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_per_second: Option<Distribution>,
+    pub samples: Vec<Sample>,
+}
+
+impl ModelResult {
+    fn new(
+        model: String,
+        concurrency: usize,
+        load: Option<f64>,
+        wall: f64,
+        samples: Vec<Sample>,
+    ) -> Self {
+        let ok = samples
+            .iter()
+            .filter(|s| s.error.is_none())
+            .collect::<Vec<_>>();
+        let collect = |f: fn(&Sample) -> Option<f64>| ok.iter().filter_map(|s| f(s)).collect();
+        let tokens = ok.iter().filter_map(|s| s.tokens).sum::<u64>();
+        Self {
+            model,
+            concurrency,
+            requests: samples.len(),
+            errors: samples.len() - ok.len(),
+            load,
+            wall,
+            aggregate_tokens_per_second: if wall > 0.0 {
+                tokens as f64 / wall
+            } else {
+                0.0
+            },
+            time_to_first_token: Distribution::new(collect(|s| s.time_to_first_token)),
+            latency: Distribution::new(collect(|s| Some(s.latency))),
+            tokens_per_second: Distribution::new(collect(|s| s.tokens_per_second)),
+            prompt_tokens_per_second: Distribution::new(collect(|s| s.prompt_tokens_per_second)),
+            samples,
+        }
+    }
+}
+
+/// Render `results` as a table with one row per model.
+pub fn table(results: &[ModelResult]) -> String {
+    let secs = |d: Option<f64>| d.map(|d| format!("{d:.2}s")).unwrap_or("-".to_string());
+    let rate = |d: Option<f64>| d.map(|d| format!("{d:.1}")).unwrap_or("-".to_string());
+    let header = [
+        "model",
+        "reqs",
+        "errs",
+        "load",
+        "ttft p50",
+        "ttft p90",
+        "ttft p99",
+        "lat p50",
+        "lat p90",
+        "lat p99",
+        "tok/s",
+        "agg tok/s",
+    ];
+    let mut rows = vec![header.iter().map(|h| h.to_string()).collect::<Vec<_>>()];
+    for result in results {
+        let ttft = result.time_to_first_token.as_ref();
+        let latency = result.latency.as_ref();
+        rows.push(vec![
+            result.model.clone(),
+            result.requests.to_string(),
+            result.errors.to_string(),
+            secs(result.load),
+            secs(ttft.map(|d| d.p50)),
+            secs(ttft.map(|d| d.p90)),
+            secs(ttft.map(|d| d.p99)),
+            secs(latency.map(|d| d.p50)),
+            secs(latency.map(|d| d.p90)),
+            secs(latency.map(|d| d.p99)),
+            rate(result.tokens_per_second.as_ref().map(|d| d.mean)),
+            rate(Some(result.aggregate_tokens_per_second)),
+        ]);
+    }
+    let widths = (0..header.len())
+        .map(|col| {
+            rows.iter()
+                .map(|r| r[col].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let mut out = String::new();
+    for row in rows {
+        let cells = row
+            .iter()
+            .enumerate()
+            .map(|(col, cell)| {
+                if col == 0 {
+                    format!("{cell:<width$}", width = widths[col])
+                } else {
+                    format!("{cell:>width$}", width = widths[col])
+                }
+            })
+            .collect::<Vec<_>>();
+        out += cells.join("  ").trim_end();
+        out += "\n";
+    }
+    out
+}
+
+/////////////////////////////////////////////// bench //////////////////////////////////////////////
+
+/// Benchmark `model`:  warm it up, then send every prompt `runs` times.
+pub async fn bench_model(
+    options: RequestOptions,
+    model: &str,
+    prompts: &[String],
+    concurrency: usize,
+    runs: usize,
+) -> Result<ModelResult, Error> {
+    let transport = options.transport()?;
+    // NOTE(rescrv):  An empty prompt loads the model without generating anything.
+    let warm = measure(
+        options.clone(),
+        transport.clone(),
+        model.to_string(),
+        String::new(),
+    )
+    .await;
+    if let Some(err) = warm.error {
+        return Err(Error::Message(format!("warming {model}: {err}")));
+    }
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(concurrency.max(1)));
+    let start = Instant::now();
+    let mut handles = vec![];
+    for run in 0..runs {
+        for (idx, prompt) in prompts.iter().enumerate() {
+            let permit = Arc::clone(&semaphore)
+                .acquire_owned()
+                .await
+                .map_err(|err| Error::Message(format!("semaphore closed: {err}")))?;
+            let options = options.clone();
+            let transport = transport.clone();
+            let model = model.to_string();
+            let prompt = prompt.clone();
+            handles.push(tokio::spawn(async move {
+                let mut sample = measure(options, transport, model, prompt).await;
+                drop(permit);
+                sample.prompt = idx;
+                sample.run = run;
+                sample
+            }));
+        }
+    }
+    let mut samples = vec![];
+    for handle in handles {
+        samples.push(
+            handle
+                .await
+                .map_err(|err| Error::Message(format!("bench task failed: {err}")))?,
+        );
+    }
+    let wall = start.elapsed().as_secs_f64();
+    Ok(ModelResult::new(
+        model.to_string(),
+        concurrency.max(1),
+        warm.load,
+        wall,
+        samples,
+    ))
+}
+
+/// Run the benchmark described by `bench`, writing JSON to its output if given.
+pub async fn bench(
+    options: RequestOptions,
+    bench: BenchOptions,
+) -> Result<Vec<ModelResult>, Error> {
+    let models = bench.models();
+    if models.is_empty() {
+        return Err(Error::Message(
+            "provide at least one model to bench".to_string(),
+        ));
+    }
+    let prompts = bench.load_prompts()?;
+    let mut results = vec![];
+    for model in models {
+        eprintln!(
+            "benchmarking {model}: {} requests, {} at a time",
+            prompts.len() * bench.runs,
+            bench.concurrency.max(1)
+        );
+        results.push(
+            bench_model(
+                options.clone(),
+                &model,
+                &prompts,
+                bench.concurrency,
+                bench.runs,
+            )
+            .await?,
+        );
+    }
+    if let Some(output) = bench.output.as_ref() {
+        let json = serde_json::json!({ "models": results });
+        std::fs::write(output, serde_json::to_string_pretty(&json)? + "\n")?;
+    }
+    Ok(results)
+}
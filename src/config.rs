@@ -0,0 +1,319 @@
+//! Configuration files and named profiles.
+//!
+//! yammer reads its configuration from `--config`, `$YAMMER_CONFIG`,
+//! `$XDG_CONFIG_HOME/yammer/config.toml`, or `~/.config/yammer/config.toml`, in that order.  The
+//! top-level keys provide defaults and each `[profiles.<name>]` table bundles settings that
+//! `--profile <name>` layers on top of the defaults:
+//!
+//! ```toml
+//! url = "http://gpu-box:11434"
+//! model = "mistral-nemo"
+//!
+//! [profiles.coder]
+//! model = "qwen2.5-coder"
+//! system = "You are a terse senior engineer."
+//! options = { temperature = 0.2 }
+//! ```
+//!
+//! Values resolve with the precedence flags > environment > profile > defaults.  Whether a flag
+//! was given is decided by [given], not by comparing against its default, so an explicit flag
+//! always wins, even when it repeats the default.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+use super::{ConversationOptions, Error, GenerateOptions, RequestOptions};
+
+////////////////////////////////////////////// Profile /////////////////////////////////////////////
+
+/// A bundle of settings.  Every field is optional so that profiles can be layered.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insecure: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Pull models the server is missing instead of failing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_pull: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// Model options such as temperature, passed through to the server verbatim.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub histfile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ps1: Option<String>,
+    /// Render responses as markdown in the terminal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub markdown: Option<bool>,
+}
+
+impl Profile {
+    /// Layer `top` over self, preferring whatever `top` sets.
+    pub fn overlay(&self, top: &Profile) -> Profile {
+        fn pick<T: Clone>(top: &Option<T>, bottom: &Option<T>) -> Option<T> {
+            top.clone().or_else(|| bottom.clone())
+        }
+        Profile {
+            url: pick(&top.url, &self.url),
+            backend: pick(&top.backend, &self.backend),
+            api_key: pick(&top.api_key, &self.api_key),
+            api_key_file: pick(&top.api_key_file, &self.api_key_file),
+            headers: pick(&top.headers, &self.headers),
+            ca_cert: pick(&top.ca_cert, &self.ca_cert),
+            client_cert: pick(&top.client_cert, &self.client_cert),
+            client_key: pick(&top.client_key, &self.client_key),
+            insecure: pick(&top.insecure, &self.insecure),
+            timeout: pick(&top.timeout, &self.timeout),
+            auto_pull: pick(&top.auto_pull, &self.auto_pull),
+            model: pick(&top.model, &self.model),
+            system: pick(&top.system, &self.system),
+            options: pick(&top.options, &self.options),
+            log: pick(&top.log, &self.log),
+            histfile: pick(&top.histfile, &self.histfile),
+            ps1: pick(&top.ps1, &self.ps1),
+            markdown: pick(&top.markdown, &self.markdown),
+        }
+    }
+
+    /// Fill in every request option that was set by neither flag nor environment.
+    ///
+    /// `flags` holds the flags given on the command line, as returned by [given].
+    pub fn apply_request(
+        &self,
+        options: &mut RequestOptions,
+        flags: &HashSet<String>,
+    ) -> Result<(), Error> {
+        if !env_is_set("OLLAMA_HOST") {
+            fill(&mut options.url, &self.url);
+        }
+        if options.backend.is_none() && !env_is_set("YAMMER_BACKEND") {
+            if let Some(backend) = self.backend.as_ref() {
+                options.backend = Some(backend.parse().map_err(Error::Message)?);
+            }
+        }
+        if options.api_key.is_none()
+            && options.api_key_file.is_none()
+            && !env_is_set("OLLAMA_API_KEY")
+        {
+            fill(&mut options.api_key, &self.api_key);
+            if options.api_key.is_none() {
+                fill(&mut options.api_key_file, &self.api_key_file);
+            }
+        }
+        if !env_is_set("YAMMER_HEADERS") {
+            fill(&mut options.headers, &self.headers);
+        }
+        fill(&mut options.ca_cert, &self.ca_cert);
+        fill(&mut options.client_cert, &self.client_cert);
+        fill(&mut options.client_key, &self.client_key);
+        pick(
+            &mut options.insecure,
+            &self.insecure,
+            flags.contains("insecure"),
+        );
+        fill(&mut options.timeout, &self.timeout);
+        pick(
+            &mut options.auto_pull,
+            &self.auto_pull,
+            flags.contains("auto-pull"),
+        );
+        Ok(())
+    }
+
+    /// Fill in every conversation option that was set by neither flag nor environment.
+    pub fn apply_conversation(&self, options: &mut ConversationOptions, flags: &HashSet<String>) {
+        pick(&mut options.model, &self.model, flags.contains("model"));
+        pick(&mut options.ps1, &self.ps1, flags.contains("ps1"));
+        fill(&mut options.system, &self.system);
+        fill(
+            &mut options.options,
+            &self.options.as_ref().map(|o| o.to_string()),
+        );
+        pick(
+            &mut options.markdown,
+            &self.markdown,
+            flags.contains("markdown"),
+        );
+        if !env_is_set("YAMMER_LOG") {
+            fill(&mut options.log, &self.log);
+        }
+        if !env_is_set("YAMMER_HISTFILE") {
+            fill(&mut options.histfile, &self.histfile);
+        }
+    }
+
+    /// Fill in every generate option that was set by neither flag nor environment.
+    pub fn apply_generate(&self, options: &mut GenerateOptions, flags: &HashSet<String>) {
+        pick(&mut options.model, &self.model, flags.contains("model"));
+        fill(&mut options.system, &self.system);
+        fill(
+            &mut options.options,
+            &self.options.as_ref().map(|o| o.to_string()),
+        );
+        pick(
+            &mut options.markdown,
+            &self.markdown,
+            flags.contains("markdown"),
+        );
+    }
+}
+
+/// The settings that will actually take effect, with secrets redacted, for `yammer config show`.
+pub fn resolved(options: &RequestOptions, conversation: &ConversationOptions) -> Profile {
+    let redact = |s: &Option<String>| s.as_ref().map(|_| super::REDACTED.to_string());
+    Profile {
+        url: Some(options.redacted_url()),
+        backend: Some(options.backend().to_string()),
+        api_key: redact(&options.api_key).or_else(|| {
+            std::env::var("OLLAMA_API_KEY")
+                .ok()
+                .map(|_| super::REDACTED.to_string())
+        }),
+        api_key_file: options.api_key_file.clone(),
+        headers: redact(
+            &options
+                .headers
+                .clone()
+                .or_else(|| std::env::var("YAMMER_HEADERS").ok()),
+        ),
+        ca_cert: options.ca_cert.clone(),
+        client_cert: options.client_cert.clone(),
+        client_key: options.client_key.clone(),
+        insecure: Some(options.insecure),
+        timeout: options.timeout,
+        auto_pull: Some(options.auto_pull),
+        model: Some(conversation.model.clone()),
+        system: conversation.system.clone(),
+        options: conversation
+            .options
+            .as_deref()
+            .and_then(|o| serde_json::from_str(o).ok()),
+        log: conversation
+            .log
+            .clone()
+            .or_else(|| std::env::var("YAMMER_LOG").ok()),
+        histfile: conversation
+            .histfile
+            .clone()
+            .or_else(|| std::env::var("YAMMER_HISTFILE").ok()),
+        ps1: Some(conversation.ps1.clone()),
+        markdown: Some(conversation.markdown),
+    }
+}
+
+/// The names of the flags in `args`, without their leading dashes.
+///
+/// Both `--name value` and `--name=value` are recognized.  Parsing stops at `--`.
+pub fn given(args: &[impl AsRef<str>]) -> HashSet<String> {
+    let mut flags = HashSet::new();
+    for arg in args.iter().map(|arg| arg.as_ref()) {
+        if arg == "--" {
+            break;
+        }
+        if let Some(flag) = arg.strip_prefix("--") {
+            let name = flag.split_once('=').map(|(name, _)| name).unwrap_or(flag);
+            flags.insert(name.to_string());
+        }
+    }
+    flags
+}
+
+fn env_is_set(var: &str) -> bool {
+    std::env::var_os(var).is_some()
+}
+
+fn fill<T: Clone>(field: &mut Option<T>, value: &Option<T>) {
+    if field.is_none() {
+        *field = value.clone();
+    }
+}
+
+fn pick<T: Clone>(field: &mut T, value: &Option<T>, given: bool) {
+    if let (false, Some(value)) = (given, value) {
+        *field = value.clone();
+    }
+}
+
+////////////////////////////////////////////// Config //////////////////////////////////////////////
+
+/// The contents of a configuration file.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Config {
+    /// Settings that apply no matter the profile.
+    #[serde(flatten)]
+    pub defaults: Profile,
+    /// Named profiles, selected with `--profile`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// The path to read the configuration from, given the value of `--config`.
+    pub fn path(config: Option<&str>) -> Option<PathBuf> {
+        if let Some(config) = config {
+            return Some(PathBuf::from(config));
+        }
+        if let Ok(config) = std::env::var("YAMMER_CONFIG") {
+            return Some(PathBuf::from(config));
+        }
+        let base = match std::env::var("XDG_CONFIG_HOME") {
+            Ok(xdg) if !xdg.is_empty() => PathBuf::from(xdg),
+            _ => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+        };
+        Some(base.join("yammer").join("config.toml"))
+    }
+
+    /// Load the configuration for `--config`.
+    ///
+    /// A missing file at the default location is an empty configuration; a missing file that was
+    /// named explicitly is an error.
+    pub fn load(config: Option<&str>) -> Result<Self, Error> {
+        let Some(path) = Self::path(config) else {
+            return Ok(Self::default());
+        };
+        let explicit = config.is_some() || std::env::var_os("YAMMER_CONFIG").is_some();
+        if !explicit && !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        toml::from_str(&content)
+            .map_err(|err| Error::Message(format!("could not parse {}: {err}", path.display())))
+    }
+
+    /// The defaults with the named profile, if any, layered on top.
+    ///
+    /// When `name` is None, the profile named by `$YAMMER_PROFILE` applies.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, Error> {
+        let from_env = std::env::var("YAMMER_PROFILE").ok();
+        let Some(name) = name.or(from_env.as_deref()) else {
+            return Ok(self.defaults.clone());
+        };
+        match self.profiles.get(name) {
+            Some(profile) => Ok(self.defaults.overlay(profile)),
+            None => Err(Error::Message(format!("no such profile: {name}"))),
+        }
+    }
+}
@@ -4,23 +4,27 @@
 //! a user and an assistant. The conversation can be used to generate a `ChatRequest` to work with
 //! the core yammer library.
 
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Write};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
+use base64::Engine;
 use rustyline::error::ReadlineError;
 use rustyline::history::FileHistory;
 use rustyline::{Config, Editor};
 
-use super::{ChatMessage, ChatRequest};
+use super::attach::Attachment;
+use super::export::ExportFormat;
+use super::log::{LogRecord, LogWriter};
+use super::{timestamp_now, ChatMessage, ChatRequest};
 
 //////////////////////////////////////// ConversationOptions ///////////////////////////////////////
 
 #[derive(Clone, Debug, Eq, PartialEq, arrrg_derive::CommandLine)]
 pub struct ConversationOptions {
-    #[arrrg(required, "Model to run.")]
+    #[arrrg(optional, "Model to run.")]
     pub model: String,
     #[arrrg(optional, "System prompt to load in advance.")]
     pub system: Option<String>,
@@ -32,6 +36,18 @@ pub struct ConversationOptions {
     pub ps1: String,
     #[arrrg(optional, "Load chat history from a file previously created by log")]
     pub load: Option<String>,
+    #[arrrg(
+        optional,
+        "A JSON object of model options, e.g., {\"temperature\": 0}."
+    )]
+    pub options: Option<String>,
+    #[arrrg(
+        flag,
+        "Render responses as markdown when stdout is a terminal and NO_COLOR is unset."
+    )]
+    pub markdown: bool,
+    #[arrrg(flag, "Allow /code run to execute shell snippets after confirmation.")]
+    pub allow_run: bool,
 }
 
 impl Default for ConversationOptions {
@@ -43,6 +59,9 @@ impl Default for ConversationOptions {
             histfile: None,
             ps1: "yammer> ".to_string(),
             load: None,
+            options: None,
+            markdown: false,
+            allow_run: false,
         }
     }
 }
@@ -50,34 +69,221 @@ impl Default for ConversationOptions {
 /////////////////////////////////////////// Conversation ///////////////////////////////////////////
 
 /// Conversation captures an exchange of messages between a user and an assistant.
+///
+/// The messages form a tree:  every message follows a parent, so that editing an earlier message
+/// forks a new branch instead of discarding what came after it.  The conversation presents the
+/// branch ending at its head as a flat list of messages.
 #[derive(Clone, Debug, Default)]
 pub struct Conversation {
+    nodes: Vec<Node>,
+    path: Vec<usize>,
     messages: Vec<ChatMessage>,
 }
 
+/// A message in the conversation tree.
+#[derive(Clone, Debug)]
+pub struct Node {
+    /// The index of the message this one follows, or None if it starts the conversation.
+    pub parent: Option<usize>,
+    /// The model the message was sent to or received from, if known.
+    pub model: Option<String>,
+    /// When the message was sent or received, if known.
+    pub at: Option<String>,
+    pub message: ChatMessage,
+}
+
 impl Conversation {
     /// Create a new conversation.
     pub fn new() -> Self {
         Self {
+            nodes: Vec::new(),
+            path: Vec::new(),
             messages: Vec::new(),
         }
     }
 
-    /// Push the ChatMessage onto the conversation.
+    /// Push the ChatMessage onto the current branch of the conversation.
     pub fn push(&mut self, message: ChatMessage) {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            parent: self.head(),
+            model: None,
+            at: Some(timestamp_now()),
+            message: message.clone(),
+        });
+        self.path.push(id);
         self.messages.push(message);
     }
 
-    /// Get the messages in the conversation.
+    /// Get the messages on the current branch of the conversation.
     pub fn messages(&self) -> &[ChatMessage] {
         &self.messages
     }
 
-    /// Truncate the conversation to at most `index` messages.
+    /// Get every message in the conversation, on every branch, indexed by id.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The ids and nodes of the messages on the current branch, in order.
+    pub fn branch(&self) -> impl Iterator<Item = (usize, &Node)> {
+        self.path.iter().map(|id| (*id, &self.nodes[*id]))
+    }
+
+    /// The id of the last message on the current branch.
+    pub fn head(&self) -> Option<usize> {
+        self.path.last().copied()
+    }
+
+    /// Truncate the current branch to at most `index` messages.
+    ///
+    /// The truncated messages remain in the tree and can be checked out again.
     pub fn truncate(&mut self, index: usize) {
+        self.path.truncate(index);
         self.messages.truncate(index);
     }
 
+    /// Make the branch ending at message `id` the current branch.
+    pub fn checkout(&mut self, id: usize) -> Result<(), super::Error> {
+        if id >= self.nodes.len() {
+            return Err(super::Error::Message(format!("no such message: {id}")));
+        }
+        let mut path = vec![id];
+        while let Some(parent) = self.nodes[path[path.len() - 1]].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        self.messages = path
+            .iter()
+            .map(|id| self.nodes[*id].message.clone())
+            .collect();
+        self.path = path;
+        Ok(())
+    }
+
+    /// Fork a new branch that replaces the content of message `index` of the current branch.
+    ///
+    /// The new message becomes the head.  The original and everything after it are kept.
+    pub fn fork(&mut self, index: usize, content: impl Into<String>) -> Result<(), super::Error> {
+        let Some(message) = self.messages.get(index) else {
+            return Err(super::Error::Message(format!(
+                "no message {} on this branch",
+                index + 1
+            )));
+        };
+        let message = ChatMessage {
+            content: content.into(),
+            ..message.clone()
+        };
+        self.truncate(index);
+        self.push(message);
+        Ok(())
+    }
+
+    /// The ids of the last message of every branch, in the order they were created.
+    pub fn branches(&self) -> Vec<usize> {
+        let mut leaf = vec![true; self.nodes.len()];
+        for node in self.nodes.iter() {
+            if let Some(parent) = node.parent {
+                leaf[parent] = false;
+            }
+        }
+        (0..self.nodes.len()).filter(|id| leaf[*id]).collect()
+    }
+
+    /// Replay the messages and checkouts of `log` into this conversation.
+    ///
+    /// Messages from logs that predate the tree continue the current branch.
+    pub fn replay(&mut self, log: &super::log::Log) {
+        // NOTE(rescrv):  Ids are scoped to the session that wrote them, and every session begins
+        // with a header, so map each session's ids onto the tree separately.  A session that
+        // resumes the log numbers every message of the log instead.
+        let base = self.nodes.len();
+        let mut ids = HashMap::new();
+        for record in log.records.iter() {
+            match record {
+                LogRecord::Header { resumes, .. } => {
+                    ids.clear();
+                    if *resumes {
+                        ids.extend((base..self.nodes.len()).map(|node| (node - base, node)));
+                    }
+                }
+                LogRecord::Message {
+                    at,
+                    model,
+                    id,
+                    parent,
+                    message,
+                } => {
+                    let node = self.nodes.len();
+                    let parent = match id {
+                        Some(_) => parent.and_then(|p| ids.get(&p).copied()),
+                        None => self.head(),
+                    };
+                    self.nodes.push(Node {
+                        parent,
+                        model: model.clone(),
+                        at: at.clone(),
+                        message: message.clone(),
+                    });
+                    if let Some(id) = id {
+                        ids.insert(*id, node);
+                    }
+                    let _ = self.checkout(node);
+                }
+                LogRecord::Checkout { id, .. } => {
+                    if let Some(node) = ids.get(id) {
+                        let _ = self.checkout(*node);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Make `content` the system prompt that every branch starts from.
+    pub fn insert_system(&mut self, content: String) {
+        for node in self.nodes.iter_mut() {
+            node.parent = Some(node.parent.map(|p| p + 1).unwrap_or(0));
+        }
+        self.nodes.insert(
+            0,
+            Node {
+                parent: None,
+                model: None,
+                at: Some(timestamp_now()),
+                message: ChatMessage {
+                    role: "system".to_string(),
+                    content,
+                    images: None,
+                    tool_calls: None,
+                },
+            },
+        );
+        let head = self.path.last().map(|id| id + 1).unwrap_or(0);
+        let _ = self.checkout(head);
+    }
+
+    /// Attribute every message created since the first `logged` to `model` and append them to
+    /// the log.
+    fn log_since(
+        &mut self,
+        log: &mut Option<LogWriter>,
+        model: &str,
+        logged: &mut usize,
+    ) -> Result<(), super::Error> {
+        for (id, node) in self.nodes.iter_mut().enumerate().skip(*logged) {
+            if node.model.is_none() {
+                node.model = Some(model.to_string());
+            }
+            if let Some(log) = log.as_mut() {
+                log.append(&LogRecord::message(id, node))?;
+            }
+        }
+        *logged = self.nodes.len();
+        Ok(())
+    }
+
     /// Interpret an assistant response and add it to the conversation.
     pub fn add_assistant_response(&mut self, pieces: Vec<serde_json::Value>) {
         let content = pieces
@@ -106,7 +312,7 @@ impl Conversation {
     }
 
     /// Return an Accumulator for the conversation.
-    pub fn accumulator(&mut self) -> ConversationAccumulator {
+    pub fn accumulator(&mut self) -> ConversationAccumulator<'_> {
         ConversationAccumulator {
             convo: self,
             pieces: Vec::new(),
@@ -122,16 +328,18 @@ impl Conversation {
             tools: None,
             format: None,
             keep_alive: None,
+            options: None,
         }
     }
 
     pub async fn shell(
         mut self,
         global: super::RequestOptions,
-        options: ConversationOptions,
+        mut options: ConversationOptions,
     ) -> Result<(), super::Error> {
         let config = Config::builder()
-            .auto_add_history(true)
+            .auto_add_history(false)
+            .bracketed_paste(true)
             .max_history_size(1_000_000)
             .expect("this should always work")
             .history_ignore_dups(true)
@@ -150,87 +358,180 @@ impl Conversation {
             Editor::with_config(config).expect("this should always work")
         };
         let mut spinner = Spinner::new();
+        let fresh = self.nodes.is_empty();
+        if let Some(load) = options.load.as_ref() {
+            self.replay(&super::load_log(load)?);
+        }
+        let replayed = self.nodes.len();
+        if let Some(system) = options.system.as_ref() {
+            if !self.messages.iter().any(|m| m.role == "system") {
+                self.insert_system(system.clone());
+            }
+        }
+        // NOTE(rescrv):  Appending to the log that was loaded resumes it, so long as the tree
+        // numbers its messages exactly as replaying the log does.
+        let resumes = fresh
+            && replayed == self.nodes.len()
+            && match (options.load.as_ref(), options.log.as_ref()) {
+                (Some(load), Some(log)) => same_file(load, log),
+                _ => false,
+            };
+        let mut model_options = options
+            .options
+            .as_deref()
+            .map(super::generate::parse_options)
+            .transpose()?;
         let mut log = if let Some(log_path) = options.log.as_ref() {
-            let log = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(log_path)?;
-            let mut log = BufWriter::new(log);
-            writeln!(
-                log,
-                "{}",
-                serde_json::to_string(&std::env::args().collect::<Vec<_>>())?
-            )?;
-            let _ = log.flush();
-            Some(BufWriter::new(log))
+            let header = LogRecord::header(
+                &options.model,
+                global.redacted_url(),
+                model_options.clone().unwrap_or_default(),
+                super::redact_args(std::env::args()),
+            )
+            .with_resumes(resumes);
+            Some(LogWriter::open(log_path, header)?)
         } else {
             None
         };
-        if let Some(load) = options.load.as_ref() {
-            for msg in super::load(load)? {
-                self.push(msg);
-            }
+        // NOTE(rescrv):  Record loaded history so that the log stands on its own, unless the log
+        // already holds it.
+        let mut logged = if resumes { self.nodes.len() } else { 0 };
+        self.log_since(&mut log, &options.model, &mut logged)?;
+        if let (Some(log), Some(head)) = (log.as_mut(), self.head()) {
+            log.append(&LogRecord::Checkout {
+                at: timestamp_now(),
+                id: head,
+            })?;
         }
+        let mut attachments: Vec<Attachment> = vec![];
         loop {
-            let line = rl.readline(&options.ps1);
+            let line = read_message(&mut rl, &options.ps1);
             match line {
                 Ok(line) => {
-                    if line.trim().starts_with("/") {
-                        if self.command(line).is_break() {
-                            return Ok(());
+                    if let Some(model) = line.trim().strip_prefix("/model ") {
+                        options.model = model.trim().to_string();
+                        if let Some(log) = log.as_mut() {
+                            log.append(&LogRecord::ModelSwitch {
+                                at: timestamp_now(),
+                                model: options.model.clone(),
+                            })?;
                         }
                         continue;
                     }
-                    if let Some(histfile) = options.histfile.as_ref() {
-                        rl.save_history(&histfile).expect("this should always work");
+                    if let Some(set) = line.trim().strip_prefix("/set ") {
+                        match set_option(&mut model_options, set) {
+                            Ok((name, value)) => {
+                                if let Some(log) = log.as_mut() {
+                                    log.append(&LogRecord::OptionChange {
+                                        at: timestamp_now(),
+                                        name,
+                                        value,
+                                    })?;
+                                }
+                            }
+                            Err(err) => {
+                                eprintln!("could not set option: {}", err);
+                            }
+                        }
+                        continue;
                     }
-                    self.push(ChatMessage {
-                        role: "user".to_string(),
-                        content: line,
-                        images: None,
-                        tool_calls: None,
-                    });
-                    if let Some(log) = log.as_mut() {
-                        writeln!(
-                            log,
-                            "{}",
-                            serde_json::to_string(&self.messages[self.messages.len() - 1])?
-                        )?;
-                        let _ = log.flush();
+                    if let Some(file) = line
+                        .trim()
+                        .strip_prefix("/file")
+                        .filter(|file| file.is_empty() || file.starts_with(char::is_whitespace))
+                    {
+                        if let Err(err) = attach(file, &mut attachments, model_options.as_ref()) {
+                            eprintln!("could not attach: {}", err);
+                        }
+                        continue;
                     }
-                    let cr = self.clone().request(&options.model);
-                    let req = match super::Request::chat(global.clone(), cr) {
-                        Ok(req) => req,
-                        Err(err) => {
-                            eprintln!("could not chat: {}", err);
+                    if let Some(code) = line.trim().strip_prefix("/code") {
+                        if code.is_empty() || code.starts_with(char::is_whitespace) {
+                            if let Err(err) = self.code(code, options.allow_run, &mut rl) {
+                                eprintln!("{}", err);
+                            }
                             continue;
                         }
-                    };
-                    let mut signal = SignalChecker;
-                    let mut printer = super::ChatAccumulator::default();
-                    let mut acc = self.accumulator();
-                    spinner.start();
-                    let resp = super::accumulate(
-                        req,
-                        &mut (&mut signal, &mut spinner, &mut acc, &mut printer),
-                    )
-                    .await;
-                    spinner.inhibit();
-                    if let Err(err) = resp {
-                        eprintln!("could not chat: {:?}", err);
-                    } else {
-                        println!();
                     }
-                    drop(acc);
-                    // FENCE: drop acc above here; log below here.
-                    if let Some(log) = log.as_mut() {
-                        writeln!(
-                            log,
-                            "{}",
-                            serde_json::to_string(&self.messages[self.messages.len() - 1])?
-                        )?;
-                        let _ = log.flush();
+                    if let Some(seed) = line
+                        .trim()
+                        .strip_prefix("/compose")
+                        .filter(|seed| seed.is_empty() || seed.starts_with(char::is_whitespace))
+                    {
+                        // NOTE(rescrv):  A composed message is never a command, even if it starts
+                        // with a slash.
+                        let content = match compose(seed.trim()) {
+                            Ok(content) if content.trim().is_empty() => {
+                                eprintln!("empty message; nothing sent");
+                                continue;
+                            }
+                            Ok(content) => content,
+                            Err(err) => {
+                                eprintln!("could not compose: {}", err);
+                                continue;
+                            }
+                        };
+                        let _ = rl.add_history_entry(&content);
+                        if let Some(histfile) = options.histfile.as_ref() {
+                            rl.save_history(&histfile).expect("this should always work");
+                        }
+                        let content = with_attachments(content, &mut attachments, &mut log)?;
+                        self.push(ChatMessage {
+                            role: "user".to_string(),
+                            content,
+                            images: None,
+                            tool_calls: None,
+                        });
+                        self.log_since(&mut log, &options.model, &mut logged)?;
+                    } else if let Some(edit) = line.trim().strip_prefix("/edit ") {
+                        if let Err(err) = self.edit(edit) {
+                            eprintln!("could not edit: {}", err);
+                            continue;
+                        }
+                        self.log_since(&mut log, &options.model, &mut logged)?;
+                        // NOTE(rescrv):  Only an edited prompt warrants a new response.
+                        if self.messages[self.messages.len() - 1].role != "user" {
+                            continue;
+                        }
+                    } else if line.trim().starts_with("/") {
+                        let head = self.head();
+                        if self.command(line).is_break() {
+                            return Ok(());
+                        }
+                        if let (Some(log), Some(id)) = (log.as_mut(), self.head()) {
+                            if self.head() != head {
+                                log.append(&LogRecord::Checkout {
+                                    at: timestamp_now(),
+                                    id,
+                                })?;
+                            }
+                        }
+                        continue;
+                    } else {
+                        if let Some(histfile) = options.histfile.as_ref() {
+                            rl.save_history(&histfile).expect("this should always work");
+                        }
+                        let content = with_attachments(line, &mut attachments, &mut log)?;
+                        self.push(ChatMessage {
+                            role: "user".to_string(),
+                            content,
+                            images: None,
+                            tool_calls: None,
+                        });
+                        self.log_since(&mut log, &options.model, &mut logged)?;
                     }
+                    // NOTE(rescrv):  A cancelled response returns to the prompt.
+                    let _ = self
+                        .turn(
+                            &global,
+                            &options.model,
+                            &model_options,
+                            options.markdown,
+                            &mut spinner,
+                            &mut log,
+                            &mut logged,
+                        )
+                        .await?;
                 }
                 Err(ReadlineError::Interrupted) => {}
                 Err(ReadlineError::Eof) => {
@@ -243,15 +544,509 @@ impl Conversation {
         }
     }
 
+    /// Re-run every user turn of the current branch of `log` against `model`, writing a new log
+    /// to `out`.
+    ///
+    /// System prompts and user messages are replayed verbatim; the assistant's responses come
+    /// from `model` instead of the log.  Returns the number of turns replayed.
+    pub async fn rerun(
+        global: super::RequestOptions,
+        log: &super::log::Log,
+        model: &str,
+        out: impl AsRef<std::path::Path>,
+    ) -> Result<usize, super::Error> {
+        let out = out.as_ref();
+        if out.exists() {
+            return Err(super::Error::Message(format!(
+                "{} already exists",
+                out.display()
+            )));
+        }
+        let mut source = Conversation::new();
+        source.replay(log);
+        let model_options = match log.header() {
+            Some(LogRecord::Header { options, .. }) if options.is_object() => Some(options.clone()),
+            _ => None,
+        };
+        let header = LogRecord::header(
+            model,
+            global.redacted_url(),
+            model_options.clone().unwrap_or_default(),
+            super::redact_args(std::env::args()),
+        );
+        let mut writer = Some(LogWriter::open(out, header)?);
+        let mut spinner = Spinner::new();
+        let mut convo = Conversation::new();
+        let mut logged = 0;
+        let mut turns = 0;
+        for message in source.messages() {
+            match message.role.as_str() {
+                "assistant" => {}
+                "user" => {
+                    println!(">>> {}", message.content);
+                    convo.push(message.clone());
+                    convo.log_since(&mut writer, model, &mut logged)?;
+                    let flow = convo
+                        .turn(
+                            &global,
+                            model,
+                            &model_options,
+                            false,
+                            &mut spinner,
+                            &mut writer,
+                            &mut logged,
+                        )
+                        .await?;
+                    if flow.is_break() {
+                        eprintln!("interrupted");
+                        break;
+                    }
+                    turns += 1;
+                }
+                _ => {
+                    convo.push(message.clone());
+                    convo.log_since(&mut writer, model, &mut logged)?;
+                }
+            }
+        }
+        Ok(turns)
+    }
+
+    /// Send the conversation to `model` and add the response, logging everything new.
+    ///
+    /// Returns [std::ops::ControlFlow::Break] if the response was cancelled.
+    #[allow(clippy::too_many_arguments)]
+    async fn turn(
+        &mut self,
+        global: &super::RequestOptions,
+        model: &str,
+        model_options: &Option<serde_json::Value>,
+        markdown: bool,
+        spinner: &mut Spinner,
+        log: &mut Option<LogWriter>,
+        logged: &mut usize,
+    ) -> Result<std::ops::ControlFlow<()>, super::Error> {
+        let mut cr = self.clone().request(model);
+        cr.options.clone_from(model_options);
+        let cancel = super::CancellationToken::new();
+        let stderr = std::io::stderr();
+        let tty = stderr.is_terminal();
+        let req = match super::Request::chat(global.clone(), cr) {
+            Ok(req) => req
+                .with_cancellation(cancel.clone())
+                .with_pull_progress(super::pull::PullProgressAccumulator::new(stderr, tty)),
+            Err(err) => {
+                eprintln!("could not chat: {}", err);
+                log_error(log, &err)?;
+                return Ok(std::ops::ControlFlow::Continue(()));
+            }
+        };
+        let canceller = SignalCanceller::new(cancel);
+        let mut printer = super::MarkdownAccumulator::chat(std::io::stdout()).with_style(markdown);
+        let mut stats = StatsAccumulator::default();
+        let mut acc = self.accumulator();
+        spinner.start();
+        let resp = super::accumulate(
+            req,
+            &mut (&mut *spinner, &mut acc, &mut printer, &mut stats),
+        )
+        .await;
+        spinner.inhibit();
+        drop(canceller);
+        drop(acc);
+        // FENCE: drop acc above here; log below here.
+        let flow = match resp {
+            Ok(()) => {
+                println!();
+                std::ops::ControlFlow::Continue(())
+            }
+            Err(super::Error::Cancelled) => {
+                println!();
+                std::ops::ControlFlow::Break(())
+            }
+            Err(err) => {
+                eprintln!("could not chat: {:?}", err);
+                log_error(log, &err)?;
+                std::ops::ControlFlow::Continue(())
+            }
+        };
+        self.log_since(log, model, logged)?;
+        if let Some(log) = log.as_mut() {
+            if let Some(stats) = stats.stats {
+                log.append(&LogRecord::Stats {
+                    at: timestamp_now(),
+                    stats,
+                })?;
+            }
+        }
+        Ok(flow)
+    }
+
     fn command(&mut self, line: String) -> std::ops::ControlFlow<()> {
-        match line.as_str() {
-            "/exit" => std::ops::ControlFlow::Break(()),
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("/exit"), None, None) => std::ops::ControlFlow::Break(()),
+            (Some("/history"), None, None) => {
+                for (idx, msg) in self.messages.iter().enumerate() {
+                    println!("{:>4} {:<9} {}", idx + 1, msg.role, preview(&msg.content));
+                }
+                std::ops::ControlFlow::Continue(())
+            }
+            (Some("/branches"), None, None) => {
+                let head = self.head();
+                for id in self.branches() {
+                    let marker = if Some(id) == head { '*' } else { ' ' };
+                    let mut last_prompt = "";
+                    let mut depth = 0;
+                    let mut cursor = Some(id);
+                    while let Some(node) = cursor {
+                        if last_prompt.is_empty() && self.nodes[node].message.role == "user" {
+                            last_prompt = &self.nodes[node].message.content;
+                        }
+                        depth += 1;
+                        cursor = self.nodes[node].parent;
+                    }
+                    println!(
+                        "{marker} {id:>4} ({depth} messages) {}",
+                        preview(last_prompt)
+                    );
+                }
+                std::ops::ControlFlow::Continue(())
+            }
+            (Some("/export"), Some(format), Some(path)) if words.next().is_none() => {
+                let exported = format
+                    .parse::<ExportFormat>()
+                    .map_err(super::Error::Message)
+                    .and_then(|format| super::export::export(self, format))
+                    .and_then(|exported| Ok(std::fs::write(path, exported)?));
+                if let Err(err) = exported {
+                    eprintln!("could not export: {}", err);
+                }
+                std::ops::ControlFlow::Continue(())
+            }
+            (Some("/checkout"), Some(id), None) => {
+                match id.parse::<usize>() {
+                    Ok(id) => {
+                        if let Err(err) = self.checkout(id) {
+                            eprintln!("could not checkout: {}", err);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("could not checkout {id}: {}", err);
+                    }
+                }
+                std::ops::ControlFlow::Continue(())
+            }
             _ => {
                 eprintln!("unknown command: {}", line);
                 std::ops::ControlFlow::Continue(())
             }
         }
     }
+
+    /// The fenced code blocks of the assistant's messages on the current branch, with the number
+    /// of the message each came from.
+    pub fn code_blocks(&self) -> Vec<(usize, super::CodeBlock)> {
+        let mut blocks = vec![];
+        for (idx, msg) in self.messages.iter().enumerate() {
+            if msg.role == "assistant" {
+                for block in super::code_blocks(&msg.content) {
+                    blocks.push((idx + 1, block));
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Apply `/code`, `/code save <N> <path>`, `/code copy <N>`, or `/code run <N>`.
+    fn code(
+        &self,
+        args: &str,
+        allow_run: bool,
+        rl: &mut Editor<(), FileHistory>,
+    ) -> Result<(), super::Error> {
+        let blocks = self.code_blocks();
+        let words = args.split_whitespace().collect::<Vec<_>>();
+        let block = |n: &str| -> Result<&super::CodeBlock, super::Error> {
+            n.parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|n| blocks.get(n))
+                .map(|(_, block)| block)
+                .ok_or_else(|| super::Error::Message(format!("no code block {n}")))
+        };
+        match words.as_slice() {
+            [] => {
+                for (idx, (msg, block)) in blocks.iter().enumerate() {
+                    let lang = if block.lang.is_empty() {
+                        "-"
+                    } else {
+                        &block.lang
+                    };
+                    println!(
+                        "{:>4} {lang:<10} message {msg}, {} lines: {}",
+                        idx + 1,
+                        block.code.lines().count(),
+                        preview(&block.code)
+                    );
+                }
+            }
+            ["save", n, path] => {
+                let block = block(n)?;
+                std::fs::write(path, &block.code)?;
+                eprintln!("saved {} bytes to {path}", block.code.len());
+            }
+            ["copy", n] => {
+                // NOTE(rescrv):  OSC 52 asks the terminal to set the clipboard, so this works over
+                // ssh, provided the terminal allows it.
+                let block = block(n)?;
+                let encoded = base64::engine::general_purpose::STANDARD.encode(&block.code);
+                let mut stdout = std::io::stdout();
+                write!(stdout, "\x1b]52;c;{encoded}\x07")?;
+                stdout.flush()?;
+                eprintln!("copied {} bytes to the clipboard", block.code.len());
+            }
+            ["run", n] => {
+                let block = block(n)?;
+                if !allow_run {
+                    return Err(super::Error::Message(
+                        "running code is disabled; start chat with --allow-run".to_string(),
+                    ));
+                }
+                let shell = match block.lang.as_str() {
+                    "" | "sh" | "shell" => "sh",
+                    "bash" => "bash",
+                    "zsh" => "zsh",
+                    lang => {
+                        return Err(super::Error::Message(format!(
+                            "only shell snippets can be run, not {lang}"
+                        )));
+                    }
+                };
+                println!("{}", block.code.trim_end());
+                let answer = rl
+                    .readline(&format!("run this with {shell}? [y/N] "))
+                    .unwrap_or_default();
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    return Ok(());
+                }
+                let status = std::process::Command::new(shell)
+                    .arg("-c")
+                    .arg(&block.code)
+                    .status()?;
+                if !status.success() {
+                    eprintln!("{shell} exited with {status}");
+                }
+            }
+            _ => {
+                return Err(super::Error::Message(
+                    "usage: /code [save <N> <path> | copy <N> | run <N>]".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply `/edit <N> <content>`, forking a branch where message N says `content` instead.
+    fn edit(&mut self, edit: &str) -> Result<(), super::Error> {
+        let usage = || super::Error::Message("usage: /edit <N> <new content>".to_string());
+        let (index, content) = edit
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(usage)?;
+        let index = index.parse::<usize>().map_err(|_| usage())?;
+        if index == 0 {
+            return Err(usage());
+        }
+        self.fork(index - 1, content.trim())
+    }
+}
+
+/// Apply `/file`, `/file clear`, or `/file <path|glob>...`, staging files for the next message.
+fn attach(
+    args: &str,
+    attachments: &mut Vec<Attachment>,
+    model_options: Option<&serde_json::Value>,
+) -> Result<(), super::Error> {
+    let patterns = args.split_whitespace().collect::<Vec<_>>();
+    match patterns.as_slice() {
+        [] => {
+            for attachment in attachments.iter() {
+                println!(
+                    "{} ({} bytes, ~{} tokens)",
+                    attachment.path,
+                    attachment.bytes,
+                    attachment.tokens()
+                );
+            }
+        }
+        ["clear"] => {
+            attachments.clear();
+        }
+        patterns => {
+            let mut staged = attachments.clone();
+            for attachment in super::attach::attach(patterns)? {
+                if !staged.iter().any(|a| a.path == attachment.path) {
+                    staged.push(attachment);
+                }
+            }
+            let budget = super::attach::budget(model_options);
+            super::attach::check_budget(&staged, budget)?;
+            for attachment in staged.iter().skip(attachments.len()) {
+                eprintln!(
+                    "attached {} ({} bytes, ~{} tokens)",
+                    attachment.path,
+                    attachment.bytes,
+                    attachment.tokens()
+                );
+            }
+            *attachments = staged;
+        }
+    }
+    Ok(())
+}
+
+/// Prefix `content` with the staged attachments, recording their digests in the log.
+fn with_attachments(
+    content: String,
+    attachments: &mut Vec<Attachment>,
+    log: &mut Option<LogWriter>,
+) -> Result<String, super::Error> {
+    if attachments.is_empty() {
+        return Ok(content);
+    }
+    let files = std::mem::take(attachments);
+    let content = super::attach::render(&files, &content);
+    if let Some(log) = log.as_mut() {
+        log.append(&LogRecord::Attach {
+            at: timestamp_now(),
+            files,
+        })?;
+    }
+    Ok(content)
+}
+
+/// Read one message from the user, adding it to the history.
+///
+/// A line that ends with a backslash continues on the next line.  A line that starts with `"""`
+/// opens a block that runs until a line that ends with `"""`.  Pasted text arrives whole,
+/// newlines included, when the terminal supports bracketed paste.
+fn read_message(rl: &mut Editor<(), FileHistory>, ps1: &str) -> rustyline::Result<String> {
+    const CONTINUATION: &str = "... ";
+    const FENCE: &str = "\"\"\"";
+    let mut message = rl.readline(ps1)?;
+    if let Some(rest) = message.trim_start().strip_prefix(FENCE) {
+        let mut block = rest.to_string();
+        if let Some(oneline) = block.trim_end().strip_suffix(FENCE) {
+            block = oneline.to_string();
+        } else {
+            loop {
+                let line = rl.readline(CONTINUATION)?;
+                if !block.is_empty() {
+                    block.push('\n');
+                }
+                if let Some(last) = line.trim_end().strip_suffix(FENCE) {
+                    block.push_str(last);
+                    break;
+                }
+                block.push_str(&line);
+            }
+        }
+        message = block.trim_matches('\n').to_string();
+    } else {
+        while message.ends_with('\\') {
+            message.pop();
+            message.push('\n');
+            message.push_str(&rl.readline(CONTINUATION)?);
+        }
+    }
+    let _ = rl.add_history_entry(&message);
+    Ok(message)
+}
+
+/// Open `$VISUAL` or `$EDITOR` on a temporary file holding `seed` and return what was saved.
+fn compose(seed: &str) -> Result<String, super::Error> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    // NOTE(rescrv):  The draft may hold secrets, so it is created afresh and readable only by us.
+    let mut draft = tempfile::Builder::new()
+        .prefix("yammer-compose-")
+        .suffix(".md")
+        .tempfile()?;
+    if !seed.is_empty() {
+        writeln!(draft, "{seed}")?;
+        draft.flush()?;
+    }
+    let path = draft.path().to_path_buf();
+    // NOTE(rescrv):  Go through the shell so that editors like "code --wait" work.
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(&path)
+        .status();
+    let content = std::fs::read_to_string(&path);
+    drop(draft);
+    let status = status?;
+    if !status.success() {
+        return Err(super::Error::Message(format!(
+            "{editor} exited with {status}"
+        )));
+    }
+    Ok(content?.trim_end().to_string())
+}
+
+/// The first line of `content`, shortened to fit on one line of a listing.
+fn preview(content: &str) -> String {
+    let line = content.lines().next().unwrap_or("");
+    if line.chars().count() > 60 || content.lines().count() > 1 {
+        format!("{}...", line.chars().take(60).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+/// Apply `/set <name> <json>` to the model options.  A null value unsets the option.
+fn set_option(
+    options: &mut Option<serde_json::Value>,
+    set: &str,
+) -> Result<(String, serde_json::Value), super::Error> {
+    let (name, value) = set
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| super::Error::Message("usage: /set <name> <json value>".to_string()))?;
+    let value: serde_json::Value = serde_json::from_str(value.trim())?;
+    let object = options
+        .get_or_insert_with(|| serde_json::Value::Object(Default::default()))
+        .as_object_mut()
+        .ok_or_else(|| super::Error::Message("options must be a JSON object".to_string()))?;
+    if value.is_null() {
+        object.remove(name);
+    } else {
+        object.insert(name.to_string(), value.clone());
+    }
+    Ok((name.to_string(), value))
+}
+
+fn log_error(log: &mut Option<LogWriter>, err: &super::Error) -> Result<(), super::Error> {
+    if let Some(log) = log.as_mut() {
+        log.append(&LogRecord::Error {
+            at: timestamp_now(),
+            error: err.to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+/// True if `a` and `b` name the same existing file.
+fn same_file(a: &str, b: &str) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
 }
 
 #[derive(Debug)]
@@ -274,19 +1069,66 @@ impl<'a> Drop for ConversationAccumulator<'a> {
     }
 }
 
-/////////////////////////////////////////// SignalChecker //////////////////////////////////////////
+/// Capture the final message of a response, minus its content, for the log.
+#[derive(Debug, Default)]
+struct StatsAccumulator {
+    stats: Option<serde_json::Value>,
+}
+
+impl super::Accumulator for StatsAccumulator {
+    fn accumulate(&mut self, mut message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        if message.get("done").and_then(|d| d.as_bool()) == Some(true) {
+            if let Some(message) = message.as_object_mut() {
+                message.remove("message");
+            }
+            self.stats = Some(message);
+        }
+        std::ops::ControlFlow::Continue(())
+    }
+}
+
+////////////////////////////////////////// SignalCanceller /////////////////////////////////////////
 
+/// SignalCanceller cancels a token when the process receives a signal, e.g., Ctrl-C.
+///
+/// This does not wait for the next message to arrive, so it can interrupt a server that has
+/// stalled.  The watch runs as a task on the current tokio runtime and ends when the
+/// SignalCanceller is dropped.
 #[derive(Debug)]
-pub struct SignalChecker;
+pub struct SignalCanceller {
+    done: super::CancellationToken,
+    background: tokio::task::JoinHandle<()>,
+}
 
-impl super::Accumulator for SignalChecker {
-    fn accumulate(&mut self, _: serde_json::Value) -> std::ops::ControlFlow<()> {
-        if minimal_signals::pending().iter().count() > 0 {
-            minimal_signals::wait(minimal_signals::SignalSet::new().fill());
-            std::ops::ControlFlow::Break(())
-        } else {
-            std::ops::ControlFlow::Continue(())
-        }
+impl SignalCanceller {
+    pub fn new(cancel: super::CancellationToken) -> Self {
+        let done = super::CancellationToken::new();
+        let done_p = done.clone();
+        // NOTE(rescrv):  The binary blocks every signal so the shell can sigwait for them, which
+        // means tokio::signal never sees Ctrl-C.  Poll for a pending signal instead.
+        let background = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_millis(50));
+            loop {
+                tokio::select! {
+                    _ = done_p.cancelled() => return,
+                    _ = interval.tick() => {}
+                }
+                if minimal_signals::pending().iter().count() > 0 {
+                    minimal_signals::wait(minimal_signals::SignalSet::new().fill());
+                    cancel.cancel();
+                    return;
+                }
+            }
+        });
+        Self { done, background }
+    }
+}
+
+impl Drop for SignalCanceller {
+    fn drop(&mut self) {
+        // NOTE(rescrv):  Never join here:  a panic in the watch would become a panic in drop.
+        self.done.cancel();
+        self.background.abort();
     }
 }
 
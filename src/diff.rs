@@ -0,0 +1,300 @@
+//! Structured comparison of two models' configuration.
+//!
+//! [diff] compares the responses of `/api/show` for two models:  their parameters, template,
+//! system prompt, and license; the details the server reports, such as family and quantization;
+//! and the digests of the base model and adapters they are built from.
+
+use super::modelfile::Modelfile;
+use super::{Error, Request, RequestOptions, ShowRequest, VecAccumulator};
+
+/// The `model_info` keys worth comparing, by suffix.  The rest are tokenizer internals.
+const MODEL_INFO: &[&str] = &[
+    "general.architecture",
+    "general.parameter_count",
+    "general.file_type",
+    "context_length",
+    "embedding_length",
+    "block_count",
+    "attention.head_count",
+    "attention.head_count_kv",
+    "rope.freq_base",
+];
+
+////////////////////////////////////////////// Change //////////////////////////////////////////////
+
+/// One difference between two models.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// A parameter differs.  Empty means unset; several values mean a repeated parameter.
+    Parameter {
+        name: String,
+        a: Vec<String>,
+        b: Vec<String>,
+    },
+    /// The template, system prompt, or license differs.
+    Text {
+        field: &'static str,
+        a: Option<String>,
+        b: Option<String>,
+    },
+    /// A detail such as family or quantization level differs.
+    Detail {
+        name: String,
+        a: Option<serde_json::Value>,
+        b: Option<serde_json::Value>,
+    },
+    /// The base model or the adapters are built from different blobs.
+    Digest {
+        what: &'static str,
+        a: Vec<String>,
+        b: Vec<String>,
+    },
+}
+
+///////////////////////////////////////////// ModelDiff ////////////////////////////////////////////
+
+/// The differences between models `a` and `b`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelDiff {
+    pub a: String,
+    pub b: String,
+    pub changes: Vec<Change>,
+}
+
+impl ModelDiff {
+    /// True if no differences were found.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl std::fmt::Display for ModelDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "--- {}", self.a)?;
+        writeln!(f, "+++ {}", self.b)?;
+        if self.changes.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        let list = |values: &[String]| {
+            if values.is_empty() {
+                "(unset)".to_string()
+            } else {
+                values.join(", ")
+            }
+        };
+        let value = |value: &Option<serde_json::Value>| match value {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(value) => value.to_string(),
+            None => "(unset)".to_string(),
+        };
+        for change in self.changes.iter() {
+            match change {
+                Change::Parameter { name, a, b } => {
+                    writeln!(f, "parameter {name}: {} -> {}", list(a), list(b))?;
+                }
+                Change::Detail { name, a, b } => {
+                    writeln!(f, "{name}: {} -> {}", value(a), value(b))?;
+                }
+                Change::Digest { what, a, b } => {
+                    writeln!(f, "{what}: {} -> {}", list(a), list(b))?;
+                }
+                Change::Text { field, a, b } => {
+                    writeln!(f, "{field}:")?;
+                    let a = a.as_deref().unwrap_or_default();
+                    let b = b.as_deref().unwrap_or_default();
+                    for line in lines(a, b) {
+                        writeln!(f, "  {line}")?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/////////////////////////////////////////////// diff ///////////////////////////////////////////////
+
+/// Fetch the `/api/show` response for `model`.
+pub async fn show(options: RequestOptions, model: &str) -> Result<serde_json::Value, Error> {
+    let mut shown = vec![];
+    Request::show(options, ShowRequest::new(model))?
+        .accumulate(&mut VecAccumulator::new(&mut shown))
+        .await?;
+    shown
+        .pop()
+        .ok_or_else(|| Error::Message(format!("no response showing {model}")))
+}
+
+/// Compare the `/api/show` responses `a` and `b` for the models named `a_name` and `b_name`.
+pub fn diff(
+    a_name: &str,
+    a: &serde_json::Value,
+    b_name: &str,
+    b: &serde_json::Value,
+) -> Result<ModelDiff, Error> {
+    let modelfile = |name: &str, show: &serde_json::Value| -> Result<Modelfile, Error> {
+        match show.get("modelfile").and_then(|m| m.as_str()) {
+            Some(modelfile) => modelfile
+                .parse()
+                .map_err(|err| Error::Message(format!("{name}: {err}"))),
+            None => Ok(Modelfile::default()),
+        }
+    };
+    let (a_mf, b_mf) = (modelfile(a_name, a)?, modelfile(b_name, b)?);
+    let mut changes = vec![];
+    // Parameters, in the order they first appear.
+    let mut names: Vec<&str> = vec![];
+    for (name, _) in a_mf.parameters.iter().chain(b_mf.parameters.iter()) {
+        if !names.contains(&name.as_str()) {
+            names.push(name);
+        }
+    }
+    for name in names {
+        let values = |mf: &Modelfile| {
+            mf.parameter(name)
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        let (a, b) = (values(&a_mf), values(&b_mf));
+        if a != b {
+            changes.push(Change::Parameter {
+                name: name.to_string(),
+                a,
+                b,
+            });
+        }
+    }
+    // Text fields prefer the show response and fall back to the Modelfile.
+    let text = |show: &serde_json::Value, field: &str, mf: Option<&String>| {
+        show.get(field)
+            .and_then(|t| t.as_str())
+            .map(String::from)
+            .or_else(|| mf.cloned())
+    };
+    let a_license = (!a_mf.licenses.is_empty()).then(|| a_mf.licenses.join("\n"));
+    let b_license = (!b_mf.licenses.is_empty()).then(|| b_mf.licenses.join("\n"));
+    for (field, a_text, b_text) in [
+        (
+            "template",
+            text(a, "template", a_mf.template.as_ref()),
+            text(b, "template", b_mf.template.as_ref()),
+        ),
+        (
+            "system",
+            text(a, "system", a_mf.system.as_ref()),
+            text(b, "system", b_mf.system.as_ref()),
+        ),
+        (
+            "license",
+            text(a, "license", a_license.as_ref()),
+            text(b, "license", b_license.as_ref()),
+        ),
+    ] {
+        if a_text != b_text {
+            changes.push(Change::Text {
+                field,
+                a: a_text,
+                b: b_text,
+            });
+        }
+    }
+    // Details and the interesting parts of model_info.
+    let mut details = vec![];
+    for show in [a, b] {
+        if let Some(object) = show.get("details").and_then(|d| d.as_object()) {
+            for key in object.keys() {
+                let key = format!("details.{key}");
+                if !details.contains(&key) {
+                    details.push(key);
+                }
+            }
+        }
+        if let Some(object) = show.get("model_info").and_then(|d| d.as_object()) {
+            for key in object.keys() {
+                let key = format!("model_info.{key}");
+                if MODEL_INFO.iter().any(|m| key.ends_with(m)) && !details.contains(&key) {
+                    details.push(key);
+                }
+            }
+        }
+    }
+    for name in details {
+        let pointer = format!("/{}", name.replacen('.', "/", 1));
+        let (a_value, b_value) = (a.pointer(&pointer).cloned(), b.pointer(&pointer).cloned());
+        if a_value != b_value {
+            changes.push(Change::Detail {
+                name,
+                a: a_value,
+                b: b_value,
+            });
+        }
+    }
+    // Digests of the blobs the models are built from.
+    for (what, a_blobs, b_blobs) in [
+        ("base", vec![digest(&a_mf.from)], vec![digest(&b_mf.from)]),
+        (
+            "adapters",
+            a_mf.adapters.iter().map(|a| digest(a)).collect(),
+            b_mf.adapters.iter().map(|a| digest(a)).collect(),
+        ),
+    ] {
+        if a_blobs != b_blobs {
+            changes.push(Change::Digest {
+                what,
+                a: a_blobs,
+                b: b_blobs,
+            });
+        }
+    }
+    Ok(ModelDiff {
+        a: a_name.to_string(),
+        b: b_name.to_string(),
+        changes,
+    })
+}
+
+/// The digest of the blob at `path`, shortened, or the path itself if it names no blob.
+pub(crate) fn digest(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name
+        .strip_prefix("sha256-")
+        .or_else(|| name.strip_prefix("sha256:"))
+    {
+        Some(hex) => format!("sha256:{}", &hex[..hex.len().min(12)]),
+        None => path.to_string(),
+    }
+}
+
+/// A line diff of `a` and `b`, each line prefixed with ' ', '-', or '+'.
+fn lines(a: &str, b: &str) -> Vec<String> {
+    let a = a.lines().collect::<Vec<_>>();
+    let b = b.lines().collect::<Vec<_>>();
+    // NOTE(rescrv):  Longest common subsequence; templates and licenses are short.
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push(format!("  {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if j < b.len() && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            out.push(format!("+ {}", b[j]));
+            j += 1;
+        } else {
+            out.push(format!("- {}", a[i]));
+            i += 1;
+        }
+    }
+    out
+}
@@ -0,0 +1,779 @@
+//! Prompt regression testing.
+//!
+//! A suite is TOML.  It names the models to run, a default system prompt and options, and the
+//! cases to run against every model.  Each case has a prompt and assertions about the response:
+//!
+//! ```toml
+//! models = ["llama3.1:8b", "qwen2.5:7b"]
+//! system = "Answer in one sentence."
+//! options = { temperature = 0 }
+//!
+//! [[case]]
+//! name = "capital"
+//! prompt = "What is the capital of France?"
+//! assert = [
+//!     { type = "contains", value = "Paris" },
+//!     { type = "judge", model = "llama3.1:70b", criteria = "Names Paris and nothing else." },
+//! ]
+//! ```
+//!
+//! Cases run concurrently.  [eval] reports each as it completes, in suite order, and writes one
+//! [CaseResult] per case and model as newline-delimited JSON.  Results are written in suite order
+//! without timings so that two runs can be compared with diff, or with `--baseline` to list what
+//! regressed and what was fixed.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::Arc;
+
+use super::batch::{BatchItem, BatchRequest};
+use super::{EmbedRequest, Error, GenerateRequest, Request, RequestOptions, VecAccumulator};
+
+/// The similarity an embedding assertion requires when it gives no threshold.
+const DEFAULT_THRESHOLD: f64 = 0.8;
+
+/// The compiled size a pattern may grow to.  Patterns come from suites and the schemas in them, so
+/// cap what one can cost rather than trust it.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+//////////////////////////////////////////// EvalOptions ///////////////////////////////////////////
+
+#[derive(Clone, Debug, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct EvalOptions {
+    #[arrrg(optional, "Newline-delimited JSON file to write results to.")]
+    pub output: Option<String>,
+    #[arrrg(optional, "Results of a previous run to report regressions against.")]
+    pub baseline: Option<String>,
+    #[arrrg(optional, "Comma-separated models to run instead of the suite's.")]
+    pub models: Option<String>,
+    #[arrrg(optional, "Maximum number of cases in flight at once.")]
+    pub concurrency: usize,
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        Self {
+            output: None,
+            baseline: None,
+            models: None,
+            concurrency: 4,
+        }
+    }
+}
+
+/////////////////////////////////////////////// Suite //////////////////////////////////////////////
+
+/// A set of cases to run against a set of models.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suite {
+    /// The models to run every case against.
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// The system prompt for cases that give none.
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Model options such as temperature.  A case's options are layered on top.
+    #[serde(default)]
+    pub options: Option<serde_json::Value>,
+    #[serde(default, rename = "case")]
+    pub cases: Vec<Case>,
+}
+
+impl Suite {
+    /// Read and check the suite at `path`.
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let suite: Suite = toml::from_str(&content)
+            .map_err(|err| Error::Message(format!("{}: {err}", path.display())))?;
+        suite
+            .validate()
+            .map_err(|err| Error::Message(format!("{}: {err}", path.display())))?;
+        Ok(suite)
+    }
+
+    /// Check that every case is runnable and every regex compiles.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut names = HashSet::new();
+        for case in self.cases.iter() {
+            if !names.insert(case.name.as_str()) {
+                return Err(Error::Message(format!("case {} appears twice", case.name)));
+            }
+            if case.models.as_ref().unwrap_or(&self.models).is_empty() {
+                return Err(Error::Message(format!("case {} has no models", case.name)));
+            }
+            for assertion in case.assertions.iter() {
+                if let Assertion::Regex { pattern } = assertion {
+                    regex(pattern)
+                        .map_err(|err| Error::Message(format!("case {}: {err}", case.name)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The runs of the suite:  every case against each of its models, in order.
+    ///
+    /// `models` replaces the models of the suite and of every case.
+    pub fn runs(&self, models: Option<&[String]>) -> Vec<(Case, String)> {
+        let mut runs = vec![];
+        for case in self.cases.iter() {
+            let case_models = models.unwrap_or(case.models.as_ref().unwrap_or(&self.models));
+            for model in case_models {
+                let mut case = case.clone();
+                case.system = case.system.or_else(|| self.system.clone());
+                case.options = merge(self.options.as_ref(), case.options.as_ref());
+                runs.push((case, model.clone()));
+            }
+        }
+        runs
+    }
+}
+
+fn merge(
+    base: Option<&serde_json::Value>,
+    over: Option<&serde_json::Value>,
+) -> Option<serde_json::Value> {
+    match (base, over) {
+        (Some(serde_json::Value::Object(base)), Some(serde_json::Value::Object(over))) => {
+            let mut merged = base.clone();
+            merged.extend(over.clone());
+            Some(serde_json::Value::Object(merged))
+        }
+        (base, over) => over.or(base).cloned(),
+    }
+}
+
+/////////////////////////////////////////////// Case ///////////////////////////////////////////////
+
+/// A prompt and the assertions its response must satisfy.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+    pub name: String,
+    pub prompt: String,
+    /// Overrides the suite's system prompt.
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Overrides the suite's models.
+    #[serde(default)]
+    pub models: Option<Vec<String>>,
+    /// Layered on top of the suite's options.
+    #[serde(default)]
+    pub options: Option<serde_json::Value>,
+    /// The format to ask for, e.g., "json".
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default, rename = "assert")]
+    pub assertions: Vec<Assertion>,
+}
+
+///////////////////////////////////////////// Assertion ////////////////////////////////////////////
+
+/// A check on a response.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Assertion {
+    /// The response contains `value`.
+    Contains {
+        value: String,
+        #[serde(default)]
+        ignore_case: bool,
+    },
+    /// The response, less surrounding whitespace, is `value`.
+    Exact { value: String },
+    /// The response matches the regular expression `pattern` somewhere.
+    Regex { pattern: String },
+    /// The response is JSON, possibly in a code fence, that `schema` accepts.
+    JsonSchema { schema: serde_json::Value },
+    /// The embeddings of the response and `reference` by `model` have cosine similarity of at
+    /// least `threshold`.
+    Similar {
+        model: String,
+        reference: String,
+        #[serde(default)]
+        threshold: Option<f64>,
+    },
+    /// The judge `model` says the response meets `criteria`.
+    Judge { model: String, criteria: String },
+}
+
+impl Assertion {
+    /// The name of the assertion's type, as written in a suite.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Contains { .. } => "contains",
+            Self::Exact { .. } => "exact",
+            Self::Regex { .. } => "regex",
+            Self::JsonSchema { .. } => "json-schema",
+            Self::Similar { .. } => "similar",
+            Self::Judge { .. } => "judge",
+        }
+    }
+
+    async fn check(
+        &self,
+        options: &RequestOptions,
+        transport: &super::Transport,
+        prompt: &str,
+        response: &str,
+    ) -> Result<(bool, Option<String>), Error> {
+        match self {
+            Self::Contains { value, ignore_case } => {
+                let found = if *ignore_case {
+                    response.to_lowercase().contains(&value.to_lowercase())
+                } else {
+                    response.contains(value.as_str())
+                };
+                Ok((found, (!found).then(|| format!("missing {value:?}"))))
+            }
+            Self::Exact { value } => {
+                let exact = response.trim() == value.trim();
+                Ok((exact, (!exact).then(|| format!("expected {value:?}"))))
+            }
+            Self::Regex { pattern } => {
+                let matched = regex(pattern)?.is_match(response);
+                Ok((
+                    matched,
+                    (!matched).then(|| format!("no match for /{pattern}/")),
+                ))
+            }
+            Self::JsonSchema { schema } => {
+                let value = match serde_json::from_str(unfence(response)) {
+                    Ok(value) => value,
+                    Err(err) => return Ok((false, Some(format!("not JSON: {err}")))),
+                };
+                let mut errors = vec![];
+                validate(schema, &value, "", &mut errors);
+                Ok((
+                    errors.is_empty(),
+                    (!errors.is_empty()).then(|| errors.join("; ")),
+                ))
+            }
+            Self::Similar {
+                model,
+                reference,
+                threshold,
+            } => {
+                let threshold = threshold.unwrap_or(DEFAULT_THRESHOLD);
+                let embed = EmbedRequest {
+                    model: model.clone(),
+                    ..EmbedRequest::default()
+                };
+                let mut embedded = vec![];
+                Request::embed(options.clone(), embed, vec![reference.as_str(), response])?
+                    .with_transport(transport.clone())
+                    .accumulate(&mut VecAccumulator::new(&mut embedded))
+                    .await?;
+                let vectors = embedded
+                    .iter()
+                    .filter_map(|e| e.get("embeddings").and_then(|e| e.as_array()))
+                    .flatten()
+                    .map(|v| {
+                        v.as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|x| x.as_f64())
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+                let [a, b] = vectors.as_slice() else {
+                    return Err(Error::Message(format!(
+                        "{model} returned {} embeddings for 2 inputs",
+                        vectors.len()
+                    )));
+                };
+                let similarity = cosine(a, b);
+                Ok((
+                    similarity >= threshold,
+                    Some(format!(
+                        "similarity {similarity:.3}, threshold {threshold:.3}"
+                    )),
+                ))
+            }
+            Self::Judge { model, criteria } => {
+                let judge = GenerateRequest {
+                    model: model.clone(),
+                    prompt: format!(
+                        "You are grading a response to a prompt against criteria.\n\n\
+                         Prompt:\n{prompt}\n\nResponse:\n{response}\n\nCriteria:\n{criteria}\n\n\
+                         Reply with PASS or FAIL on the first line and one sentence explaining \
+                         why on the second."
+                    ),
+                    options: Some(serde_json::json!({"temperature": 0})),
+                    ..GenerateRequest::default()
+                };
+                let (verdict, _) = generate(options, transport, judge).await?;
+                let mut lines = verdict.lines().map(str::trim).filter(|l| !l.is_empty());
+                let first = lines.next().unwrap_or_default();
+                let reason = lines.collect::<Vec<_>>().join(" ");
+                let reason = (!reason.is_empty()).then_some(reason);
+                let word = first
+                    .trim_matches(|c: char| !c.is_alphanumeric())
+                    .to_uppercase();
+                if word.starts_with("PASS") {
+                    Ok((true, reason))
+                } else if word.starts_with("FAIL") {
+                    Ok((false, reason))
+                } else {
+                    Err(Error::Message(format!(
+                        "{model} gave no verdict: {first:?}"
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// Compile `pattern` within [REGEX_SIZE_LIMIT].
+fn regex(pattern: &str) -> Result<regex::Regex, Error> {
+    regex::RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|err| Error::Message(format!("invalid regex /{pattern}/: {err}")))
+}
+
+/// The response with any surrounding code fence removed.
+fn unfence(response: &str) -> &str {
+    let trimmed = response.trim();
+    let Some(fenced) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+    let body = fenced.split_once('\n').map(|(_, b)| b).unwrap_or_default();
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+fn cosine(a: &[f64], b: &[f64]) -> f64 {
+    let dot = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f64>();
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 || a.len() != b.len() {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+//////////////////////////////////////////// JSON schema ///////////////////////////////////////////
+
+/// Check `value` against `schema`, pushing one error per violation onto `errors`.
+///
+/// This covers the common keywords of JSON Schema:  type, enum, const, properties, required,
+/// additionalProperties, items, the length, size, and range bounds, pattern, allOf, anyOf, oneOf,
+/// and not.  Other keywords are ignored.
+pub fn validate(
+    schema: &serde_json::Value,
+    value: &serde_json::Value,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    use serde_json::Value;
+    let at = if path.is_empty() { "/" } else { path };
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            errors.push(format!("{at}: not allowed"));
+            return;
+        }
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+    let number = |key: &str| schema.get(key).and_then(|n| n.as_f64());
+    let count = |key: &str| schema.get(key).and_then(|n| n.as_u64());
+    if let Some(types) = schema.get("type") {
+        let types = match types {
+            Value::Array(types) => types.iter().filter_map(|t| t.as_str()).collect(),
+            Value::String(t) => vec![t.as_str()],
+            _ => vec![],
+        };
+        let is = |t: &str| match t {
+            "null" => value.is_null(),
+            "boolean" => value.is_boolean(),
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "integer" => {
+                value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0)
+            }
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            _ => false,
+        };
+        if !types.is_empty() && !types.iter().any(|t| is(t)) {
+            errors.push(format!(
+                "{at}: expected {}, got {}",
+                types.join(" or "),
+                kind(value)
+            ));
+            return;
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            errors.push(format!("{at}: {value} is not one of the allowed values"));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            errors.push(format!("{at}: expected {constant}, got {value}"));
+        }
+    }
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(|r| r.as_str()) {
+                    if !object.contains_key(key) {
+                        errors.push(format!("{at}: missing property {key:?}"));
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(|p| p.as_object());
+            for (key, child) in object.iter() {
+                let child_path = format!("{path}/{key}");
+                match properties.and_then(|p| p.get(key)) {
+                    Some(property) => validate(property, child, &child_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{at}: unexpected property {key:?}"));
+                        }
+                        Some(additional) => validate(additional, child, &child_path, errors),
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(array) => {
+            if let Some(items) = schema.get("items") {
+                for (idx, item) in array.iter().enumerate() {
+                    validate(items, item, &format!("{path}/{idx}"), errors);
+                }
+            }
+            if count("minItems").is_some_and(|min| (array.len() as u64) < min) {
+                errors.push(format!(
+                    "{at}: fewer than {} items",
+                    count("minItems").unwrap()
+                ));
+            }
+            if count("maxItems").is_some_and(|max| (array.len() as u64) > max) {
+                errors.push(format!(
+                    "{at}: more than {} items",
+                    count("maxItems").unwrap()
+                ));
+            }
+        }
+        Value::String(string) => {
+            let len = string.chars().count() as u64;
+            if count("minLength").is_some_and(|min| len < min) {
+                errors.push(format!(
+                    "{at}: shorter than {}",
+                    count("minLength").unwrap()
+                ));
+            }
+            if count("maxLength").is_some_and(|max| len > max) {
+                errors.push(format!("{at}: longer than {}", count("maxLength").unwrap()));
+            }
+            if let Some(pattern) = schema.get("pattern").and_then(|p| p.as_str()) {
+                match regex(pattern) {
+                    Ok(regex) if regex.is_match(string) => {}
+                    Ok(_) => errors.push(format!("{at}: does not match /{pattern}/")),
+                    Err(err) => errors.push(format!("{at}: {err}")),
+                }
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if number("minimum").is_some_and(|min| n < min) {
+                errors.push(format!("{at}: less than {}", schema["minimum"]));
+            }
+            if number("maximum").is_some_and(|max| n > max) {
+                errors.push(format!("{at}: greater than {}", schema["maximum"]));
+            }
+            if number("exclusiveMinimum").is_some_and(|min| n <= min) {
+                errors.push(format!(
+                    "{at}: not greater than {}",
+                    schema["exclusiveMinimum"]
+                ));
+            }
+            if number("exclusiveMaximum").is_some_and(|max| n >= max) {
+                errors.push(format!(
+                    "{at}: not less than {}",
+                    schema["exclusiveMaximum"]
+                ));
+            }
+        }
+        _ => {}
+    }
+    let passes = |sub: &Value| {
+        let mut sub_errors = vec![];
+        validate(sub, value, path, &mut sub_errors);
+        sub_errors.is_empty()
+    };
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+        for sub in all {
+            validate(sub, value, path, errors);
+        }
+    }
+    if let Some(Value::Array(any)) = schema.get("anyOf") {
+        if !any.iter().any(passes) {
+            errors.push(format!("{at}: matches none of anyOf"));
+        }
+    }
+    if let Some(Value::Array(one)) = schema.get("oneOf") {
+        let matched = one.iter().filter(|sub| passes(sub)).count();
+        if matched != 1 {
+            errors.push(format!("{at}: matches {matched} of oneOf, not exactly 1"));
+        }
+    }
+    if let Some(not) = schema.get("not") {
+        if passes(not) {
+            errors.push(format!("{at}: matches the schema under not"));
+        }
+    }
+}
+
+fn kind(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+//////////////////////////////////////////// CaseResult ////////////////////////////////////////////
+
+/// The outcome of one assertion.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct AssertionResult {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub passed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// The outcome of one case against one model.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CaseResult {
+    pub case: String,
+    pub model: String,
+    pub passed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub assertions: Vec<AssertionResult>,
+}
+
+impl CaseResult {
+    fn key(&self) -> (String, String) {
+        (self.case.clone(), self.model.clone())
+    }
+}
+
+/// Read the results of a previous run, keyed by case and model.
+pub fn read_results(
+    path: impl AsRef<std::path::Path>,
+) -> Result<HashMap<(String, String), CaseResult>, Error> {
+    let content = std::fs::read_to_string(path)?;
+    let mut results = HashMap::new();
+    for (idx, line) in content.split_terminator('\n').enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let result: CaseResult = serde_json::from_str(line)
+            .map_err(|err| Error::Message(format!("line {}: {err}", idx + 1)))?;
+        results.insert(result.key(), result);
+    }
+    Ok(results)
+}
+
+//////////////////////////////////////////////// run ///////////////////////////////////////////////
+
+async fn generate(
+    options: &RequestOptions,
+    transport: &super::Transport,
+    request: GenerateRequest,
+) -> Result<(String, Option<serde_json::Value>), Error> {
+    let item = BatchItem {
+        id: None,
+        request: BatchRequest::Generate(request),
+    };
+    let result = super::batch::execute(options.clone(), transport.clone(), 0, item).await;
+    match result.error {
+        Some(err) => Err(Error::Message(err)),
+        None => Ok((result.response.unwrap_or_default(), result.stats)),
+    }
+}
+
+async fn run_case(
+    options: RequestOptions,
+    transport: super::Transport,
+    case: Case,
+    model: String,
+) -> CaseResult {
+    let mut result = CaseResult {
+        case: case.name.clone(),
+        model: model.clone(),
+        passed: false,
+        response: None,
+        error: None,
+        assertions: vec![],
+    };
+    let request = GenerateRequest {
+        model,
+        prompt: case.prompt.clone(),
+        system: case.system.clone(),
+        format: case.format.clone(),
+        options: case.options.clone(),
+        ..GenerateRequest::default()
+    };
+    let response = match generate(&options, &transport, request).await {
+        Ok((response, _)) => response,
+        Err(err) => {
+            result.error = Some(err.to_string());
+            return result;
+        }
+    };
+    for assertion in case.assertions.iter() {
+        let (passed, detail) = assertion
+            .check(&options, &transport, &case.prompt, &response)
+            .await
+            .unwrap_or_else(|err| (false, Some(format!("error: {err}"))));
+        result.assertions.push(AssertionResult {
+            kind: assertion.kind().to_string(),
+            passed,
+            detail,
+        });
+    }
+    result.passed = result.assertions.iter().all(|a| a.passed);
+    result.response = Some(response);
+    result
+}
+
+/// Run `runs` with at most `concurrency` in flight.  `sink` sees the results in order.
+pub async fn run(
+    options: RequestOptions,
+    runs: Vec<(Case, String)>,
+    concurrency: usize,
+    mut sink: impl FnMut(CaseResult) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let transport = options.transport()?;
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(concurrency.max(1)));
+    let mut handles = vec![];
+    for (case, model) in runs {
+        let options = options.clone();
+        let transport = transport.clone();
+        let semaphore = Arc::clone(&semaphore);
+        let key = (case.name.clone(), model.clone());
+        handles.push((
+            key,
+            tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                run_case(options, transport, case, model).await
+            }),
+        ));
+    }
+    for ((case, model), handle) in handles {
+        // NOTE(rescrv):  A case that panics fails like any other rather than ending the run.
+        let result = handle.await.unwrap_or_else(|err| CaseResult {
+            case,
+            model,
+            passed: false,
+            response: None,
+            error: Some(format!("case panicked: {err}")),
+            assertions: vec![],
+        });
+        sink(result)?;
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////// eval ///////////////////////////////////////////////
+
+/// The tally of an eval run.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Report {
+    pub passed: usize,
+    pub failed: usize,
+    /// Runs that passed in the baseline and fail now.
+    pub regressed: Vec<String>,
+    /// Runs that failed in the baseline and pass now.
+    pub fixed: Vec<String>,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} of {} passed", self.passed, self.passed + self.failed)?;
+        if !self.regressed.is_empty() {
+            writeln!(f, "regressed: {}", self.regressed.join(", "))?;
+        }
+        if !self.fixed.is_empty() {
+            writeln!(f, "fixed: {}", self.fixed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the suite at `path`, printing a line per run to `out` and returning the tally.
+pub async fn eval(
+    options: RequestOptions,
+    path: &str,
+    eval: EvalOptions,
+    mut out: impl Write,
+) -> Result<Report, Error> {
+    let suite = Suite::load(path)?;
+    let models = eval.models.as_ref().map(|m| {
+        m.split(',')
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>()
+    });
+    let baseline = eval.baseline.as_ref().map(read_results).transpose()?;
+    let mut output = eval
+        .output
+        .as_ref()
+        .map(|o| std::fs::File::create(o).map(std::io::BufWriter::new))
+        .transpose()?;
+    let mut report = Report::default();
+    let runs = suite.runs(models.as_deref());
+    run(options, runs, eval.concurrency, |result| {
+        let label = format!("{} [{}]", result.case, result.model);
+        if result.passed {
+            report.passed += 1;
+            writeln!(out, "PASS {label}")?;
+        } else {
+            report.failed += 1;
+            writeln!(out, "FAIL {label}")?;
+            if let Some(err) = result.error.as_ref() {
+                writeln!(out, "     error: {err}")?;
+            }
+            for assertion in result.assertions.iter().filter(|a| !a.passed) {
+                match assertion.detail.as_ref() {
+                    Some(detail) => writeln!(out, "     {}: {detail}", assertion.kind)?,
+                    None => writeln!(out, "     {}", assertion.kind)?,
+                }
+            }
+        }
+        if let Some(before) = baseline.as_ref().and_then(|b| b.get(&result.key())) {
+            if before.passed && !result.passed {
+                report.regressed.push(label);
+            } else if !before.passed && result.passed {
+                report.fixed.push(label);
+            }
+        }
+        if let Some(output) = output.as_mut() {
+            writeln!(output, "{}", serde_json::to_string(&result)?)?;
+            output.flush()?;
+        }
+        Ok(())
+    })
+    .await?;
+    Ok(report)
+}
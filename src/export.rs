@@ -0,0 +1,277 @@
+//! Export of conversations to formats meant for people and other tools.
+//!
+//! Markdown and HTML transcripts are meant for reviews and wikis:  each message is headed by its
+//! role, with the model and timestamp when the log recorded them.  Code blocks are preserved,
+//! images are referenced by number and size rather than inlined, and tool calls are rendered as
+//! JSON.  The OpenAI format is a `messages` array suitable for replaying against any
+//! OpenAI-compatible endpoint.
+
+use super::markdown::{segments, Segment};
+use super::{openai, CodeBlock, Conversation, Error, Node};
+
+/////////////////////////////////////////// ExportFormat ///////////////////////////////////////////
+
+/// The formats a conversation can be exported to.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    Html,
+    OpenAiJson,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            "openai-json" => Ok(Self::OpenAiJson),
+            _ => Err(format!(
+                "unknown format {s}; expected markdown, html, or openai-json"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Markdown => write!(f, "markdown"),
+            Self::Html => write!(f, "html"),
+            Self::OpenAiJson => write!(f, "openai-json"),
+        }
+    }
+}
+
+/////////////////////////////////////////// ExportOptions //////////////////////////////////////////
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct ExportOptions {
+    #[arrrg(optional, "The format to export:  markdown, html, or openai-json.")]
+    pub format: ExportFormat,
+    #[arrrg(optional, "The file to write the export to instead of stdout.")]
+    pub output: Option<String>,
+}
+
+////////////////////////////////////////////// export //////////////////////////////////////////////
+
+/// Render the current branch of `convo` in `format`.
+pub fn export(convo: &Conversation, format: ExportFormat) -> Result<String, Error> {
+    match format {
+        ExportFormat::Markdown => Ok(markdown(convo)),
+        ExportFormat::Html => Ok(html(convo)),
+        ExportFormat::OpenAiJson => openai_json(convo),
+    }
+}
+
+/// Render the current branch of `convo` as Markdown.
+pub fn markdown(convo: &Conversation) -> String {
+    let mut out = String::from("# Conversation\n");
+    for (_, node) in convo.branch() {
+        out += &format!("\n## {}\n\n", node.message.role);
+        if let Some(meta) = metadata(node) {
+            out += &format!("_{meta}_\n\n");
+        }
+        if !node.message.content.is_empty() {
+            out += node.message.content.trim_end();
+            out += "\n";
+        }
+        for image in images(node) {
+            out += &format!("\n*[{image}]*\n");
+        }
+        for (name, arguments) in tool_calls(node) {
+            out += &format!("\n**Tool call:** `{name}`\n\n```json\n{arguments}\n```\n");
+        }
+    }
+    out
+}
+
+/// Render the current branch of `convo` as a self-contained HTML page.
+pub fn html(convo: &Conversation) -> String {
+    let mut out = String::from(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Conversation</title>
+<style>
+body { font-family: sans-serif; max-width: 50em; margin: auto; }
+section { border-left: 4px solid #ccc; margin: 1em 0; padding: 0 1em; }
+section.user { border-color: #36c; }
+section.assistant { border-color: #3a3; }
+section.system { border-color: #999; }
+.meta { color: #777; font-size: smaller; }
+pre { background: #f4f4f4; overflow-x: auto; padding: 0.5em; }
+</style>
+</head>
+<body>
+<h1>Conversation</h1>
+"#,
+    );
+    for (_, node) in convo.branch() {
+        let role = escape(&node.message.role);
+        out += &format!("<section class=\"{role}\">\n<h2>{role}</h2>\n");
+        if let Some(meta) = metadata(node) {
+            out += &format!("<p class=\"meta\">{}</p>\n", escape(&meta));
+        }
+        for block in blocks(&node.message.content) {
+            match block {
+                Block::Text(text) => {
+                    out += &format!("<p>{}</p>\n", inline(&text).replace('\n', "<br>\n"));
+                }
+                Block::Code { lang, code } if lang.is_empty() => {
+                    out += &format!("<pre><code>{}</code></pre>\n", escape(&code));
+                }
+                Block::Code { lang, code } => {
+                    out += &format!(
+                        "<pre><code class=\"language-{}\">{}</code></pre>\n",
+                        escape(&lang),
+                        escape(&code)
+                    );
+                }
+            }
+        }
+        for image in images(node) {
+            out += &format!("<p class=\"meta\">[{}]</p>\n", escape(&image));
+        }
+        for (name, arguments) in tool_calls(node) {
+            out += &format!(
+                "<p>Tool call: <code>{}</code></p>\n<pre><code class=\"language-json\">{}</code></pre>\n",
+                escape(&name),
+                escape(&arguments)
+            );
+        }
+        out += "</section>\n";
+    }
+    out += "</body>\n</html>\n";
+    out
+}
+
+/// Render the current branch of `convo` as an OpenAI chat completion request body.
+pub fn openai_json(convo: &Conversation) -> Result<String, Error> {
+    let messages = convo.messages();
+    let mut body = serde_json::json!({ "messages": openai::messages(messages) });
+    if let Some(model) = convo.branch().filter_map(|(_, n)| n.model.clone()).last() {
+        body["model"] = serde_json::Value::String(model);
+    }
+    Ok(serde_json::to_string_pretty(&body)? + "\n")
+}
+
+fn metadata(node: &Node) -> Option<String> {
+    match (node.model.as_ref(), node.at.as_ref()) {
+        (Some(model), Some(at)) => Some(format!("{model} at {at}")),
+        (Some(model), None) => Some(model.clone()),
+        (None, Some(at)) => Some(at.clone()),
+        (None, None) => None,
+    }
+}
+
+fn images(node: &Node) -> Vec<String> {
+    let images = node.message.images.as_deref().unwrap_or_default();
+    images
+        .iter()
+        .enumerate()
+        .map(|(idx, image)| {
+            // NOTE(rescrv):  Images travel base64-encoded; report the decoded size.
+            let bytes = image.len() / 4 * 3;
+            format!(
+                "image {} of {}, {} KiB",
+                idx + 1,
+                images.len(),
+                bytes.div_ceil(1024)
+            )
+        })
+        .collect()
+}
+
+fn tool_calls(node: &Node) -> Vec<(String, String)> {
+    let calls = node.message.tool_calls.as_deref().unwrap_or_default();
+    calls
+        .iter()
+        .map(|call| {
+            let function = call.get("function").unwrap_or(call);
+            let name = function
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or("unknown")
+                .to_string();
+            let arguments = function
+                .get("arguments")
+                .unwrap_or(&serde_json::Value::Null);
+            // NOTE(rescrv):  OpenAI sends arguments as a string of JSON; ollama as an object.
+            let arguments = match arguments {
+                serde_json::Value::String(s) => {
+                    serde_json::from_str(s).unwrap_or_else(|_| serde_json::Value::String(s.clone()))
+                }
+                arguments => arguments.clone(),
+            };
+            let arguments =
+                serde_json::to_string_pretty(&arguments).unwrap_or_else(|_| arguments.to_string());
+            (name, arguments)
+        })
+        .collect()
+}
+
+////////////////////////////////////////////// blocks //////////////////////////////////////////////
+
+enum Block {
+    Text(String),
+    Code { lang: String, code: String },
+}
+
+/// Split Markdown content into paragraphs and fenced code blocks.
+fn blocks(content: &str) -> Vec<Block> {
+    let mut blocks = vec![];
+    for segment in segments(content) {
+        match segment {
+            Segment::Text(text) => {
+                let mut paragraph = String::new();
+                for line in text.lines().chain(std::iter::once("")) {
+                    if !line.trim().is_empty() {
+                        paragraph.push_str(line);
+                        paragraph.push('\n');
+                    } else if !paragraph.is_empty() {
+                        blocks.push(Block::Text(paragraph.trim().to_string()));
+                        paragraph.clear();
+                    }
+                }
+            }
+            Segment::Code(CodeBlock { lang, code }) => blocks.push(Block::Code { lang, code }),
+        }
+    }
+    blocks
+}
+
+/// Escape text for HTML, rendering `inline code` spans as code.
+fn inline(text: &str) -> String {
+    let mut out = String::new();
+    let pieces = text.split('`').collect::<Vec<_>>();
+    for (idx, piece) in pieces.iter().enumerate() {
+        // NOTE(rescrv):  An unmatched backtick is literal.
+        if idx % 2 == 1 && idx + 1 < pieces.len() {
+            out += &format!("<code>{}</code>", escape(piece));
+        } else if idx % 2 == 1 {
+            out += &format!("`{}", escape(piece));
+        } else {
+            out += &escape(piece);
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,309 @@
+//! Command-line construction of a [GenerateRequest] from literal text, files, stdin, and templates,
+//! and sessions that carry the context of one generation into the next.
+
+use std::io::Read;
+
+use base64::Engine;
+
+use super::{Error, GenerateRequest};
+
+////////////////////////////////////////// GenerateOptions /////////////////////////////////////////
+
+/// GenerateOptions describes a [GenerateRequest] in terms convenient for the command line.
+///
+/// The prompt and system prompt are templates.  `{{file:path}}` expands to the contents of path,
+/// `{{env:VAR}}` to the value of VAR, and `{{stdin}}` to everything read from standard input.  A
+/// prompt of `-` is shorthand for `{{stdin}}`.  Files attached with `file` precede the prompt.
+#[derive(Clone, Debug, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct GenerateOptions {
+    #[arrrg(
+        optional,
+        "The name of the ollama model to use from the ollama library."
+    )]
+    pub model: String,
+    #[arrrg(optional, "The prompt template, or - to read the prompt from stdin.")]
+    pub prompt: Option<String>,
+    #[arrrg(optional, "A file containing the prompt template.")]
+    pub prompt_file: Option<String>,
+    #[arrrg(
+        optional,
+        "The suffix to append to the prompt.  This is useful for generating a response that is a continuation of the prompt."
+    )]
+    pub suffix: Option<String>,
+    #[arrrg(optional, "The system prompt template.")]
+    pub system: Option<String>,
+    #[arrrg(optional, "Comma-separated paths of images to supply to the model.")]
+    pub image: Option<String>,
+    #[arrrg(
+        optional,
+        "Comma-separated files, directories, or globs to attach to the prompt."
+    )]
+    pub file: Option<String>,
+    #[arrrg(
+        optional,
+        "The format to return the response in.  If provided, this must be \"json\"."
+    )]
+    pub format: Option<String>,
+    #[arrrg(optional, "The template to use for the response.")]
+    pub template: Option<String>,
+    #[arrrg(flag, "Send the prompt without applying the model's template.")]
+    pub raw: bool,
+    #[arrrg(optional, "Whether to stream the response:  true or false.")]
+    pub stream: Option<bool>,
+    #[arrrg(
+        optional,
+        "How long to hold the model in memory for once the request completes."
+    )]
+    pub keep_alive: Option<String>,
+    #[arrrg(
+        optional,
+        "A file holding the session to continue; created if missing."
+    )]
+    pub session: Option<String>,
+    #[arrrg(
+        optional,
+        "A JSON object of model options, e.g., {\"temperature\": 0}."
+    )]
+    pub options: Option<String>,
+    #[arrrg(
+        flag,
+        "Render the response as markdown when stdout is a terminal and NO_COLOR is unset."
+    )]
+    pub markdown: bool,
+    #[arrrg(flag, "Print only the fenced code blocks of the response.")]
+    pub extract_code: bool,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        Self {
+            model: GenerateRequest::default().model,
+            prompt: None,
+            prompt_file: None,
+            suffix: None,
+            system: None,
+            image: None,
+            file: None,
+            format: None,
+            template: None,
+            raw: false,
+            stream: None,
+            keep_alive: None,
+            session: None,
+            options: None,
+            markdown: false,
+            extract_code: false,
+        }
+    }
+}
+
+impl GenerateOptions {
+    /// Expand the templates, read the images, and construct the GenerateRequest.
+    pub fn request(&self) -> Result<GenerateRequest, Error> {
+        let mut stdin = Stdin::default();
+        let prompt = match (self.prompt.as_deref(), self.prompt_file.as_ref()) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Message(
+                    "provide at most one of --prompt and --prompt-file".to_string(),
+                ));
+            }
+            (Some("-"), None) => stdin.read()?,
+            (Some(prompt), None) => expand(prompt, &mut stdin)?,
+            (None, Some(prompt_file)) => {
+                expand(&std::fs::read_to_string(prompt_file)?, &mut stdin)?
+            }
+            (None, None) => {
+                return Err(Error::Message(
+                    "provide one of --prompt or --prompt-file".to_string(),
+                ));
+            }
+        };
+        let options = self.options.as_deref().map(parse_options).transpose()?;
+        let prompt = match self.file.as_ref() {
+            Some(file) => {
+                let patterns = file
+                    .split(',')
+                    .filter(|p| !p.is_empty())
+                    .collect::<Vec<_>>();
+                let attachments = super::attach::attach(&patterns)?;
+                super::attach::check_budget(&attachments, super::attach::budget(options.as_ref()))?;
+                super::attach::render(&attachments, &prompt)
+            }
+            None => prompt,
+        };
+        let system = match self.system.as_ref() {
+            Some(system) => Some(expand(system, &mut stdin)?),
+            None => None,
+        };
+        let images = match self.image.as_ref() {
+            Some(image) => {
+                let mut images = vec![];
+                for path in image.split(',').filter(|p| !p.is_empty()) {
+                    let bytes = std::fs::read(path)?;
+                    images.push(base64::engine::general_purpose::STANDARD.encode(bytes));
+                }
+                Some(images)
+            }
+            None => None,
+        };
+        Ok(GenerateRequest {
+            model: self.model.clone(),
+            prompt,
+            suffix: self.suffix.clone().unwrap_or_default(),
+            images,
+            format: self.format.clone(),
+            system,
+            template: self.template.clone(),
+            stream: self.stream,
+            raw: if self.raw { Some(true) } else { None },
+            keep_alive: self.keep_alive.clone(),
+            context: None,
+            options,
+        })
+    }
+}
+
+/// Parse a JSON object of model options.
+pub(crate) fn parse_options(options: &str) -> Result<serde_json::Value, Error> {
+    let options: serde_json::Value = serde_json::from_str(options)?;
+    if !options.is_object() {
+        return Err(Error::Message(format!(
+            "options must be a JSON object, not {options}"
+        )));
+    }
+    Ok(options)
+}
+
+////////////////////////////////////////// GenerateSession /////////////////////////////////////////
+
+/// GenerateSession threads the context returned by one generate call into the next, so that
+/// `/api/generate` can hold a stateful exchange.
+#[derive(Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct GenerateSession {
+    /// The model the context belongs to.  Context is meaningless to any other model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The token ids returned by the most recent response.
+    #[serde(default)]
+    pub context: Vec<i64>,
+}
+
+impl GenerateSession {
+    /// Create a new, empty session.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the session at `path`, or start an empty one if there is no such file.
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Save the session to `path`.
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), Error> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Continue the session with `req`.
+    pub fn request(&mut self, mut req: GenerateRequest) -> Result<GenerateRequest, Error> {
+        match self.model.as_ref() {
+            Some(model) if *model != req.model && !self.context.is_empty() => {
+                return Err(Error::Message(format!(
+                    "session belongs to model {model}, not {}",
+                    req.model
+                )));
+            }
+            _ => {}
+        }
+        self.model = Some(req.model.clone());
+        if !self.context.is_empty() {
+            req.context = Some(self.context.clone());
+        }
+        Ok(req)
+    }
+
+    /// Return an Accumulator that records the context of the response into the session.
+    pub fn accumulator(&mut self) -> GenerateSessionAccumulator<'_> {
+        GenerateSessionAccumulator { session: self }
+    }
+}
+
+#[derive(Debug)]
+pub struct GenerateSessionAccumulator<'a> {
+    session: &'a mut GenerateSession,
+}
+
+impl<'a> super::Accumulator for GenerateSessionAccumulator<'a> {
+    fn accumulate(&mut self, message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        // NOTE(rescrv):  Backends that do not speak context send none or an empty one; keep the
+        // context we have rather than forgetting the session.
+        if let Some(context) = message.get("context") {
+            if let Ok(context) = serde_json::from_value::<Vec<i64>>(context.clone()) {
+                if !context.is_empty() {
+                    self.session.context = context;
+                }
+            }
+        }
+        std::ops::ControlFlow::Continue(())
+    }
+}
+
+////////////////////////////////////////////// expand //////////////////////////////////////////////
+
+/// Expand the `{{file:path}}`, `{{env:VAR}}`, and `{{stdin}}` directives in `template`.
+///
+/// Standard input is read at most once, no matter how many times `{{stdin}}` appears.  Text that
+/// is not a recognized directive passes through untouched.
+pub fn expand_template(template: &str) -> Result<String, Error> {
+    expand(template, &mut Stdin::default())
+}
+
+fn expand(template: &str, stdin: &mut Stdin) -> Result<String, Error> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        expanded.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let directive = &rest[start + 2..start + end];
+        if directive == "stdin" {
+            expanded.push_str(&stdin.read()?);
+        } else if let Some(path) = directive.strip_prefix("file:") {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|err| Error::Message(format!("could not read {path}: {err}")))?;
+            expanded.push_str(&contents);
+        } else if let Some(var) = directive.strip_prefix("env:") {
+            let value = std::env::var(var)
+                .map_err(|err| Error::Message(format!("could not read ${var}: {err}")))?;
+            expanded.push_str(&value);
+        } else {
+            expanded.push_str(&rest[start..start + end + 2]);
+        }
+        rest = &rest[start + end + 2..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[derive(Debug, Default)]
+struct Stdin {
+    contents: Option<String>,
+}
+
+impl Stdin {
+    fn read(&mut self) -> Result<String, Error> {
+        if self.contents.is_none() {
+            let mut contents = String::new();
+            std::io::stdin().read_to_string(&mut contents)?;
+            self.contents = Some(contents);
+        }
+        Ok(self.contents.clone().unwrap_or_default())
+    }
+}
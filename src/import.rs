@@ -0,0 +1,298 @@
+//! Import of conversations from other tools.
+//!
+//! Each importer converts another tool's history into a sequence of [ChatMessage] that yammer can
+//! continue.  [import] writes the messages as a chat log suitable for `--load`.
+//!
+//! - `chatgpt`:  the `conversations.json` of a ChatGPT data export.  The branch that was current
+//!   when the export was taken is imported.
+//! - `openai`:  an OpenAI-style `messages` array, bare or inside a request body.
+//! - `ollama`:  the ollama CLI's history file, one prompt per line.  ollama does not keep the
+//!   responses, so only the prompts are imported.
+
+use super::log::{Log, LogRecord};
+use super::{ChatMessage, Error};
+
+/////////////////////////////////////////// ImportFormat ///////////////////////////////////////////
+
+/// The formats a conversation can be imported from.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ImportFormat {
+    #[default]
+    OpenAi,
+    ChatGpt,
+    Ollama,
+}
+
+impl std::str::FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openai" => Ok(Self::OpenAi),
+            "chatgpt" => Ok(Self::ChatGpt),
+            "ollama" => Ok(Self::Ollama),
+            _ => Err(format!(
+                "unknown format {s}; expected chatgpt, openai, or ollama"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OpenAi => write!(f, "openai"),
+            Self::ChatGpt => write!(f, "chatgpt"),
+            Self::Ollama => write!(f, "ollama"),
+        }
+    }
+}
+
+/////////////////////////////////////////// ImportOptions //////////////////////////////////////////
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct ImportOptions {
+    #[arrrg(required, "The format to import:  chatgpt, openai, or ollama.")]
+    pub from: ImportFormat,
+    #[arrrg(required, "The chat log to write.")]
+    pub out: String,
+    #[arrrg(
+        optional,
+        "The id or title of the conversation to import from a ChatGPT export."
+    )]
+    pub conversation: Option<String>,
+}
+
+////////////////////////////////////////////// import //////////////////////////////////////////////
+
+/// Convert `content` in `options.from` format to messages.
+pub fn messages(options: &ImportOptions, content: &str) -> Result<Vec<ChatMessage>, Error> {
+    match options.from {
+        ImportFormat::ChatGpt => chatgpt(content, options.conversation.as_deref()),
+        ImportFormat::OpenAi => openai(content),
+        ImportFormat::Ollama => Ok(ollama(content)),
+    }
+}
+
+/// Import the conversation in `path` and write it as a chat log to `options.out`.
+///
+/// Returns the number of messages imported.
+pub fn import(options: &ImportOptions, path: impl AsRef<std::path::Path>) -> Result<usize, Error> {
+    if std::path::Path::new(&options.out).exists() {
+        return Err(Error::Message(format!("{} already exists", options.out)));
+    }
+    let messages = messages(options, &std::fs::read_to_string(path)?)?;
+    let mut log = Log::default();
+    log.records.push(LogRecord::header(
+        "",
+        "",
+        serde_json::Value::Null,
+        super::redact_args(std::env::args()),
+    ));
+    for (id, message) in messages.iter().enumerate() {
+        log.records.push(LogRecord::Message {
+            at: None,
+            model: None,
+            id: Some(id),
+            parent: id.checked_sub(1),
+            message: message.clone(),
+        });
+    }
+    log.save(&options.out)?;
+    Ok(messages.len())
+}
+
+////////////////////////////////////////////// chatgpt /////////////////////////////////////////////
+
+/// Convert a ChatGPT export to the messages of one conversation.
+///
+/// `selector` is the id or title of the conversation.  It may be omitted when the export holds
+/// exactly one conversation.
+pub fn chatgpt(content: &str, selector: Option<&str>) -> Result<Vec<ChatMessage>, Error> {
+    let export: serde_json::Value = serde_json::from_str(content)?;
+    let conversations = match export {
+        serde_json::Value::Array(conversations) => conversations,
+        conversation => vec![conversation],
+    };
+    let title = |c: &serde_json::Value| {
+        c.get("title")
+            .and_then(|t| t.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    let conversation = match selector {
+        Some(selector) => conversations
+            .iter()
+            .find(|c| {
+                c.get("id").and_then(|id| id.as_str()) == Some(selector)
+                    || c.get("conversation_id").and_then(|id| id.as_str()) == Some(selector)
+                    || title(c) == selector
+            })
+            .ok_or_else(|| Error::Message(format!("no conversation {selector} in export")))?,
+        None if conversations.len() == 1 => &conversations[0],
+        None => {
+            return Err(Error::Message(format!(
+                "export holds {} conversations; choose one with --conversation:  {}",
+                conversations.len(),
+                conversations
+                    .iter()
+                    .map(title)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )));
+        }
+    };
+    let mapping = conversation
+        .get("mapping")
+        .and_then(|m| m.as_object())
+        .ok_or_else(|| Error::Message("conversation has no mapping".to_string()))?;
+    // NOTE(rescrv):  The mapping is a tree; walk up from the node that was current.
+    let mut cursor = conversation
+        .get("current_node")
+        .and_then(|c| c.as_str())
+        .map(String::from);
+    let mut nodes = vec![];
+    while let Some(id) = cursor.take() {
+        let Some(node) = mapping.get(&id) else {
+            break;
+        };
+        nodes.push(node);
+        if nodes.len() > mapping.len() {
+            return Err(Error::Message(
+                "conversation mapping has a cycle".to_string(),
+            ));
+        }
+        cursor = node
+            .get("parent")
+            .and_then(|p| p.as_str())
+            .map(String::from);
+    }
+    nodes.reverse();
+    let mut messages = vec![];
+    for node in nodes {
+        let Some(message) = node.get("message").filter(|m| !m.is_null()) else {
+            continue;
+        };
+        let role = message
+            .pointer("/author/role")
+            .and_then(|r| r.as_str())
+            .unwrap_or("");
+        if !matches!(role, "system" | "user" | "assistant") {
+            continue;
+        }
+        let parts = message
+            .pointer("/content/parts")
+            .and_then(|p| p.as_array())
+            .cloned()
+            .unwrap_or_default();
+        let content = parts
+            .iter()
+            .flat_map(|p| p.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        // NOTE(rescrv):  ChatGPT records hidden system and empty placeholder messages.
+        if content.trim().is_empty() {
+            continue;
+        }
+        messages.push(ChatMessage {
+            role: role.to_string(),
+            content,
+            images: None,
+            tool_calls: None,
+        });
+    }
+    Ok(messages)
+}
+
+////////////////////////////////////////////// openai //////////////////////////////////////////////
+
+/// Convert an OpenAI-style messages array, or a request body holding one, to messages.
+pub fn openai(content: &str) -> Result<Vec<ChatMessage>, Error> {
+    let value: serde_json::Value = serde_json::from_str(content)?;
+    let value = match value.get("messages") {
+        Some(messages) => messages.clone(),
+        None => value,
+    };
+    let serde_json::Value::Array(array) = value else {
+        return Err(Error::Message("expected an array of messages".to_string()));
+    };
+    let mut messages = vec![];
+    for (idx, message) in array.into_iter().enumerate() {
+        let role = message
+            .get("role")
+            .and_then(|r| r.as_str())
+            .ok_or_else(|| Error::Message(format!("message {idx} has no role")))?;
+        let mut content = String::new();
+        let mut images = vec![];
+        match message.get("content") {
+            Some(serde_json::Value::String(s)) => content.push_str(s),
+            Some(serde_json::Value::Array(parts)) => {
+                for part in parts {
+                    if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
+                        if !content.is_empty() {
+                            content.push('\n');
+                        }
+                        content.push_str(text);
+                    }
+                    let url = part.pointer("/image_url/url").and_then(|u| u.as_str());
+                    if let Some((_, data)) = url.and_then(|u| u.split_once(";base64,")) {
+                        images.push(data.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+        let tool_calls = message
+            .get("tool_calls")
+            .and_then(|t| t.as_array())
+            .map(|calls| {
+                calls
+                    .iter()
+                    .map(|call| {
+                        // NOTE(rescrv):  OpenAI sends arguments as a string of JSON; ollama wants an
+                        // object.
+                        let mut call = call.clone();
+                        if let Some(arguments) = call.pointer_mut("/function/arguments") {
+                            if let Some(parsed) = arguments
+                                .as_str()
+                                .and_then(|a| serde_json::from_str::<serde_json::Value>(a).ok())
+                            {
+                                *arguments = parsed;
+                            }
+                        }
+                        call
+                    })
+                    .collect::<Vec<_>>()
+            });
+        messages.push(ChatMessage {
+            role: role.to_string(),
+            content,
+            images: if images.is_empty() {
+                None
+            } else {
+                Some(images)
+            },
+            tool_calls,
+        });
+    }
+    Ok(messages)
+}
+
+////////////////////////////////////////////// ollama //////////////////////////////////////////////
+
+/// Convert the ollama CLI history, one prompt per line, to user messages.
+///
+/// Lines that start with `/` are commands to the CLI, e.g. `/set` or `/bye`, and never reached
+/// the model, so they are skipped.
+pub fn ollama(content: &str) -> Vec<ChatMessage> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('/'))
+        .map(|line| ChatMessage {
+            role: "user".to_string(),
+            content: line.to_string(),
+            images: None,
+            tool_calls: None,
+        })
+        .collect()
+}
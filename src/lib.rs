@@ -2,11 +2,28 @@
 
 use std::io::Write;
 
-use reqwest::Client;
-
+pub mod attach;
+pub mod batch;
+pub mod bench;
+pub mod config;
 mod conversation;
-
-pub use conversation::{Conversation, ConversationOptions, Spinner};
+pub mod diff;
+pub mod eval;
+pub mod export;
+mod generate;
+pub mod import;
+pub mod log;
+mod markdown;
+pub mod modelfile;
+pub mod openai;
+pub mod pull;
+pub mod sync;
+mod transport;
+
+pub use conversation::{Conversation, ConversationOptions, Node, SignalCanceller, Spinner};
+pub use generate::{expand_template, GenerateOptions, GenerateSession};
+pub use markdown::{code_blocks, segments, CodeBlock, MarkdownAccumulator, Segment};
+pub use transport::Transport;
 
 /////////////////////////////////////////////// Error //////////////////////////////////////////////
 
@@ -16,15 +33,40 @@ pub enum Error {
     Message(String),
     Io(std::io::Error),
     Request(reqwest::Error),
+    Hyper(hyper::Error),
     Json(serde_json::Error),
+    Cancelled,
+    Timeout,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Message(msg) => write!(f, "{msg}"),
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Request(err) => write!(f, "{err}"),
+            Self::Hyper(err) => write!(f, "{err}"),
+            Self::Json(err) => write!(f, "{err}"),
+            Self::Cancelled => write!(f, "request cancelled"),
+            Self::Timeout => write!(f, "request timed out"),
+        }
+    }
 }
 
+impl std::error::Error for Error {}
+
 impl From<reqwest::Error> for Error {
     fn from(e: reqwest::Error) -> Self {
         Self::Request(e)
     }
 }
 
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Self {
+        Self::Hyper(e)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(e: serde_json::Error) -> Self {
         Self::Json(e)
@@ -152,6 +194,7 @@ pub struct GenerateRequest {
         optional,
         "The suffix to append to the prompt.  This is useful for generating a response that is a continuation of the prompt."
     )]
+    #[serde(default)]
     pub suffix: String,
 
     /// A list of base64-encoded images to supply to the model.
@@ -190,13 +233,20 @@ pub struct GenerateRequest {
     )]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_alive: Option<String>,
+
+    /// The context returned by a previous response, used to continue from where it left off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<i64>>,
+    /// Model options such as temperature, passed to the server verbatim.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<serde_json::Value>,
 }
 
 impl Default for GenerateRequest {
     fn default() -> Self {
         Self {
             model: "mistral-nemo".to_string(),
-            prompt: "42".to_string(),
+            prompt: "".to_string(),
             suffix: "".to_string(),
             images: None,
             format: None,
@@ -205,6 +255,8 @@ impl Default for GenerateRequest {
             stream: None,
             raw: None,
             keep_alive: None,
+            context: None,
+            options: None,
         }
     }
 }
@@ -224,7 +276,9 @@ pub struct GenerateResponse {
     pub prompt_eval_duration: Option<f64>,
     pub eval_count: Option<f64>,
     pub eval_duration: Option<f64>,
-    pub context: Vec<f64>,
+    /// The token ids encoding the conversation so far.  Only the final response carries them.
+    #[serde(default)]
+    pub context: Vec<i64>,
 }
 
 /////////////////////////////////////////// EmbedRequest ///////////////////////////////////////////
@@ -286,6 +340,23 @@ impl ShowRequest {
     }
 }
 
+/////////////////////////////////////////// DeleteRequest //////////////////////////////////////////
+
+/// A request to delete a model from the server.
+#[derive(Clone, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct DeleteRequest {
+    /// The name of the model to delete.
+    pub model: String,
+}
+
+impl DeleteRequest {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+        }
+    }
+}
+
 //////////////////////////////////////////// ChatMessage ///////////////////////////////////////////
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
@@ -312,6 +383,8 @@ pub struct ChatRequest {
     pub stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_alive: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<serde_json::Value>,
 }
 
 /////////////////////////////////////////// ChatResponse ///////////////////////////////////////////
@@ -323,12 +396,88 @@ pub struct ChatResponse {
     pub done: bool,
 }
 
+////////////////////////////////////////////// Backend /////////////////////////////////////////////
+
+/// The wire protocol spoken by the server.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Backend {
+    /// The native ollama API rooted at `/api` and streaming newline-delimited JSON.
+    #[default]
+    Ollama,
+    /// The OpenAI-compatible API rooted at `/v1` and streaming server-sent events.
+    OpenAi,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ollama" => Ok(Self::Ollama),
+            "openai" => Ok(Self::OpenAi),
+            _ => Err(format!(
+                "unknown backend {s:?}; expected \"ollama\" or \"openai\""
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ollama => write!(f, "ollama"),
+            Self::OpenAi => write!(f, "openai"),
+        }
+    }
+}
+
 ////////////////////////////////////////// RequestOptions //////////////////////////////////////////
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, arrrg_derive::CommandLine)]
+/// The CA bundle, client certificate, client key, and insecure flag of a client.
+type ClientKey = (Option<String>, Option<String>, Option<String>, bool);
+
+/// The HTTP clients built so far, by their TLS options.
+static CLIENTS: std::sync::Mutex<Vec<(ClientKey, reqwest::Client)>> =
+    std::sync::Mutex::new(Vec::new());
+
+/// The Unix domain socket clients built so far, by their socket paths.
+static UNIX_CLIENTS: std::sync::Mutex<Vec<(std::path::PathBuf, transport::UnixClient)>> =
+    std::sync::Mutex::new(Vec::new());
+
+#[derive(Clone, Default, Eq, PartialEq, arrrg_derive::CommandLine)]
 pub struct RequestOptions {
     #[arrrg(optional, "The URL of an ollama server.")]
     pub url: Option<String>,
+    #[arrrg(optional, "The wire protocol to speak:  ollama or openai.")]
+    pub backend: Option<Backend>,
+    #[arrrg(optional, "An API key to send as a bearer token.")]
+    pub api_key: Option<String>,
+    #[arrrg(optional, "A file containing the API key to send as a bearer token.")]
+    pub api_key_file: Option<String>,
+    #[arrrg(
+        optional,
+        "Extra headers to send, as \"Name: value\" pairs separated by ';'."
+    )]
+    pub headers: Option<String>,
+    #[arrrg(
+        optional,
+        "A PEM bundle of CA certificates to trust in addition to the system's."
+    )]
+    pub ca_cert: Option<String>,
+    #[arrrg(optional, "A PEM client certificate to present to the server.")]
+    pub client_cert: Option<String>,
+    #[arrrg(optional, "The PKCS#8 PEM private key for --client-cert.")]
+    pub client_key: Option<String>,
+    #[arrrg(flag, "Accept invalid TLS certificates.  Dangerous.")]
+    pub insecure: bool,
+    #[arrrg(optional, "Seconds to allow each request before giving up.")]
+    pub timeout: Option<u64>,
+    #[arrrg(flag, "Pull a model the server is missing and retry the request.")]
+    pub auto_pull: bool,
+    #[arrrg(optional, "The configuration file to read.")]
+    pub config: Option<String>,
+    #[arrrg(optional, "The profile from the configuration file to apply.")]
+    pub profile: Option<String>,
 }
 
 impl RequestOptions {
@@ -338,127 +487,471 @@ impl RequestOptions {
             .or_else(|| std::env::var("OLLAMA_HOST").ok())
             .unwrap_or_else(|| "http://localhost:11434".to_string())
     }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+            .or_else(|| {
+                std::env::var("YAMMER_BACKEND")
+                    .ok()
+                    .and_then(|b| b.parse().ok())
+            })
+            .unwrap_or_default()
+    }
+
+    /// The API key from --api-key, --api-key-file, or OLLAMA_API_KEY, in that order.
+    pub fn api_key(&self) -> Result<Option<String>, Error> {
+        if let Some(api_key) = self.api_key.as_ref() {
+            return Ok(Some(api_key.clone()));
+        }
+        if let Some(api_key_file) = self.api_key_file.as_ref() {
+            let api_key = std::fs::read_to_string(api_key_file)?;
+            return Ok(Some(api_key.trim().to_string()));
+        }
+        Ok(std::env::var("OLLAMA_API_KEY").ok())
+    }
+
+    /// The headers to send with every request, including the bearer token if there is one.
+    ///
+    /// Extra headers come from --headers or YAMMER_HEADERS.  Credentials embedded in the URL are
+    /// sent as basic auth by the HTTP client and need no header here.
+    pub fn headers(&self) -> Result<Vec<(String, String)>, Error> {
+        let mut headers = vec![];
+        let extra = self
+            .headers
+            .clone()
+            .or_else(|| std::env::var("YAMMER_HEADERS").ok())
+            .unwrap_or_default();
+        for header in extra.split(';') {
+            if header.trim().is_empty() {
+                continue;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                return Err(Error::Message(format!(
+                    "header {:?} is not of the form \"Name: value\"",
+                    header.trim()
+                )));
+            };
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        if let Some(api_key) = self.api_key()? {
+            headers.push((
+                reqwest::header::AUTHORIZATION.to_string(),
+                format!("Bearer {api_key}"),
+            ));
+        }
+        Ok(headers)
+    }
+
+    /// The transport to reach the server.
+    ///
+    /// A URL of the form `unix:///path/to.sock` dials the Unix domain socket at that path.
+    /// Otherwise the TLS options configure an HTTP client.
+    ///
+    /// Clients are built once per socket or combination of TLS options and shared by every
+    /// request, so the certificates are read and the connection pool is kept for the life of the
+    /// process.
+    pub fn transport(&self) -> Result<Transport, Error> {
+        if let Some(path) = self.url().strip_prefix("unix://") {
+            let path = std::path::PathBuf::from(path);
+            let mut clients = UNIX_CLIENTS.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some((_, client)) = clients.iter().find(|(p, _)| *p == path) {
+                return Ok(Transport::Unix(client.clone()));
+            }
+            let client = Transport::unix_client(path.clone());
+            clients.push((path, client.clone()));
+            return Ok(Transport::Unix(client));
+        }
+        let key = (
+            self.ca_cert.clone(),
+            self.client_cert.clone(),
+            self.client_key.clone(),
+            self.insecure,
+        );
+        let mut clients = CLIENTS.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some((_, client)) = clients.iter().find(|(k, _)| *k == key) {
+            return Ok(Transport::Http(client.clone()));
+        }
+        let client = self.client()?;
+        clients.push((key, client.clone()));
+        Ok(Transport::Http(client))
+    }
+
+    fn client(&self) -> Result<reqwest::Client, Error> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(ca_cert) = self.ca_cert.as_ref() {
+            for cert in reqwest::Certificate::from_pem_bundle(&std::fs::read(ca_cert)?)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        match (self.client_cert.as_ref(), self.client_key.as_ref()) {
+            (Some(cert), Some(key)) => {
+                let identity =
+                    reqwest::Identity::from_pkcs8_pem(&std::fs::read(cert)?, &std::fs::read(key)?)?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(Error::Message(
+                    "--client-cert and --client-key must be provided together".to_string(),
+                ));
+            }
+        }
+        if self.insecure {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// The URL with any password replaced so that it is safe to print or log.
+    pub fn redacted_url(&self) -> String {
+        redact_url(&self.url())
+    }
+}
+
+impl std::fmt::Debug for RequestOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestOptions")
+            .field("url", &self.url.as_deref().map(redact_url))
+            .field("backend", &self.backend)
+            .field("api_key", &self.api_key.as_ref().map(|_| REDACTED))
+            .field("api_key_file", &self.api_key_file)
+            .field("headers", &self.headers.as_deref().map(redact_headers))
+            .field("ca_cert", &self.ca_cert)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field("insecure", &self.insecure)
+            .field("timeout", &self.timeout)
+            .field("auto_pull", &self.auto_pull)
+            .field("config", &self.config)
+            .field("profile", &self.profile)
+            .finish()
+    }
+}
+
+///////////////////////////////////////////// redaction ////////////////////////////////////////////
+
+const REDACTED: &str = "<redacted>";
+
+fn redact_url(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let Some((userinfo, host)) = authority.rsplit_once('@') else {
+        return url.to_string();
+    };
+    // NOTE(rescrv):  A bare username is often a token, so it is as secret as a password.
+    let redacted = if userinfo.contains(':') {
+        format!("{REDACTED}:{REDACTED}")
+    } else {
+        REDACTED.to_string()
+    };
+    format!("{scheme}://{redacted}@{host}{}", &rest[authority.len()..])
+}
+
+fn redact_headers(headers: &str) -> String {
+    headers
+        .split(';')
+        .filter(|h| !h.trim().is_empty())
+        .map(|h| match h.split_once(':') {
+            Some((name, _)) => format!("{}: {REDACTED}", name.trim()),
+            None => REDACTED.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Redact the secrets from a command line so that it can be logged.
+///
+/// This understands both `--flag value` and `--flag=value` for the flags of [RequestOptions].
+pub fn redact_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    fn redact(flag: &str, value: &str) -> String {
+        match flag {
+            "url" => redact_url(value),
+            "api-key" => REDACTED.to_string(),
+            "headers" => redact_headers(value),
+            _ => value.to_string(),
+        }
+    }
+    let mut redacted = vec![];
+    let mut pending: Option<String> = None;
+    for arg in args {
+        if let Some(flag) = pending.take() {
+            redacted.push(redact(&flag, &arg));
+            continue;
+        }
+        let flag = arg.trim_start_matches('-');
+        if flag.len() == arg.len() {
+            redacted.push(arg);
+            continue;
+        }
+        let dashes = &arg[..arg.len() - flag.len()];
+        if let Some((flag, value)) = flag.split_once('=') {
+            redacted.push(format!("{dashes}{flag}={}", redact(flag, value)));
+        } else {
+            if matches!(flag, "url" | "api-key" | "headers") {
+                pending = Some(flag.to_string());
+            }
+            redacted.push(arg);
+        }
+    }
+    redacted
 }
 
 ////////////////////////////////////////////// Request /////////////////////////////////////////////
 
-#[derive(Debug)]
+#[derive(Clone)]
 pub struct Request {
     pub url: String,
+    pub backend: Backend,
+    pub headers: Vec<(String, String)>,
+    pub transport: Transport,
     pub api: String,
     pub payload: String,
     pub streaming: bool,
+    pub cancel: Option<CancellationToken>,
+    pub deadline: Option<std::time::Instant>,
+    pub auto_pull: bool,
+    pub pull_progress: Option<PullProgress>,
+}
+
+/// An accumulator shared between a request and its clones that sees the progress of auto-pulls.
+#[derive(Clone, Debug)]
+pub struct PullProgress(std::sync::Arc<std::sync::Mutex<dyn Accumulator + Send>>);
+
+impl std::fmt::Debug for Request {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let headers = self
+            .headers
+            .iter()
+            .map(|(name, _)| (name.as_str(), REDACTED))
+            .collect::<Vec<_>>();
+        f.debug_struct("Request")
+            .field("url", &redact_url(&self.url))
+            .field("backend", &self.backend)
+            .field("headers", &headers)
+            .field("transport", &self.transport)
+            .field("api", &self.api)
+            .field("payload", &self.payload)
+            .field("streaming", &self.streaming)
+            .field("cancel", &self.cancel)
+            .field("deadline", &self.deadline)
+            .field("auto_pull", &self.auto_pull)
+            .field("pull_progress", &self.pull_progress)
+            .finish()
+    }
 }
 
 impl Request {
-    pub fn pull(options: RequestOptions, pull: PullRequest) -> Result<Self, serde_json::Error> {
-        let payload = serde_json::to_string(&pull)?;
+    fn new(
+        options: &RequestOptions,
+        api: &str,
+        payload: String,
+        streaming: bool,
+    ) -> Result<Self, Error> {
         Ok(Self {
             url: options.url(),
-            api: "pull".to_string(),
+            backend: options.backend(),
+            headers: options.headers()?,
+            transport: options.transport()?,
+            api: api.to_string(),
             payload,
-            streaming: true,
+            streaming,
+            cancel: None,
+            deadline: options
+                .timeout
+                .map(|secs| std::time::Instant::now() + std::time::Duration::from_secs(secs)),
+            auto_pull: options.auto_pull,
+            pull_progress: None,
         })
     }
 
-    pub fn create(
-        options: RequestOptions,
-        create: CreateRequest,
-    ) -> Result<Self, serde_json::Error> {
+    pub fn pull(options: RequestOptions, pull: PullRequest) -> Result<Self, Error> {
+        let payload = serde_json::to_string(&pull)?;
+        Self::new(&options, "pull", payload, true)
+    }
+
+    pub fn create(options: RequestOptions, create: CreateRequest) -> Result<Self, Error> {
         let payload = serde_json::to_string(&create)?;
-        Ok(Self {
-            url: options.url(),
-            api: "create".to_string(),
-            payload,
-            streaming: true,
-        })
+        Self::new(&options, "create", payload, true)
     }
 
-    pub fn generate(
-        options: RequestOptions,
-        generate: GenerateRequest,
-    ) -> Result<Self, serde_json::Error> {
-        let payload = serde_json::to_string(&generate)?;
-        Ok(Self {
-            url: options.url(),
-            api: "generate".to_string(),
-            payload,
-            streaming: true,
-        })
+    pub fn generate(options: RequestOptions, generate: GenerateRequest) -> Result<Self, Error> {
+        let payload = match options.backend() {
+            Backend::Ollama => serde_json::to_string(&generate)?,
+            Backend::OpenAi => serde_json::to_string(&openai::generate_request(&generate))?,
+        };
+        Self::new(&options, "generate", payload, true)
     }
 
     pub fn embed(
         options: RequestOptions,
         embed: EmbedRequest,
         inputs: Vec<impl Into<String>>,
-    ) -> Result<Self, serde_json::Error> {
+    ) -> Result<Self, Error> {
         let model = embed.model;
         let input: Vec<String> = inputs.into_iter().map(|s| s.into()).collect();
+        // NOTE(rescrv):  The OpenAI embeddings request has the same shape as ollama's.
         let payload =
             serde_json::to_string(&serde_json::json!({ "model": model, "input": input }))?;
-        Ok(Self {
-            url: options.url(),
-            api: "embed".to_string(),
-            payload,
-            streaming: false,
-        })
+        Self::new(&options, "embed", payload, false)
     }
 
-    pub fn chat(options: RequestOptions, chat: ChatRequest) -> Result<Self, serde_json::Error> {
-        let payload = serde_json::to_string(&chat)?;
-        Ok(Self {
-            url: options.url(),
-            api: "chat".to_string(),
-            payload,
-            streaming: true,
-        })
+    pub fn chat(options: RequestOptions, chat: ChatRequest) -> Result<Self, Error> {
+        let payload = match options.backend() {
+            Backend::Ollama => serde_json::to_string(&chat)?,
+            Backend::OpenAi => serde_json::to_string(&openai::chat_request(&chat))?,
+        };
+        Self::new(&options, "chat", payload, true)
     }
 
-    pub fn tags(options: RequestOptions) -> Result<Self, serde_json::Error> {
+    pub fn tags(options: RequestOptions) -> Result<Self, Error> {
         let payload = serde_json::to_string(&serde_json::json!({}))?;
-        Ok(Self {
-            url: options.url(),
-            api: "tags".to_string(),
-            payload,
-            streaming: false,
-        })
+        Self::new(&options, "tags", payload, false)
     }
 
-    pub fn show(options: RequestOptions, show: ShowRequest) -> Result<Self, serde_json::Error> {
+    pub fn show(options: RequestOptions, show: ShowRequest) -> Result<Self, Error> {
         let payload = serde_json::to_string(&show)?;
-        Ok(Self {
-            url: options.url(),
-            api: "show".to_string(),
-            payload,
-            streaming: false,
-        })
+        Self::new(&options, "show", payload, false)
+    }
+
+    pub fn delete(options: RequestOptions, delete: DeleteRequest) -> Result<Self, Error> {
+        let payload = serde_json::to_string(&delete)?;
+        Self::new(&options, "delete", payload, false)
+    }
+
+    /// Send the request over `transport`, e.g., to share one connection pool across requests.
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Abort the request with [Error::Cancelled] as soon as `cancel` is cancelled.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Abort the request with [Error::Timeout] if it has not completed by `deadline`.
+    pub fn with_deadline(mut self, deadline: std::time::Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Abort the request with [Error::Timeout] if it has not completed within `timeout`.
+    pub fn with_timeout(self, timeout: std::time::Duration) -> Self {
+        self.with_deadline(std::time::Instant::now() + timeout)
+    }
+
+    /// Pull the model named by the request and retry if the server does not have it.
+    ///
+    /// Only chat, generate, and embed requests to the ollama backend auto-pull.  The pull has no
+    /// deadline, and the retry is allowed the request's full timeout again.
+    pub fn with_auto_pull(mut self, auto_pull: bool) -> Self {
+        self.auto_pull = auto_pull;
+        self
+    }
+
+    /// Report the progress of any auto-pull to `progress`.  Without it, models pull silently.
+    pub fn with_pull_progress(mut self, progress: impl Accumulator + Send + 'static) -> Self {
+        self.pull_progress = Some(PullProgress(std::sync::Arc::new(std::sync::Mutex::new(
+            progress,
+        ))));
+        self
     }
 
     pub async fn accumulate(self, acc: &mut impl Accumulator) -> Result<(), Error> {
         accumulate(self, acc).await
     }
 
-    async fn doit(self) -> reqwest::Result<reqwest::Response> {
-        let client = Client::new();
+    pub async fn accumulate_async(self, acc: &mut impl AsyncAccumulator) -> Result<(), Error> {
+        accumulate_async(self, acc).await
+    }
+
+    /// True if the request pulls its model when the server does not have it.
+    fn auto_pulls(&self) -> bool {
+        self.auto_pull
+            && self.backend == Backend::Ollama
+            && matches!(self.api.as_str(), "chat" | "generate" | "embed")
+    }
+
+    /// The request that pulls `model` on behalf of this request.
+    ///
+    /// It is a streaming pull like [Request::pull], cancelled along with this request but without
+    /// its deadline, because a pull may take far longer than any one response.
+    fn auto_pull_request(&self, model: String) -> Result<Self, Error> {
+        Ok(Self {
+            api: "pull".to_string(),
+            payload: serde_json::to_string(&PullRequest::new(model))?,
+            streaming: true,
+            deadline: None,
+            auto_pull: false,
+            pull_progress: None,
+            ..self.clone()
+        })
+    }
+
+    /// The model named by the request's payload, if any.
+    pub fn model(&self) -> Option<String> {
+        let payload: serde_json::Value = serde_json::from_str(&self.payload).ok()?;
+        payload.get("model")?.as_str().map(String::from)
+    }
+
+    /// The endpoint this request will be sent to, or None if the backend does not support it.
+    pub fn endpoint(&self) -> Option<String> {
+        let path = match self.backend {
+            Backend::Ollama => format!("api/{}", self.api),
+            Backend::OpenAi => openai::path(&self.api)?.to_string(),
+        };
+        match self.transport {
+            Transport::Http(_) => Some(format!("{}/{}", self.url, path)),
+            // NOTE(rescrv):  The host is meaningless on a socket, but HTTP/1.1 demands one.
+            Transport::Unix(_) => Some(format!("http://localhost/{}", path)),
+        }
+    }
+
+    async fn doit(self) -> Result<transport::Response, Error> {
+        let Some(endpoint) = self.endpoint() else {
+            return Err(Error::Message(format!(
+                "the {} backend does not support {}",
+                self.backend, self.api
+            )));
+        };
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::ACCEPT,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        for (name, value) in self.headers.iter() {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|err| Error::Message(format!("invalid header name {name:?}: {err}")))?;
+            let mut value = reqwest::header::HeaderValue::from_str(value)
+                .map_err(|err| Error::Message(format!("invalid value for {name}: {err}")))?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
         // NOTE(rescrv): This is intentionally match.  I could embed the Method in the Request, but
         // that wouldn't allow me the flexibility to e.g., easily add a new variant with special
         // headers down the line.  This allows me to add methods to where I need them.
         match self.api.as_str() {
             "pull" | "create" | "generate" | "embed" | "chat" | "show" => {
-                client
-                    .post(&format!("{}/api/{}", self.url, self.api))
-                    .header(reqwest::header::ACCEPT, "application/json")
-                    .header(reqwest::header::CONTENT_LENGTH, "10485760")
-                    .body(self.payload)
-                    .send()
+                self.transport
+                    .send(reqwest::Method::POST, endpoint, headers, Some(self.payload))
                     .await
             }
             "tags" => {
-                client
-                    .get(&format!("{}/api/{}", self.url, self.api))
-                    .header(reqwest::header::ACCEPT, "application/json")
-                    .header(reqwest::header::CONTENT_LENGTH, "10485760")
-                    .send()
+                self.transport
+                    .send(reqwest::Method::GET, endpoint, headers, None)
+                    .await
+            }
+            "delete" => {
+                self.transport
+                    .send(
+                        reqwest::Method::DELETE,
+                        endpoint,
+                        headers,
+                        Some(self.payload),
+                    )
                     .await
             }
             _ => {
@@ -468,6 +961,52 @@ impl Request {
     }
 }
 
+///////////////////////////////////////// CancellationToken ////////////////////////////////////////
+
+/// A handle that aborts every request it is attached to.  Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    inner: std::sync::Arc<CancellationState>,
+}
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: std::sync::atomic::AtomicBool,
+    notify: tokio::sync::Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every request attached to this token.  Cancellation cannot be undone.
+    pub fn cancel(&self) {
+        self.inner
+            .cancelled
+            .store(true, std::sync::atomic::Ordering::Release);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// True if and only if [CancellationToken::cancel] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner
+            .cancelled
+            .load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Wait until the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
 //////////////////////////////////////////// Accumulator ///////////////////////////////////////////
 
 pub trait Accumulator: std::fmt::Debug {
@@ -480,6 +1019,22 @@ impl<T: Accumulator> Accumulator for &mut T {
     }
 }
 
+impl Accumulator for PullProgress {
+    fn accumulate(&mut self, message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        let mut acc = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        acc.accumulate(message)
+    }
+}
+
+impl<A: Accumulator> Accumulator for Option<A> {
+    fn accumulate(&mut self, message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        match self {
+            Some(acc) => acc.accumulate(message),
+            None => std::ops::ControlFlow::Continue(()),
+        }
+    }
+}
+
 macro_rules! impl_accumulator {
     ($($name:ident)+) => {
         #[allow(non_snake_case)]
@@ -626,10 +1181,195 @@ impl Accumulator for ChatAccumulator {
     }
 }
 
+///////////////////////////////////////// AsyncAccumulator /////////////////////////////////////////
+
+/// AsyncAccumulator is an [Accumulator] that may await, e.g., to write to a socket or to send on a
+/// bounded channel, without blocking the runtime.  Use [accumulate_async] to drive one.
+pub trait AsyncAccumulator: std::fmt::Debug {
+    fn accumulate(
+        &mut self,
+        message: serde_json::Value,
+    ) -> impl std::future::Future<Output = std::ops::ControlFlow<()>> + Send;
+}
+
+impl<T: AsyncAccumulator> AsyncAccumulator for &mut T {
+    fn accumulate(
+        &mut self,
+        message: serde_json::Value,
+    ) -> impl std::future::Future<Output = std::ops::ControlFlow<()>> + Send {
+        (**self).accumulate(message)
+    }
+}
+
+impl<A: AsyncAccumulator + Send> AsyncAccumulator for Option<A> {
+    async fn accumulate(&mut self, message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        match self {
+            Some(acc) => acc.accumulate(message).await,
+            None => std::ops::ControlFlow::Continue(()),
+        }
+    }
+}
+
+macro_rules! impl_async_accumulator {
+    ($($name:ident)+) => {
+        #[allow(non_snake_case)]
+        impl<$($name: AsyncAccumulator + Send),+> AsyncAccumulator for ($($name,)+)
+        where ($($name,)+): std::fmt::Debug,
+        {
+            fn accumulate(
+                &mut self,
+                message: serde_json::Value,
+            ) -> impl std::future::Future<Output = std::ops::ControlFlow<()>> + Send {
+                async move {
+                    let ($($name,)+) = self;
+                    $($name.accumulate(message.clone()).await?;)+
+                    std::ops::ControlFlow::Continue(())
+                }
+            }
+        }
+    };
+}
+
+impl_async_accumulator! { A }
+impl_async_accumulator! { A B }
+impl_async_accumulator! { A B C }
+impl_async_accumulator! { A B C D }
+impl_async_accumulator! { A B C D E }
+impl_async_accumulator! { A B C D E F }
+impl_async_accumulator! { A B C D E F G }
+impl_async_accumulator! { A B C D E F G H }
+impl_async_accumulator! { A B C D E F G H I }
+impl_async_accumulator! { A B C D E F G H I J }
+impl_async_accumulator! { A B C D E F G H I J K }
+impl_async_accumulator! { A B C D E F G H I J K L }
+impl_async_accumulator! { A B C D E F G H I J K L M }
+impl_async_accumulator! { A B C D E F G H I J K L M N }
+impl_async_accumulator! { A B C D E F G H I J K L M N O }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R S }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R S T }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R S T U }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R S T U V }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R S T U V W }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R S T U V W X }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R S T U V W X Y }
+impl_async_accumulator! { A B C D E F G H I J K L M N O P Q R S T U V W X Y Z }
+
+/// SyncAccumulator adapts an [Accumulator] to an [AsyncAccumulator].  Each message is accumulated
+/// before the returned future is first polled, so the adapter imposes no `Send` bound.
+#[derive(Debug, Default)]
+pub struct SyncAccumulator<A: Accumulator> {
+    acc: A,
+}
+
+impl<A: Accumulator> SyncAccumulator<A> {
+    pub fn new(acc: A) -> Self {
+        Self { acc }
+    }
+
+    pub fn into_inner(self) -> A {
+        self.acc
+    }
+}
+
+impl<A: Accumulator> AsyncAccumulator for SyncAccumulator<A> {
+    fn accumulate(
+        &mut self,
+        message: serde_json::Value,
+    ) -> impl std::future::Future<Output = std::ops::ControlFlow<()>> + Send {
+        std::future::ready(self.acc.accumulate(message))
+    }
+}
+
+/// ChannelAccumulator sends every message on a bounded channel, waiting while the channel is full.
+/// It stops the stream when the receiver goes away.
+#[derive(Debug)]
+pub struct ChannelAccumulator {
+    output: tokio::sync::mpsc::Sender<serde_json::Value>,
+}
+
+impl ChannelAccumulator {
+    pub fn new(output: tokio::sync::mpsc::Sender<serde_json::Value>) -> Self {
+        Self { output }
+    }
+}
+
+impl AsyncAccumulator for ChannelAccumulator {
+    async fn accumulate(&mut self, message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        match self.output.send(message).await {
+            Ok(()) => std::ops::ControlFlow::Continue(()),
+            Err(_) => std::ops::ControlFlow::Break(()),
+        }
+    }
+}
+
 //////////////////////////////////////////// accumulate ////////////////////////////////////////////
 
-pub async fn accumulate(req: Request, mut acc: impl Accumulator) -> Result<(), Error> {
+pub async fn accumulate(req: Request, acc: impl Accumulator) -> Result<(), Error> {
+    accumulate_async(req, SyncAccumulator::new(acc)).await
+}
+
+/// Send `req` and stream its messages into `acc`, awaiting `acc` as each message arrives.
+pub async fn accumulate_async(req: Request, mut acc: impl AsyncAccumulator) -> Result<(), Error> {
+    let Some(model) = req.model().filter(|_| req.auto_pulls()) else {
+        return bounded(req, acc).await;
+    };
+    let timeout = req
+        .deadline
+        .map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()));
+    let mut retry = req.clone();
+    // NOTE(rescrv):  A missing model fails before any message arrives, so the accumulator sees
+    // only the messages of the retry.
+    match bounded(req, &mut acc).await {
+        Err(Error::Message(err)) if is_missing_model(&err) => {
+            let pull = retry.auto_pull_request(model)?;
+            bounded(pull, SyncAccumulator::new(retry.pull_progress.clone())).await?;
+            retry.deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+            bounded(retry, acc).await
+        }
+        resp => resp,
+    }
+}
+
+/// Send `req`, giving up when it is cancelled or its deadline passes.
+async fn bounded(req: Request, acc: impl AsyncAccumulator) -> Result<(), Error> {
+    let cancel = req.cancel.clone();
+    let deadline = req.deadline;
+    let cancelled = async {
+        match cancel {
+            Some(cancel) => cancel.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+    let expired = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    };
+    // NOTE(rescrv):  Losing the race drops the in-flight request, which closes the connection.
+    tokio::select! {
+        resp = send(req, acc) => resp,
+        _ = cancelled => Err(Error::Cancelled),
+        _ = expired => Err(Error::Timeout),
+    }
+}
+
+/// True if `err` is the server saying that it does not have the requested model.
+fn is_missing_model(err: &str) -> bool {
+    let err = serde_json::from_str::<ErrorResponse>(err)
+        .map(|e| e.error)
+        .unwrap_or_else(|_| err.to_string());
+    err.contains("model") && err.contains("not found")
+}
+
+async fn send(req: Request, mut acc: impl AsyncAccumulator) -> Result<(), Error> {
     let streaming = req.streaming;
+    let backend = req.backend;
+    let api = req.api.clone();
+    let mut responses = openai::Responses::new(&api);
     let mut resp = req.doit().await?;
     if resp.status() != 200 {
         let mut text = String::new();
@@ -638,12 +1378,26 @@ pub async fn accumulate(req: Request, mut acc: impl Accumulator) -> Result<(), E
         }
         return Err(Error::Message(text));
     }
+    if backend == Backend::OpenAi {
+        let is_sse = resp
+            .content_type()
+            .map(|ct| ct.starts_with("text/event-stream"))
+            .unwrap_or(false);
+        if is_sse {
+            return accumulate_sse(responses, resp, acc).await;
+        }
+    }
     if streaming {
         let mut leftovers = String::new();
         while let Some(chunk) = resp.chunk().await? {
-            let chunk = std::str::from_utf8(chunk.as_ref())?.trim();
-            leftovers.push_str(chunk);
-            if !chunk.is_empty() {
+            // NOTE(rescrv):  A chunk may carry several newline-delimited messages or a fraction of
+            // one; every line is glued onto the leftovers until they parse.
+            for line in std::str::from_utf8(chunk.as_ref())?.split_inclusive('\n') {
+                let line = line.trim();
+                leftovers.push_str(line);
+                if line.is_empty() {
+                    continue;
+                }
                 if let Ok(err) = serde_json::from_str::<ErrorResponse>(&leftovers) {
                     return Err(Error::Message(err.error));
                 }
@@ -651,10 +1405,19 @@ pub async fn accumulate(req: Request, mut acc: impl Accumulator) -> Result<(), E
                 else {
                     continue;
                 };
-                if acc.accumulate(message).is_break() {
-                    break;
-                }
                 leftovers.clear();
+                let message = match backend {
+                    Backend::Ollama => message,
+                    Backend::OpenAi => {
+                        let Some(message) = responses.push(message) else {
+                            continue;
+                        };
+                        message
+                    }
+                };
+                if acc.accumulate(message).await.is_break() {
+                    return Ok(());
+                }
             }
         }
     } else {
@@ -665,27 +1428,121 @@ pub async fn accumulate(req: Request, mut acc: impl Accumulator) -> Result<(), E
                 text.push_str(chunk);
             }
         }
+        // NOTE(rescrv):  Some calls, e.g., delete, succeed with an empty body.
+        if text.trim().is_empty() {
+            return Ok(());
+        }
         let message: serde_json::Value = serde_json::from_str(text.trim())?;
-        acc.accumulate(message);
+        let message = match backend {
+            Backend::Ollama => Some(message),
+            Backend::OpenAi => responses.push(message),
+        };
+        if let Some(message) = message {
+            if acc.accumulate(message).await.is_break() {
+                return Ok(());
+            }
+        }
+    }
+    if let Some(last) = responses.finish() {
+        let _ = acc.accumulate(last).await;
+    }
+    Ok(())
+}
+
+async fn accumulate_sse(
+    mut responses: openai::Responses,
+    mut resp: transport::Response,
+    mut acc: impl AsyncAccumulator,
+) -> Result<(), Error> {
+    let mut decoder = openai::SseDecoder::default();
+    let mut finished = false;
+    while !finished {
+        let events = match resp.chunk().await? {
+            Some(chunk) => decoder.push(std::str::from_utf8(chunk.as_ref())?),
+            None => {
+                finished = true;
+                decoder.finish().into_iter().collect()
+            }
+        };
+        for event in events {
+            if event.trim() == "[DONE]" {
+                finished = true;
+                break;
+            }
+            let message: serde_json::Value = serde_json::from_str(&event)?;
+            if let Some(serde_json::Value::Object(err)) = message.get("error") {
+                let err = err
+                    .get("message")
+                    .and_then(|m| m.as_str())
+                    .unwrap_or("unknown error");
+                return Err(Error::Message(err.to_string()));
+            }
+            let Some(message) = responses.push(message) else {
+                continue;
+            };
+            if acc.accumulate(message).await.is_break() {
+                return Ok(());
+            }
+        }
+    }
+    if let Some(last) = responses.finish() {
+        let _ = acc.accumulate(last).await;
     }
     Ok(())
 }
 
+///////////////////////////////////////////// timestamp ////////////////////////////////////////////
+
+/// Format seconds since the UNIX epoch as an RFC 3339 timestamp in UTC.
+pub fn timestamp(secs: u64) -> String {
+    // NOTE(rescrv):  Howard Hinnant's civil_from_days; avoids pulling in a date crate.
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3_600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// The current time as an RFC 3339 timestamp in UTC.
+pub fn timestamp_now() -> String {
+    timestamp(
+        std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    )
+}
+
 /////////////////////////////////////////////// load ///////////////////////////////////////////////
 
+/// Load the messages of the current branch of the chat log at `path`, in either the current or
+/// the legacy format.
+///
+/// Lines that cannot be understood are reported on stderr and skipped.
 pub fn load(path: impl AsRef<std::path::Path>) -> Result<Vec<ChatMessage>, Error> {
+    let mut convo = Conversation::new();
+    convo.replay(&load_log(path)?);
+    Ok(convo.messages().to_vec())
+}
+
+/// Load the chat log at `path`, reporting lines that cannot be understood on stderr.
+pub fn load_log(path: impl AsRef<std::path::Path>) -> Result<log::Log, Error> {
     let path = path.as_ref();
-    let content = std::fs::read_to_string(path)?;
-    let mut msgs = vec![];
-    for line in content.split_terminator('\n') {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
-        let Ok(msg) = serde_json::from_str::<ChatMessage>(line) else {
-            continue;
-        };
-        msgs.push(msg);
+    let log = log::Log::load(path)?;
+    for (line, reason) in log.skipped.iter() {
+        eprintln!("{}:{line}: skipped: {reason}", path.display());
     }
-    Ok(msgs)
+    Ok(log)
 }
@@ -0,0 +1,493 @@
+//! The chat log:  a versioned, self-describing record of a conversation.
+//!
+//! A log is newline-delimited JSON.  The first line is a [LogRecord::Header] naming the format
+//! version, the yammer version, and the settings the conversation started with.  Every later line
+//! is a typed, timestamped record.
+//!
+//! Logs written before the format was versioned start with a bare JSON array of the command line
+//! and continue with one bare [ChatMessage] per line.  [Log::load] reads both formats, and
+//! [Log::save] always writes the current one, which makes `load` followed by `save` a migration.
+
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+
+use super::attach::Attachment;
+use super::{timestamp_now, ChatMessage, Error, Node};
+
+/// The version of the log format written by this version of yammer.
+pub const LOG_VERSION: u64 = 2;
+
+///////////////////////////////////////////// LogRecord ////////////////////////////////////////////
+
+/// One line of a chat log.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogRecord {
+    /// The first line of every log.
+    Header {
+        version: u64,
+        yammer: String,
+        model: String,
+        host: String,
+        #[serde(default)]
+        options: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+        started_at: String,
+        /// True if the session continues the log it is appended to.  Its ids then number the
+        /// messages of the whole log rather than starting over.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        resumes: bool,
+    },
+    /// A message from the user, assistant, or system.
+    ///
+    /// Messages form a tree.  The id numbers the message within its session and the parent names
+    /// the message it follows.  Messages without an id continue the current branch.
+    Message {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        at: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent: Option<usize>,
+        message: ChatMessage,
+    },
+    /// The conversation continues from the message with the given id.
+    Checkout { at: String, id: usize },
+    /// The conversation continues with a different model.
+    ModelSwitch { at: String, model: String },
+    /// A model option changed; a null value means it was unset.
+    OptionChange {
+        at: String,
+        name: String,
+        value: serde_json::Value,
+    },
+    /// The timing and token counts the server reported for a response.
+    Stats {
+        at: String,
+        stats: serde_json::Value,
+    },
+    /// A request failed.
+    Error { at: String, error: String },
+    /// Files attached to the message that follows, by path and digest.
+    Attach { at: String, files: Vec<Attachment> },
+}
+
+impl LogRecord {
+    /// A header for a conversation starting now.
+    pub fn header(
+        model: impl Into<String>,
+        host: impl Into<String>,
+        options: serde_json::Value,
+        args: Vec<String>,
+    ) -> Self {
+        Self::Header {
+            version: LOG_VERSION,
+            yammer: env!("CARGO_PKG_VERSION").to_string(),
+            model: model.into(),
+            host: host.into(),
+            options,
+            args,
+            started_at: timestamp_now(),
+            resumes: false,
+        }
+    }
+
+    /// Mark a header as resuming the log it is appended to.
+    pub fn with_resumes(mut self, resume: bool) -> Self {
+        if let Self::Header { resumes, .. } = &mut self {
+            *resumes = resume;
+        }
+        self
+    }
+
+    /// A record of the message with the given id.
+    pub fn message(id: usize, node: &Node) -> Self {
+        Self::Message {
+            at: node.at.clone().or_else(|| Some(timestamp_now())),
+            model: node.model.clone(),
+            id: Some(id),
+            parent: node.parent,
+            message: node.message.clone(),
+        }
+    }
+}
+
+//////////////////////////////////////////////// Log ///////////////////////////////////////////////
+
+/// A chat log read from disk.
+#[derive(Clone, Debug, Default)]
+pub struct Log {
+    /// The records of the log, including the header if there is one.
+    pub records: Vec<LogRecord>,
+    /// True if the log predates the versioned format.
+    pub legacy: bool,
+    /// The one-based line numbers of lines that could not be understood, and why.
+    pub skipped: Vec<(usize, String)>,
+}
+
+impl Log {
+    /// Read the log at `path`, in either the current or the legacy format.
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path)?;
+        let mut log = Log::default();
+        for (idx, line) in content.split_terminator('\n').enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let value: serde_json::Value = match serde_json::from_str(line) {
+                Ok(value) => value,
+                Err(err) => {
+                    log.skipped.push((idx + 1, err.to_string()));
+                    continue;
+                }
+            };
+            if value.get("type").is_some() {
+                match serde_json::from_value::<LogRecord>(value) {
+                    Ok(record) => log.records.push(record),
+                    Err(err) => log.skipped.push((idx + 1, err.to_string())),
+                }
+            } else if let serde_json::Value::Array(args) = value {
+                log.legacy = true;
+                let args = args
+                    .into_iter()
+                    .flat_map(|a| a.as_str().map(String::from))
+                    .collect::<Vec<_>>();
+                log.records.push(legacy_header(args));
+            } else {
+                match serde_json::from_value::<ChatMessage>(value) {
+                    Ok(message) => {
+                        log.legacy = true;
+                        log.records.push(LogRecord::Message {
+                            at: None,
+                            model: None,
+                            id: None,
+                            parent: None,
+                            message,
+                        });
+                    }
+                    Err(err) => log.skipped.push((idx + 1, err.to_string())),
+                }
+            }
+        }
+        Ok(log)
+    }
+
+    /// Read the log at `path` to be saved in the current format.
+    ///
+    /// The header of a legacy log is brought up to the current version.  Legacy logs do not record
+    /// when they started, so the file's creation time, or failing that its modification time,
+    /// stands in.
+    pub fn migrate(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut log = Self::load(path)?;
+        if !log.legacy {
+            return Ok(log);
+        }
+        if !matches!(log.records.first(), Some(LogRecord::Header { .. })) {
+            log.records.insert(0, legacy_header(vec![]));
+        }
+        let metadata = std::fs::metadata(path)?;
+        let when = metadata
+            .created()
+            .or_else(|_| metadata.modified())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::SystemTime::UNIX_EPOCH).ok())
+            .map(|d| super::timestamp(d.as_secs()));
+        for record in log.records.iter_mut() {
+            if let LogRecord::Header {
+                version,
+                started_at,
+                ..
+            } = record
+            {
+                *version = LOG_VERSION;
+                if started_at.is_empty() {
+                    if let Some(when) = when.as_ref() {
+                        *started_at = when.clone();
+                    }
+                }
+            }
+        }
+        Ok(log)
+    }
+
+    /// Write the log to `path` in the current format, replacing whatever is there.
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), Error> {
+        let mut output = BufWriter::new(std::fs::File::create(path)?);
+        for record in self.records.iter() {
+            writeln!(output, "{}", serde_json::to_string(record)?)?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+
+    /// The header of the log, if it has one.
+    pub fn header(&self) -> Option<&LogRecord> {
+        self.records
+            .iter()
+            .find(|r| matches!(r, LogRecord::Header { .. }))
+    }
+
+    /// The chat messages of the log, on every branch, in the order they were written.
+    pub fn messages(&self) -> impl Iterator<Item = &ChatMessage> {
+        self.records.iter().flat_map(|r| match r {
+            LogRecord::Message { message, .. } => Some(message),
+            _ => None,
+        })
+    }
+
+    /// The model, start time, and number of messages of the log, for `yammer logs list`.
+    pub fn summary(&self) -> (String, String, usize) {
+        let (model, started_at) = match self.header() {
+            Some(LogRecord::Header {
+                model, started_at, ..
+            }) => (model.clone(), started_at.clone()),
+            _ => (String::new(), String::new()),
+        };
+        (model, started_at, self.messages().count())
+    }
+
+    /// The lines of messages that contain `pattern`, as (message number, role, line) triples.
+    ///
+    /// Messages are numbered from one in the order they were written.
+    pub fn grep<'a>(
+        &'a self,
+        pattern: &str,
+        options: &GrepOptions,
+    ) -> Vec<(usize, &'a str, &'a str)> {
+        let pattern = if options.ignore_case {
+            pattern.to_lowercase()
+        } else {
+            pattern.to_string()
+        };
+        let mut matches = vec![];
+        for (idx, message) in self.messages().enumerate() {
+            if options
+                .role
+                .as_ref()
+                .is_some_and(|role| *role != message.role)
+            {
+                continue;
+            }
+            for line in message.content.lines() {
+                let found = if options.ignore_case {
+                    line.to_lowercase().contains(&pattern)
+                } else {
+                    line.contains(&pattern)
+                };
+                if found {
+                    matches.push((idx + 1, message.role.as_str(), line));
+                }
+            }
+        }
+        matches
+    }
+
+    /// Render every record of the log for a person to read.
+    pub fn show(&self) -> String {
+        let mut out = String::new();
+        let mut prev = None;
+        for record in self.records.iter() {
+            match record {
+                LogRecord::Header {
+                    version,
+                    yammer,
+                    model,
+                    host,
+                    options,
+                    started_at,
+                    ..
+                } => {
+                    out += &format!(
+                        "=== {} with {model} on {host} (yammer {yammer}, log version {version})\n",
+                        or_unknown(started_at)
+                    );
+                    if !options.is_null() {
+                        out += &format!("=== options {options}\n");
+                    }
+                    prev = None;
+                }
+                LogRecord::Message {
+                    at,
+                    model,
+                    id,
+                    parent,
+                    message,
+                } => {
+                    out += &format!("\n--- {}", message.role);
+                    if let Some(id) = id {
+                        out += &format!(" #{id}");
+                    }
+                    if parent.is_some() && *parent != prev {
+                        out += &format!(" (following #{})", parent.unwrap_or_default());
+                    }
+                    if let Some(model) = model {
+                        out += &format!(" {model}");
+                    }
+                    if let Some(at) = at {
+                        out += &format!(" at {at}");
+                    }
+                    out += "\n";
+                    out += message.content.trim_end();
+                    out += "\n";
+                    if let Some(images) = message.images.as_ref() {
+                        out += &format!("[{} images]\n", images.len());
+                    }
+                    if let Some(tool_calls) = message.tool_calls.as_ref() {
+                        for call in tool_calls {
+                            out += &format!("[tool call {call}]\n");
+                        }
+                    }
+                    prev = *id;
+                }
+                LogRecord::Checkout { at, id } => {
+                    out += &format!("\n=== {at} checkout #{id}\n");
+                    prev = Some(*id);
+                }
+                LogRecord::ModelSwitch { at, model } => {
+                    out += &format!("\n=== {at} model {model}\n");
+                }
+                LogRecord::OptionChange { at, name, value } => {
+                    out += &format!("\n=== {at} set {name} {value}\n");
+                }
+                LogRecord::Stats { stats, .. } => {
+                    let count = |key: &str| stats.get(key).and_then(|c| c.as_u64());
+                    if let (Some(tokens), Some(nanos)) =
+                        (count("eval_count"), count("eval_duration"))
+                    {
+                        out += &format!(
+                            "=== {tokens} tokens in {:.2}s\n",
+                            nanos as f64 / 1_000_000_000.0
+                        );
+                    }
+                }
+                LogRecord::Error { at, error } => {
+                    out += &format!("\n=== {at} error: {error}\n");
+                }
+                LogRecord::Attach { at, files } => {
+                    out += &format!("\n=== {at} attached\n");
+                    for file in files {
+                        out += &format!(
+                            "=== {} ({} bytes, sha256 {})\n",
+                            file.path, file.bytes, file.sha256
+                        );
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn or_unknown(s: &str) -> &str {
+    if s.is_empty() {
+        "unknown"
+    } else {
+        s
+    }
+}
+
+/// Reconstruct what we can of a header from the command line a legacy log recorded.
+fn legacy_header(args: Vec<String>) -> LogRecord {
+    let flag = |name: &str| {
+        args.iter()
+            .position(|a| a.trim_start_matches('-') == name && a.starts_with('-'))
+            .and_then(|idx| args.get(idx + 1))
+            .cloned()
+    };
+    LogRecord::Header {
+        version: 0,
+        yammer: "unknown".to_string(),
+        model: flag("model").unwrap_or_default(),
+        host: flag("url").unwrap_or_default(),
+        options: serde_json::Value::Null,
+        args,
+        started_at: "".to_string(),
+        resumes: false,
+    }
+}
+
+///////////////////////////////////////////// browsing /////////////////////////////////////////////
+
+/// The options for `yammer logs grep`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct GrepOptions {
+    #[arrrg(
+        optional,
+        "Only search messages with this role, e.g., user or assistant."
+    )]
+    pub role: Option<String>,
+    #[arrrg(flag, "Match without regard to case.")]
+    pub ignore_case: bool,
+}
+
+/// The options for `yammer logs replay`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct ReplayOptions {
+    #[arrrg(required, "The model to re-run every user turn against.")]
+    pub model: String,
+    #[arrrg(
+        optional,
+        "The log to write; defaults to the input's name with the model appended."
+    )]
+    pub out: Option<String>,
+}
+
+/// Find the chat logs among `paths`, looking inside directories.
+///
+/// Files that are not chat logs are passed over, so a directory may hold other files too.
+pub fn find(paths: &[std::path::PathBuf]) -> Result<Vec<(std::path::PathBuf, Log)>, Error> {
+    let mut logs = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.sort();
+            for entry in entries.into_iter().filter(|e| e.is_file()) {
+                // NOTE(rescrv):  Unreadable or binary files are not logs.
+                let Ok(log) = Log::load(&entry) else {
+                    continue;
+                };
+                if matches!(log.records.first(), Some(LogRecord::Header { .. })) {
+                    logs.push((entry, log));
+                }
+            }
+        } else {
+            let log = Log::load(path)?;
+            logs.push((path.clone(), log));
+        }
+    }
+    Ok(logs)
+}
+
+///////////////////////////////////////////// LogWriter ////////////////////////////////////////////
+
+/// Append records to a log, flushing each so that a crash loses nothing.
+#[derive(Debug)]
+pub struct LogWriter {
+    output: BufWriter<std::fs::File>,
+}
+
+impl LogWriter {
+    /// Open `path` for appending, writing `header` first.
+    pub fn open(path: impl AsRef<std::path::Path>, header: LogRecord) -> Result<Self, Error> {
+        let output = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = Self {
+            output: BufWriter::new(output),
+        };
+        writer.append(&header)?;
+        Ok(writer)
+    }
+
+    /// Append `record` to the log.
+    pub fn append(&mut self, record: &LogRecord) -> Result<(), Error> {
+        writeln!(self.output, "{}", serde_json::to_string(record)?)?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
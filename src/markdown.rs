@@ -0,0 +1,757 @@
+//! Streaming rendering of markdown responses for the terminal.
+//!
+//! Responses arrive a few tokens at a time, so the renderer writes prose as it arrives and holds
+//! back only what it cannot style yet:  the start of a line until its kind is known, emphasis, code
+//! spans, and links until they close, and fenced code a line at a time.  It styles headings and
+//! emphasis in bold and italics, list bullets, block quotes, and fenced code with keyword, string,
+//! number, and comment highlighting.  Styling is only applied when the output is a terminal and
+//! `NO_COLOR` is unset; otherwise text passes through untouched as it arrives.
+
+use std::io::{IsTerminal, Write};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const KEYWORD: &str = "\x1b[35m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const COMMENT: &str = "\x1b[90m";
+const CODE: &str = "\x1b[36m";
+
+/// True if stdout is a terminal and the user has not asked for plain output with `NO_COLOR`.
+pub fn color_enabled() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+//////////////////////////////////////// MarkdownAccumulator ///////////////////////////////////////
+
+/// MarkdownAccumulator writes the text of each message to `output`, styled as markdown.
+#[derive(Debug)]
+pub struct MarkdownAccumulator<W: Write + std::fmt::Debug> {
+    output: W,
+    pointer: String,
+    styled: bool,
+    trim_start: bool,
+    seen_non_ws: bool,
+    line: String,
+    open: Option<Open>,
+    fence: Option<Fence>,
+}
+
+#[derive(Debug)]
+struct Fence {
+    marker: String,
+    lang: String,
+}
+
+/// A line whose kind is known and whose prefix has been written.
+#[derive(Debug)]
+struct Open {
+    /// Headings are not styled inline and drop trailing whitespace.
+    plain: bool,
+    /// Whitespace before the text is dropped, e.g., after the `>` of a quote.
+    trim_start: bool,
+    inline: Inline,
+}
+
+impl Open {
+    fn new(plain: bool, trim_start: bool) -> Self {
+        Self {
+            plain,
+            trim_start,
+            inline: Inline::default(),
+        }
+    }
+
+    /// Style what can be styled of `pending` and remove it.
+    fn stream(&mut self, pending: &mut String) -> String {
+        if self.trim_start {
+            let text = pending.trim_start().len();
+            pending.drain(..pending.len() - text);
+            if pending.is_empty() {
+                return String::new();
+            }
+            self.trim_start = false;
+        }
+        if !self.plain {
+            let (rendered, used) = self.inline.render(pending, false);
+            pending.drain(..used);
+            return rendered;
+        }
+        let used = pending.trim_end().len();
+        pending.drain(..used).collect()
+    }
+
+    /// Style `rest`, the end of the line.
+    fn finish(mut self, rest: &str) -> String {
+        let rest = if self.trim_start {
+            rest.trim_start()
+        } else {
+            rest
+        };
+        if self.plain {
+            return format!("{}{RESET}", rest.trim_end());
+        }
+        let (mut rendered, _) = self.inline.render(rest, true);
+        if self.inline.bold || self.inline.italic {
+            rendered += RESET;
+        }
+        rendered
+    }
+}
+
+impl<W: Write + std::fmt::Debug> MarkdownAccumulator<W> {
+    /// Render the text found at the JSON `pointer` of each message, e.g., `/response`.
+    pub fn new(output: W, pointer: impl Into<String>) -> Self {
+        Self {
+            output,
+            pointer: pointer.into(),
+            styled: color_enabled(),
+            trim_start: false,
+            seen_non_ws: false,
+            line: String::new(),
+            open: None,
+            fence: None,
+        }
+    }
+
+    /// Render the content of chat responses, dropping leading whitespace like [ChatAccumulator].
+    ///
+    /// [ChatAccumulator]: crate::ChatAccumulator
+    pub fn chat(output: W) -> Self {
+        let mut this = Self::new(output, "/message/content");
+        this.trim_start = true;
+        this
+    }
+
+    /// Render the response of generate requests.
+    pub fn generate(output: W) -> Self {
+        Self::new(output, "/response")
+    }
+
+    /// Style the output if `style` is true and the terminal allows it; pass it through if not.
+    pub fn with_style(mut self, style: bool) -> Self {
+        self.styled = style && color_enabled();
+        self
+    }
+
+    /// Style the output if `color` is true, whether or not stdout is a terminal.
+    ///
+    /// This is for output that goes somewhere other than stdout.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.styled = color;
+        self
+    }
+
+    /// Render whatever remains of the current line and reset for the next response.
+    pub fn finish(&mut self) {
+        if !self.line.is_empty() || self.open.is_some() {
+            let line = std::mem::take(&mut self.line);
+            let rendered = self.end_line(&line);
+            let _ = write!(self.output, "{rendered}");
+        }
+        let _ = self.output.flush();
+        self.fence = None;
+        self.seen_non_ws = false;
+    }
+
+    /// Render the end of the current line, `rest` being the part not yet written.
+    fn end_line(&mut self, rest: &str) -> String {
+        match self.open.take() {
+            Some(open) => open.finish(rest),
+            None => self.render(rest),
+        }
+    }
+
+    /// Write the part of the current line that can be styled before the line is complete.
+    fn stream(&mut self) -> String {
+        let mut rendered = String::new();
+        if self.open.is_none() {
+            let Some((prefix, used, open)) = self.open_line(&self.line) else {
+                return rendered;
+            };
+            rendered += &prefix;
+            self.line.drain(..used);
+            self.open = Some(open);
+        }
+        if let Some(open) = self.open.as_mut() {
+            rendered += &open.stream(&mut self.line);
+        }
+        rendered
+    }
+
+    /// The prefix of `line`, the bytes it covers, and how to style the rest, once the start of
+    /// `line` fixes its kind as [render](Self::render) would see it.
+    ///
+    /// Fenced code, and lines that could still be a fence or a rule, wait for the whole line.
+    fn open_line(&self, line: &str) -> Option<(String, usize, Open)> {
+        if self.fence.is_some() {
+            return None;
+        }
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        if trimmed.is_empty()
+            || ["```", "~~~"]
+                .iter()
+                .any(|m| trimmed.starts_with(m) || m.starts_with(trimmed))
+        {
+            return None;
+        }
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if hashes == trimmed.len() && hashes <= 6 {
+            return None;
+        }
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            let style = if hashes == 1 {
+                format!("{BOLD}{UNDERLINE}")
+            } else {
+                BOLD.to_string()
+            };
+            return Some((style, indent.len() + hashes, Open::new(true, true)));
+        }
+        if trimmed
+            .chars()
+            .all(|c| c.is_whitespace() || ['-', '*', '_'].contains(&c))
+        {
+            return None;
+        }
+        if trimmed.starts_with('>') {
+            let prefix = format!("{indent}{DIM}│{RESET} ");
+            return Some((prefix, indent.len() + 1, Open::new(false, true)));
+        }
+        if trimmed == "+" {
+            return None;
+        }
+        for bullet in ["- ", "* ", "+ "] {
+            if trimmed.starts_with(bullet) {
+                let prefix = format!("{indent}• ");
+                return Some((prefix, indent.len() + 2, Open::new(false, false)));
+            }
+        }
+        let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits > 0 && (digits == trimmed.len() || &trimmed[digits..] == ".") {
+            return None;
+        }
+        if digits > 0 && trimmed[digits..].starts_with(". ") {
+            let prefix = format!("{indent}{BOLD}{}{RESET} ", &trimmed[..digits + 1]);
+            return Some((prefix, indent.len() + digits + 2, Open::new(false, false)));
+        }
+        Some((String::new(), 0, Open::new(false, false)))
+    }
+
+    fn render(&mut self, line: &str) -> String {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        if let Some(fence) = self.fence.as_ref() {
+            if trimmed.starts_with(&fence.marker) && trimmed.trim_end() == fence.marker {
+                self.fence = None;
+                return format!("{DIM}{line}{RESET}");
+            }
+            return highlight(line, &fence.lang);
+        }
+        for marker in ["```", "~~~"] {
+            if let Some(lang) = trimmed.strip_prefix(marker) {
+                let extra = lang
+                    .chars()
+                    .take_while(|c| *c == marker.as_bytes()[0] as char);
+                let marker = format!("{marker}{}", extra.collect::<String>());
+                let lang = trimmed[marker.len()..].trim();
+                self.fence = Some(Fence {
+                    marker,
+                    lang: lang.split_whitespace().next().unwrap_or("").to_lowercase(),
+                });
+                return format!("{DIM}{line}{RESET}");
+            }
+        }
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            let style = if hashes == 1 {
+                format!("{BOLD}{UNDERLINE}")
+            } else {
+                BOLD.to_string()
+            };
+            return format!("{style}{}{RESET}", trimmed[hashes..].trim());
+        }
+        let compact = trimmed
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>();
+        if compact.len() >= 3
+            && ['-', '*', '_']
+                .iter()
+                .any(|m| compact.chars().all(|c| c == *m))
+        {
+            return format!("{DIM}{}{RESET}", "─".repeat(40));
+        }
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            return format!("{indent}{DIM}│{RESET} {}", inline(quote.trim_start()));
+        }
+        for bullet in ["- ", "* ", "+ "] {
+            if let Some(item) = trimmed.strip_prefix(bullet) {
+                return format!("{indent}• {}", inline(item));
+            }
+        }
+        let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits > 0 && trimmed[digits..].starts_with(". ") {
+            return format!(
+                "{indent}{BOLD}{}{RESET} {}",
+                &trimmed[..digits + 1],
+                inline(&trimmed[digits + 2..])
+            );
+        }
+        inline(line)
+    }
+}
+
+impl<W: Write + std::fmt::Debug> super::Accumulator for MarkdownAccumulator<W> {
+    fn accumulate(&mut self, message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        if let Some(text) = message.pointer(&self.pointer).and_then(|t| t.as_str()) {
+            if self.trim_start && !self.seen_non_ws && text.trim().is_empty() {
+                return std::ops::ControlFlow::Continue(());
+            }
+            self.seen_non_ws = true;
+            if self.styled {
+                self.line.push_str(text);
+                while let Some(idx) = self.line.find('\n') {
+                    let line = self.line[..idx].to_string();
+                    self.line.drain(..=idx);
+                    let rendered = self.end_line(&line);
+                    let _ = writeln!(self.output, "{rendered}");
+                }
+                let rendered = self.stream();
+                let _ = write!(self.output, "{rendered}");
+            } else {
+                let _ = write!(self.output, "{text}");
+            }
+            let _ = self.output.flush();
+        }
+        if message.get("done").and_then(|d| d.as_bool()) == Some(true) {
+            self.finish();
+        }
+        std::ops::ControlFlow::Continue(())
+    }
+}
+
+impl<W: Write + std::fmt::Debug> Drop for MarkdownAccumulator<W> {
+    fn drop(&mut self) {
+        // NOTE(rescrv):  A cancelled response never sends done; show what did arrive.
+        self.finish();
+    }
+}
+
+////////////////////////////////////////////// inline //////////////////////////////////////////////
+
+/// Style the emphasis, code spans, and links of one line of prose.
+fn inline(text: &str) -> String {
+    let mut inline = Inline::default();
+    let (mut rendered, _) = inline.render(text, true);
+    if inline.bold || inline.italic {
+        rendered += RESET;
+    }
+    rendered
+}
+
+/// The styles open at some point in a line of prose.
+#[derive(Debug, Default)]
+struct Inline {
+    bold: bool,
+    italic: bool,
+    prev: Option<char>,
+}
+
+impl Inline {
+    /// Style as much of `text` as can be decided, returning it and the bytes of `text` it covers.
+    ///
+    /// Whether a marker opens emphasis, a code span, or a link depends on what follows it on the
+    /// line, so unless `end` says the line is complete, rendering stops at the first marker that
+    /// could go either way.
+    fn render(&mut self, text: &str, end: bool) -> (String, usize) {
+        let chars = text.chars().collect::<Vec<_>>();
+        let closes = |from: usize, marker: &[char]| {
+            (from..chars.len().saturating_sub(marker.len() - 1))
+                .any(|j| chars[j..j + marker.len()] == *marker)
+        };
+        let mut out = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let prev = if i > 0 { Some(chars[i - 1]) } else { self.prev };
+            let prev_alnum = prev.is_some_and(|p| p.is_alphanumeric());
+            let next = chars.get(i + 1).copied();
+            if c == '`' {
+                if let Some(len) = chars[i + 1..].iter().position(|x| *x == '`') {
+                    let code = chars[i + 1..i + 1 + len].iter().collect::<String>();
+                    out += &format!("{CODE}{code}{RESET}{}", restyle(self.bold, self.italic));
+                    i += len + 2;
+                    continue;
+                } else if !end {
+                    break;
+                }
+            } else if (c == '*' || c == '_') && next.is_none() && !end {
+                break;
+            } else if (c == '*' || c == '_') && next == Some(c) {
+                if self.bold || (!prev_alnum && closes(i + 2, &[c, c])) {
+                    self.bold = !self.bold;
+                    out += RESET;
+                    out += &restyle(self.bold, self.italic);
+                    i += 2;
+                    continue;
+                } else if !prev_alnum && !end {
+                    break;
+                }
+            } else if c == '*' || (c == '_' && !prev_alnum) || (c == '_' && self.italic) {
+                let could_open = !self.italic && next.is_some_and(|n| !n.is_whitespace());
+                if self.italic || (could_open && closes(i + 1, &[c])) {
+                    self.italic = !self.italic;
+                    out += RESET;
+                    out += &restyle(self.bold, self.italic);
+                    i += 1;
+                    continue;
+                } else if could_open && !end {
+                    break;
+                }
+            } else if c == '[' {
+                let rest = chars[i..].iter().collect::<String>();
+                if let Some(mid) = rest.find("](") {
+                    if let Some(close) = rest[mid..].find(')') {
+                        let label = &rest[1..mid];
+                        let url = &rest[mid + 2..mid + close];
+                        out += &format!(
+                            "{UNDERLINE}{label}{RESET}{DIM} ({url}){RESET}{}",
+                            restyle(self.bold, self.italic)
+                        );
+                        i += rest[..mid + close + 1].chars().count();
+                        continue;
+                    }
+                }
+                if !end {
+                    break;
+                }
+            }
+            out.push(c);
+            i += 1;
+        }
+        if i > 0 {
+            self.prev = Some(chars[i - 1]);
+        }
+        (out, chars[..i].iter().map(|c| c.len_utf8()).sum())
+    }
+}
+
+fn restyle(bold: bool, italic: bool) -> String {
+    let mut style = String::new();
+    if bold {
+        style += BOLD;
+    }
+    if italic {
+        style += ITALIC;
+    }
+    style
+}
+
+///////////////////////////////////////////// highlight ////////////////////////////////////////////
+
+/// Highlight one line of code in `lang`.
+///
+/// This is a lexer, not a parser:  it knows each language's keywords, strings, numbers, and line
+/// comments, which is enough to make a snippet readable at a glance.
+fn highlight(line: &str, lang: &str) -> String {
+    let (keywords, comment): (&[&str], Option<&str>) = match lang {
+        "rust" | "rs" => (
+            &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+                "enum", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+                "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+                "trait", "true", "type", "unsafe", "use", "where", "while",
+            ],
+            Some("//"),
+        ),
+        "python" | "py" => (
+            &[
+                "and", "as", "async", "await", "break", "class", "continue", "def", "elif", "else",
+                "except", "False", "finally", "for", "from", "if", "import", "in", "is", "lambda",
+                "None", "not", "or", "pass", "raise", "return", "self", "True", "try", "while",
+                "with", "yield",
+            ],
+            Some("#"),
+        ),
+        "javascript" | "js" | "typescript" | "ts" | "jsx" | "tsx" => (
+            &[
+                "async",
+                "await",
+                "break",
+                "case",
+                "catch",
+                "class",
+                "const",
+                "continue",
+                "default",
+                "else",
+                "export",
+                "extends",
+                "false",
+                "finally",
+                "for",
+                "from",
+                "function",
+                "if",
+                "import",
+                "interface",
+                "let",
+                "new",
+                "null",
+                "return",
+                "switch",
+                "this",
+                "throw",
+                "true",
+                "try",
+                "type",
+                "typeof",
+                "undefined",
+                "var",
+                "while",
+            ],
+            Some("//"),
+        ),
+        "go" | "golang" => (
+            &[
+                "break",
+                "case",
+                "chan",
+                "const",
+                "continue",
+                "default",
+                "defer",
+                "else",
+                "false",
+                "for",
+                "func",
+                "go",
+                "if",
+                "import",
+                "interface",
+                "map",
+                "nil",
+                "package",
+                "range",
+                "return",
+                "select",
+                "struct",
+                "switch",
+                "true",
+                "type",
+                "var",
+            ],
+            Some("//"),
+        ),
+        "c" | "cpp" | "c++" | "h" | "hpp" | "java" | "cs" | "csharp" => (
+            &[
+                "auto",
+                "break",
+                "case",
+                "char",
+                "class",
+                "const",
+                "continue",
+                "default",
+                "delete",
+                "double",
+                "else",
+                "enum",
+                "false",
+                "float",
+                "for",
+                "if",
+                "include",
+                "int",
+                "long",
+                "namespace",
+                "new",
+                "null",
+                "NULL",
+                "nullptr",
+                "private",
+                "protected",
+                "public",
+                "return",
+                "sizeof",
+                "static",
+                "struct",
+                "switch",
+                "this",
+                "true",
+                "typedef",
+                "unsigned",
+                "void",
+                "while",
+            ],
+            Some("//"),
+        ),
+        "sh" | "bash" | "shell" | "zsh" | "console" => (
+            &[
+                "case", "do", "done", "echo", "elif", "else", "esac", "export", "fi", "for",
+                "function", "if", "in", "local", "return", "then", "while",
+            ],
+            Some("#"),
+        ),
+        "toml" | "yaml" | "yml" => (&["true", "false", "null"], Some("#")),
+        "json" => (&["true", "false", "null"], None),
+        "sql" => (
+            &[
+                "AND", "AS", "BY", "CREATE", "DELETE", "FROM", "GROUP", "INSERT", "INTO", "JOIN",
+                "NOT", "NULL", "ON", "OR", "ORDER", "SELECT", "SET", "TABLE", "UPDATE", "VALUES",
+                "WHERE",
+            ],
+            Some("--"),
+        ),
+        _ => (&[], None),
+    };
+    // NOTE(rescrv):  A quote in Rust is as likely a lifetime as a char.
+    let quotes: &[char] = if matches!(lang, "rust" | "rs") {
+        &['"']
+    } else {
+        &['"', '\'', '`']
+    };
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some(comment) = comment {
+            if chars[i..].iter().collect::<String>().starts_with(comment) {
+                out += &format!("{COMMENT}{}{RESET}", chars[i..].iter().collect::<String>());
+                break;
+            }
+        }
+        if quotes.contains(&c) {
+            let mut j = i + 1;
+            while j < chars.len() && chars[j] != c {
+                j += if chars[j] == '\\' { 2 } else { 1 };
+            }
+            let end = (j + 1).min(chars.len());
+            out += &format!(
+                "{STRING}{}{RESET}",
+                chars[i..end].iter().collect::<String>()
+            );
+            i = end;
+        } else if c.is_ascii_digit() && (i == 0 || !is_ident(chars[i - 1])) {
+            let end = (i..chars.len())
+                .find(|j| {
+                    !(chars[*j].is_ascii_alphanumeric() || chars[*j] == '.' || chars[*j] == '_')
+                })
+                .unwrap_or(chars.len());
+            out += &format!(
+                "{NUMBER}{}{RESET}",
+                chars[i..end].iter().collect::<String>()
+            );
+            i = end;
+        } else if is_ident(c) {
+            let end = (i..chars.len())
+                .find(|j| !is_ident(chars[*j]))
+                .unwrap_or(chars.len());
+            let word = chars[i..end].iter().collect::<String>();
+            if keywords.contains(&word.as_str()) {
+                out += &format!("{KEYWORD}{word}{RESET}");
+            } else {
+                out += &word;
+            }
+            i = end;
+        } else {
+            out.push(c);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+///////////////////////////////////////////// CodeBlock ////////////////////////////////////////////
+
+/// A fenced code block.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CodeBlock {
+    /// The language named by the opening fence, which may be empty.
+    pub lang: String,
+    /// The code, with a trailing newline after every line.
+    pub code: String,
+}
+
+/// The fenced code blocks of `content`, in order.
+///
+/// An unterminated final block, e.g., from a cancelled response, still counts.
+pub fn code_blocks(content: &str) -> Vec<CodeBlock> {
+    segments(content)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Code(block) => Some(block),
+            Segment::Text(_) => None,
+        })
+        .collect()
+}
+
+/// A run of text or a fenced code block.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Code(CodeBlock),
+}
+
+/// Split `content` into fenced code blocks and the text between them, in order.
+pub fn segments(content: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut open: Option<(String, usize, CodeBlock)> = None;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        if let Some((marker, fence_indent, mut block)) = open.take() {
+            if trimmed.trim_end() == marker {
+                segments.push(Segment::Code(block));
+            } else {
+                // NOTE(rescrv):  Fences nested in lists are indented; so is their code.
+                let strip = line
+                    .chars()
+                    .take(fence_indent)
+                    .take_while(|c| *c == ' ')
+                    .count();
+                block.code.push_str(&line[strip..]);
+                block.code.push('\n');
+                open = Some((marker, fence_indent, block));
+            }
+            continue;
+        }
+        for fence in ['`', '~'] {
+            let width = trimmed.chars().take_while(|c| *c == fence).count();
+            if width >= 3 {
+                let lang = trimmed[width..].split_whitespace().next().unwrap_or("");
+                let block = CodeBlock {
+                    lang: lang.to_lowercase(),
+                    code: String::new(),
+                };
+                open = Some((trimmed[..width].to_string(), indent, block));
+                break;
+            }
+        }
+        if open.is_some() {
+            if !text.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+        } else {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    if let Some((_, _, block)) = open {
+        segments.push(Segment::Code(block));
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    segments
+}
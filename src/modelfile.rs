@@ -0,0 +1,485 @@
+//! A typed representation of ollama's Modelfile.
+//!
+//! [Modelfile] parses the `FROM`, `PARAMETER`, `TEMPLATE`, `SYSTEM`, `ADAPTER`, `LICENSE`, and
+//! `MESSAGE` instructions, validates them, and formats them back out in a canonical order.  A
+//! Modelfile written by hand and the one `yammer show --modelfile` prints for the model it built
+//! format identically, so the two can be compared with diff.
+//!
+//! Values are written bare, in double quotes with `\"`, `\\`, and `\n` escapes, or between triple
+//! quotes, which may span lines and are taken verbatim.
+//!
+//! Parsing with [std::str::FromStr] accepts any parameter and role so that the Modelfile of a
+//! model built by a newer ollama still reads.  [Modelfile::parse_checked] additionally rejects
+//! what ollama would, and is what `create` uses.
+
+use std::path::Path;
+
+use super::{CreateRequest, Error};
+
+/// The parameters ollama accepts and the type of value each takes.
+const PARAMETERS: &[(&str, Kind)] = &[
+    ("num_ctx", Kind::Int),
+    ("num_batch", Kind::Int),
+    ("num_gpu", Kind::Int),
+    ("main_gpu", Kind::Int),
+    ("num_thread", Kind::Int),
+    ("num_keep", Kind::Int),
+    ("num_predict", Kind::Int),
+    ("seed", Kind::Int),
+    ("top_k", Kind::Int),
+    ("repeat_last_n", Kind::Int),
+    ("mirostat", Kind::Int),
+    ("temperature", Kind::Float),
+    ("top_p", Kind::Float),
+    ("min_p", Kind::Float),
+    ("typical_p", Kind::Float),
+    ("tfs_z", Kind::Float),
+    ("repeat_penalty", Kind::Float),
+    ("presence_penalty", Kind::Float),
+    ("frequency_penalty", Kind::Float),
+    ("mirostat_tau", Kind::Float),
+    ("mirostat_eta", Kind::Float),
+    ("penalize_newline", Kind::Bool),
+    ("numa", Kind::Bool),
+    ("low_vram", Kind::Bool),
+    ("f16_kv", Kind::Bool),
+    ("vocab_only", Kind::Bool),
+    ("use_mmap", Kind::Bool),
+    ("use_mlock", Kind::Bool),
+    ("stop", Kind::String),
+];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Int,
+    Float,
+    Bool,
+    String,
+}
+
+fn kind(name: &str) -> Option<Kind> {
+    PARAMETERS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, kind)| *kind)
+}
+
+///////////////////////////////////////////// Modelfile ////////////////////////////////////////////
+
+/// A Modelfile:  the base model and everything layered on top of it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Modelfile {
+    /// The model, GGUF file, or safetensors directory to build from.
+    pub from: String,
+    /// Parameters in the order given.  A name may repeat, e.g., `stop`.
+    pub parameters: Vec<(String, String)>,
+    /// The prompt template.
+    pub template: Option<String>,
+    /// The system prompt.
+    pub system: Option<String>,
+    /// LoRA adapters to apply.
+    pub adapters: Vec<String>,
+    /// The licenses the model is distributed under.
+    pub licenses: Vec<String>,
+    /// The conversation to seed every chat with, as (role, content).
+    pub messages: Vec<(String, String)>,
+}
+
+impl Modelfile {
+    /// A Modelfile that builds from `from` and changes nothing.
+    pub fn new(from: impl Into<String>) -> Self {
+        Self {
+            from: from.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_parameter(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.parameters.push((name.into(), value.to_string()));
+        self
+    }
+
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    pub fn with_adapter(mut self, adapter: impl Into<String>) -> Self {
+        self.adapters.push(adapter.into());
+        self
+    }
+
+    pub fn with_license(mut self, license: impl Into<String>) -> Self {
+        self.licenses.push(license.into());
+        self
+    }
+
+    pub fn with_message(mut self, role: impl Into<String>, content: impl Into<String>) -> Self {
+        self.messages.push((role.into(), content.into()));
+        self
+    }
+
+    /// The values given for the parameter `name`, in order.
+    pub fn parameter(&self, name: &str) -> Vec<&str> {
+        self.parameters
+            .iter()
+            .filter(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// The parameters as a JSON object of model options.  Repeated parameters become arrays.
+    pub fn options(&self) -> serde_json::Value {
+        let mut options = serde_json::Map::new();
+        for (name, value) in self.parameters.iter() {
+            let value = match kind(name) {
+                Some(Kind::Int) => value.parse::<i64>().ok().map(serde_json::Value::from),
+                Some(Kind::Float) => value.parse::<f64>().ok().map(serde_json::Value::from),
+                Some(Kind::Bool) => value.parse::<bool>().ok().map(serde_json::Value::from),
+                Some(Kind::String) | None => None,
+            }
+            .unwrap_or_else(|| serde_json::Value::String(value.clone()));
+            if name == "stop" {
+                let stop = options
+                    .entry(name.clone())
+                    .or_insert_with(|| serde_json::Value::Array(vec![]));
+                if let Some(stop) = stop.as_array_mut() {
+                    stop.push(value);
+                }
+            } else {
+                options.insert(name.clone(), value);
+            }
+        }
+        serde_json::Value::Object(options)
+    }
+
+    /// Check the Modelfile for mistakes that ollama would reject.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.from.trim().is_empty() {
+            return Err(Error::Message("a Modelfile needs FROM".to_string()));
+        }
+        for (name, value) in self.parameters.iter() {
+            check_parameter(name, value)?;
+        }
+        for (role, _) in self.messages.iter() {
+            check_role(role)?;
+        }
+        Ok(())
+    }
+
+    /// Make the relative paths of FROM and ADAPTER relative to `dir` instead.
+    ///
+    /// A Modelfile read from disk names local files relative to itself, but the server resolves
+    /// them relative to wherever it runs.
+    pub fn resolve_paths(&mut self, dir: impl AsRef<Path>) {
+        let dir = dir.as_ref();
+        let resolve = |path: &mut String| {
+            if let Some(relative) = path.strip_prefix("./") {
+                *path = dir.join(relative).display().to_string();
+            } else if path.starts_with("../") {
+                *path = dir.join(&*path).display().to_string();
+            }
+        };
+        resolve(&mut self.from);
+        self.adapters.iter_mut().for_each(resolve);
+    }
+
+    /// Parse and validate a Modelfile, reporting the line of the first mistake.
+    pub fn parse_checked(s: &str) -> Result<Self, Error> {
+        let modelfile = Self::parse(s, true)?;
+        modelfile.validate()?;
+        Ok(modelfile)
+    }
+
+    fn parse(s: &str, checked: bool) -> Result<Self, Error> {
+        let lines = s.lines().collect::<Vec<_>>();
+        let mut modelfile = Modelfile::default();
+        let mut from = None;
+        let mut template = None;
+        let mut system = None;
+        let mut idx = 0;
+        while idx < lines.len() {
+            let lineno = idx + 1;
+            let line = lines[idx].trim();
+            idx += 1;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: String| Error::Message(format!("line {lineno}: {msg}"));
+            let (instruction, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let instruction = instruction.to_ascii_uppercase();
+            let rest = rest.trim_start();
+            let once = |seen: &mut Option<usize>| match seen.replace(lineno) {
+                Some(first) => Err(err(format!(
+                    "{instruction} given twice; first on line {first}"
+                ))),
+                None => Ok(()),
+            };
+            match instruction.as_str() {
+                "FROM" => {
+                    once(&mut from)?;
+                    modelfile.from = value(&instruction, rest, &lines, &mut idx, lineno)?;
+                }
+                "PARAMETER" => {
+                    let (name, rest) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| err("PARAMETER needs a name and a value".to_string()))?;
+                    let name = name.to_ascii_lowercase();
+                    let value = value(&instruction, rest.trim_start(), &lines, &mut idx, lineno)?;
+                    if checked {
+                        check_parameter(&name, &value).map_err(|e| err(e.to_string()))?;
+                    }
+                    modelfile.parameters.push((name, value));
+                }
+                "TEMPLATE" => {
+                    once(&mut template)?;
+                    modelfile.template = Some(value(&instruction, rest, &lines, &mut idx, lineno)?);
+                }
+                "SYSTEM" => {
+                    once(&mut system)?;
+                    modelfile.system = Some(value(&instruction, rest, &lines, &mut idx, lineno)?);
+                }
+                "ADAPTER" => {
+                    let adapter = value(&instruction, rest, &lines, &mut idx, lineno)?;
+                    modelfile.adapters.push(adapter);
+                }
+                "LICENSE" => {
+                    let license = value(&instruction, rest, &lines, &mut idx, lineno)?;
+                    modelfile.licenses.push(license);
+                }
+                "MESSAGE" => {
+                    let (role, rest) = rest
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| err("MESSAGE needs a role and content".to_string()))?;
+                    let role = role.to_ascii_lowercase();
+                    if checked {
+                        check_role(&role).map_err(|e| err(e.to_string()))?;
+                    }
+                    let content = value(&instruction, rest.trim_start(), &lines, &mut idx, lineno)?;
+                    modelfile.messages.push((role, content));
+                }
+                _ => {
+                    return Err(err(format!("unknown instruction {instruction}")));
+                }
+            }
+        }
+        if from.is_none() {
+            return Err(Error::Message("a Modelfile needs FROM".to_string()));
+        }
+        Ok(modelfile)
+    }
+}
+
+impl std::str::FromStr for Modelfile {
+    type Err = Error;
+
+    /// Parse a Modelfile without judging its parameters or roles, reporting the line of the first
+    /// syntax error.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, false)
+    }
+}
+
+impl std::fmt::Display for Modelfile {
+    /// Format the Modelfile canonically:  FROM, ADAPTER, PARAMETER, TEMPLATE, SYSTEM, MESSAGE,
+    /// and LICENSE, in that order.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "FROM {}", bare(&self.from))?;
+        for adapter in self.adapters.iter() {
+            writeln!(f, "ADAPTER {}", bare(adapter))?;
+        }
+        for (name, value) in self.parameters.iter() {
+            writeln!(f, "PARAMETER {name} {}", bare(value))?;
+        }
+        if let Some(template) = self.template.as_ref() {
+            writeln!(f, "TEMPLATE {}", quoted(template))?;
+        }
+        if let Some(system) = self.system.as_ref() {
+            writeln!(f, "SYSTEM {}", quoted(system))?;
+        }
+        for (role, content) in self.messages.iter() {
+            writeln!(f, "MESSAGE {role} {}", quoted(content))?;
+        }
+        for license in self.licenses.iter() {
+            writeln!(f, "LICENSE {}", quoted(license))?;
+        }
+        Ok(())
+    }
+}
+
+fn check_parameter(name: &str, value: &str) -> Result<(), Error> {
+    let ok = match kind(name) {
+        Some(Kind::Int) => value.parse::<i64>().is_ok(),
+        Some(Kind::Float) => value.parse::<f64>().is_ok(),
+        Some(Kind::Bool) => value.parse::<bool>().is_ok(),
+        Some(Kind::String) => true,
+        None => {
+            return Err(Error::Message(format!("unknown parameter {name}")));
+        }
+    };
+    if !ok {
+        return Err(Error::Message(format!(
+            "parameter {name} takes {}, not {value:?}",
+            match kind(name) {
+                Some(Kind::Int) => "an integer",
+                Some(Kind::Float) => "a number",
+                _ => "true or false",
+            }
+        )));
+    }
+    Ok(())
+}
+
+fn check_role(role: &str) -> Result<(), Error> {
+    if !matches!(role, "system" | "user" | "assistant") {
+        return Err(Error::Message(format!(
+            "unknown role {role}; expected system, user, or assistant"
+        )));
+    }
+    Ok(())
+}
+
+/// Read the value that starts at `rest` on line `lineno`, consuming more lines for `"""`.
+fn value(
+    instruction: &str,
+    rest: &str,
+    lines: &[&str],
+    idx: &mut usize,
+    lineno: usize,
+) -> Result<String, Error> {
+    let err = |msg: String| Error::Message(format!("line {lineno}: {msg}"));
+    if let Some(after) = rest.strip_prefix("\"\"\"") {
+        if let Some((value, trailing)) = after.split_once("\"\"\"") {
+            if !trailing.trim().is_empty() {
+                return Err(err(format!("unexpected {trailing:?} after \"\"\"")));
+            }
+            return Ok(value.to_string());
+        }
+        let mut value = after.to_string();
+        while *idx < lines.len() {
+            let line = lines[*idx];
+            *idx += 1;
+            value.push('\n');
+            if let Some((last, trailing)) = line.split_once("\"\"\"") {
+                if !trailing.trim().is_empty() {
+                    return Err(Error::Message(format!(
+                        "line {}: unexpected {trailing:?} after \"\"\"",
+                        *idx
+                    )));
+                }
+                value.push_str(last);
+                return Ok(value);
+            }
+            value.push_str(line);
+        }
+        return Err(err("unterminated \"\"\"".to_string()));
+    }
+    if let Some(after) = rest.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = after.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    let trailing = chars.as_str();
+                    if !trailing.trim().is_empty() {
+                        return Err(err(format!("unexpected {trailing:?} after closing quote")));
+                    }
+                    return Ok(value);
+                }
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some(c) => value.push(c),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        return Err(err("unterminated quote".to_string()));
+    }
+    let value = rest.trim_end();
+    if value.is_empty() {
+        return Err(err(format!("{instruction} needs a value")));
+    }
+    Ok(value.to_string())
+}
+
+/// Format a short value bare when that reads back the same, and quoted otherwise.
+fn bare(value: &str) -> String {
+    if !value.is_empty() && !value.contains(char::is_whitespace) && !value.starts_with('"') {
+        value.to_string()
+    } else {
+        escaped(value)
+    }
+}
+
+/// Format a value between triple quotes, unless it holds triple quotes itself.
+fn quoted(value: &str) -> String {
+    if value.contains("\"\"\"") || value.ends_with('"') {
+        escaped(value)
+    } else {
+        format!("\"\"\"{value}\"\"\"")
+    }
+}
+
+fn escaped(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/////////////////////////////////////////// CreateOptions //////////////////////////////////////////
+
+/// CreateOptions describes a [CreateRequest] in terms convenient for the command line.
+#[derive(Clone, Debug, Default, Eq, PartialEq, arrrg_derive::CommandLine, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateOptions {
+    #[arrrg(required, "The name of the model to create.")]
+    pub name: String,
+    #[arrrg(optional, "The contents of the Modelfile.")]
+    #[serde(default)]
+    pub modelfile: Option<String>,
+    #[arrrg(optional, "A file containing the Modelfile.")]
+    #[serde(default)]
+    pub file: Option<String>,
+    #[arrrg(optional, "An optional quantize specification.")]
+    #[serde(default)]
+    pub quantize: Option<String>,
+}
+
+impl CreateOptions {
+    /// Read and validate the Modelfile and construct the CreateRequest.
+    pub fn request(&self) -> Result<CreateRequest, Error> {
+        let modelfile = match (self.modelfile.as_ref(), self.file.as_ref()) {
+            (Some(modelfile), None) => Modelfile::parse_checked(modelfile)?,
+            (None, Some(file)) => {
+                let mut modelfile = Modelfile::parse_checked(&std::fs::read_to_string(file)?)
+                    .map_err(|err| Error::Message(format!("{file}: {err}")))?;
+                let dir = Path::new(file).parent().unwrap_or(Path::new("."));
+                let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+                modelfile.resolve_paths(dir);
+                modelfile
+            }
+            _ => {
+                return Err(Error::Message(
+                    "provide one of --modelfile or --file".to_string(),
+                ));
+            }
+        };
+        let mut request = CreateRequest::new(&self.name, modelfile.to_string());
+        request.quantize.clone_from(&self.quantize);
+        Ok(request)
+    }
+}
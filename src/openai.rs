@@ -0,0 +1,430 @@
+//! Translation between the ollama wire format and the OpenAI-compatible wire format.
+//!
+//! Servers like llama.cpp, vLLM, and ollama's own `/v1` shim speak the OpenAI protocol:  requests
+//! go to `/v1/chat/completions` and friends and streams arrive as server-sent events.  This module
+//! converts yammer's requests into that shape and maps the responses back into the shape ollama
+//! would have returned so that every [Accumulator](crate::Accumulator) works unchanged.
+//!
+//! OpenAI reports token counts but not durations, and generate responses carry no context, so the
+//! counts map to `prompt_eval_count` and `eval_count`, [Responses] times the request itself, and
+//! there is no context to continue a generate session from.
+
+use std::time::Instant;
+
+use super::{timestamp, timestamp_now, ChatMessage, ChatRequest, GenerateRequest};
+
+/////////////////////////////////////////////// path ///////////////////////////////////////////////
+
+/// The path of the OpenAI endpoint that corresponds to the ollama `api`.
+pub(crate) fn path(api: &str) -> Option<&'static str> {
+    match api {
+        "chat" => Some("v1/chat/completions"),
+        "generate" => Some("v1/completions"),
+        "embed" => Some("v1/embeddings"),
+        "tags" => Some("v1/models"),
+        _ => None,
+    }
+}
+
+///////////////////////////////////////////// requests /////////////////////////////////////////////
+
+/// Convert a ChatRequest into an OpenAI chat completion request.
+pub fn chat_request(chat: &ChatRequest) -> serde_json::Value {
+    let mut req = serde_json::json!({
+        "model": chat.model,
+        "messages": messages(&chat.messages),
+        "stream": chat.stream.unwrap_or(true),
+    });
+    include_usage(&mut req);
+    if let Some(tools) = chat.tools.as_ref() {
+        req["tools"] = tools.clone();
+    }
+    if chat.format.as_deref() == Some("json") {
+        req["response_format"] = serde_json::json!({ "type": "json_object" });
+    }
+    apply_options(&mut req, chat.options.as_ref());
+    req
+}
+
+/// Convert chat messages into OpenAI messages, with images as data URLs.
+///
+/// Tool calls recorded in ollama's shape gain the id and type OpenAI requires, and their
+/// arguments are encoded as a string of JSON.
+pub fn messages(messages: &[ChatMessage]) -> Vec<serde_json::Value> {
+    messages
+        .iter()
+        .enumerate()
+        .map(|(msg_idx, msg)| {
+            let mut obj = serde_json::json!({ "role": msg.role });
+            match msg.images.as_ref() {
+                Some(images) if !images.is_empty() => {
+                    let mut parts =
+                        vec![serde_json::json!({ "type": "text", "text": msg.content })];
+                    for image in images {
+                        parts.push(serde_json::json!({
+                            "type": "image_url",
+                            "image_url": { "url": format!("data:image/png;base64,{image}") },
+                        }));
+                    }
+                    obj["content"] = serde_json::Value::Array(parts);
+                }
+                _ => {
+                    obj["content"] = serde_json::Value::String(msg.content.clone());
+                }
+            }
+            if let Some(tool_calls) = msg.tool_calls.as_ref() {
+                obj["tool_calls"] = tool_calls
+                    .iter()
+                    .enumerate()
+                    .map(|(call_idx, call)| tool_call(msg_idx, call_idx, call))
+                    .collect();
+            }
+            obj
+        })
+        .collect()
+}
+
+/// Convert one tool call to OpenAI's shape.  Calls already in that shape pass through.
+fn tool_call(msg_idx: usize, call_idx: usize, call: &serde_json::Value) -> serde_json::Value {
+    let function = call.get("function").unwrap_or(call);
+    let name = function
+        .get("name")
+        .cloned()
+        .unwrap_or(serde_json::Value::Null);
+    let arguments = match function.get("arguments") {
+        Some(serde_json::Value::String(arguments)) => arguments.clone(),
+        Some(arguments) => arguments.to_string(),
+        None => "{}".to_string(),
+    };
+    let id = call
+        .get("id")
+        .and_then(|id| id.as_str())
+        .map(String::from)
+        .unwrap_or_else(|| format!("call_{msg_idx}_{call_idx}"));
+    serde_json::json!({
+        "id": id,
+        "type": "function",
+        "function": { "name": name, "arguments": arguments },
+    })
+}
+
+/// Convert a GenerateRequest into an OpenAI (legacy) completion request.
+///
+/// The completions endpoint has no notion of images, templates, or system prompts, so those
+/// fields are dropped.
+pub fn generate_request(generate: &GenerateRequest) -> serde_json::Value {
+    let mut req = serde_json::json!({
+        "model": generate.model,
+        "prompt": generate.prompt,
+        "stream": generate.stream.unwrap_or(true),
+    });
+    include_usage(&mut req);
+    if !generate.suffix.is_empty() {
+        req["suffix"] = serde_json::Value::String(generate.suffix.clone());
+    }
+    if generate.format.as_deref() == Some("json") {
+        req["response_format"] = serde_json::json!({ "type": "json_object" });
+    }
+    apply_options(&mut req, generate.options.as_ref());
+    req
+}
+
+/// Ask a stream to end with its token counts, which it otherwise omits.
+fn include_usage(req: &mut serde_json::Value) {
+    if req["stream"] == serde_json::Value::Bool(true) {
+        req["stream_options"] = serde_json::json!({ "include_usage": true });
+    }
+}
+
+/// Carry over the ollama model options that have an OpenAI equivalent.
+fn apply_options(req: &mut serde_json::Value, options: Option<&serde_json::Value>) {
+    let Some(serde_json::Value::Object(options)) = options else {
+        return;
+    };
+    for (ollama, openai) in [
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("seed", "seed"),
+        ("stop", "stop"),
+        ("num_predict", "max_tokens"),
+        ("frequency_penalty", "frequency_penalty"),
+        ("presence_penalty", "presence_penalty"),
+    ] {
+        if let Some(value) = options.get(ollama) {
+            req[openai] = value.clone();
+        }
+    }
+}
+
+///////////////////////////////////////////// responses ////////////////////////////////////////////
+
+/// Map an OpenAI response or stream chunk for `api` back into the shape ollama would return.
+///
+/// Returns None when the message carries nothing the ollama format can represent.  Tool calls
+/// are converted only when complete; the fragments a stream sends are for [Responses] to gather.
+pub fn response(api: &str, message: serde_json::Value) -> Option<serde_json::Value> {
+    match api {
+        "chat" => {
+            let model = message.get("model").cloned().unwrap_or_default();
+            let created_at = created_at(&message);
+            let choice = message.get("choices")?.get(0)?;
+            let done = !choice
+                .get("finish_reason")
+                .unwrap_or(&serde_json::Value::Null)
+                .is_null();
+            let body = choice.get("delta").or_else(|| choice.get("message"))?;
+            let mut msg = serde_json::json!({
+                "role": "assistant",
+                "content": body.get("content").and_then(|c| c.as_str()).unwrap_or(""),
+            });
+            if let Some(serde_json::Value::Array(tool_calls)) =
+                choice.pointer("/message/tool_calls")
+            {
+                msg["tool_calls"] = tool_calls
+                    .iter()
+                    .map(|call| {
+                        let text = |key: &str| call.pointer(key).and_then(|v| v.as_str());
+                        ollama_tool_call(
+                            text("/id"),
+                            text("/function/name").unwrap_or(""),
+                            text("/function/arguments").unwrap_or(""),
+                        )
+                    })
+                    .collect();
+            }
+            let mut resp = serde_json::json!({
+                "model": model,
+                "created_at": created_at,
+                "message": msg,
+                "done": done,
+            });
+            counts(&mut resp, message.get("usage"));
+            Some(resp)
+        }
+        "generate" => {
+            let model = message.get("model").cloned().unwrap_or_default();
+            let created_at = created_at(&message);
+            let choice = message.get("choices")?.get(0)?;
+            let done = !choice
+                .get("finish_reason")
+                .unwrap_or(&serde_json::Value::Null)
+                .is_null();
+            let text = choice.get("text").and_then(|t| t.as_str()).unwrap_or("");
+            let mut resp = serde_json::json!({
+                "model": model,
+                "created_at": created_at,
+                "response": text,
+                "done": done,
+            });
+            counts(&mut resp, message.get("usage"));
+            Some(resp)
+        }
+        "embed" => {
+            let model = message.get("model").cloned().unwrap_or_default();
+            let embeddings = message
+                .get("data")?
+                .as_array()?
+                .iter()
+                .flat_map(|d| d.get("embedding").cloned())
+                .collect::<Vec<_>>();
+            Some(serde_json::json!({
+                "model": model,
+                "embeddings": embeddings,
+            }))
+        }
+        "tags" => {
+            let models = message
+                .get("data")?
+                .as_array()?
+                .iter()
+                .flat_map(|m| m.get("id").cloned())
+                .map(|id| serde_json::json!({ "name": id, "model": id }))
+                .collect::<Vec<_>>();
+            Some(serde_json::json!({ "models": models }))
+        }
+        _ => Some(message),
+    }
+}
+
+/// Copy the token counts of an OpenAI `usage` object onto `resp` as ollama names them.
+fn counts(resp: &mut serde_json::Value, usage: Option<&serde_json::Value>) {
+    let Some(usage) = usage.filter(|u| u.is_object()) else {
+        return;
+    };
+    for (openai, ollama) in [
+        ("prompt_tokens", "prompt_eval_count"),
+        ("completion_tokens", "eval_count"),
+    ] {
+        if let Some(count) = usage.get(openai).filter(|c| c.is_u64()) {
+            resp[ollama] = count.clone();
+        }
+    }
+}
+
+/// A tool call in ollama's shape, with the arguments decoded when they are JSON.
+fn ollama_tool_call(id: Option<&str>, name: &str, arguments: &str) -> serde_json::Value {
+    let arguments = serde_json::from_str(arguments)
+        .unwrap_or_else(|_| serde_json::Value::String(arguments.to_string()));
+    let mut call = serde_json::json!({ "function": { "name": name, "arguments": arguments } });
+    if let Some(id) = id {
+        call["id"] = serde_json::Value::String(id.to_string());
+    }
+    call
+}
+
+fn created_at(message: &serde_json::Value) -> String {
+    match message.get("created").and_then(|c| c.as_u64()) {
+        Some(created) => timestamp(created),
+        None => timestamp_now(),
+    }
+}
+
+///////////////////////////////////////////// Responses ////////////////////////////////////////////
+
+/// The fragments of one streamed tool call.
+#[derive(Debug, Default)]
+struct ToolCall {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+/// Converts the responses to one request, gathering what a stream spreads across its chunks.
+///
+/// A stream sends tool calls in fragments, keyed by index, and its token counts after its final
+/// choice.  The final message is therefore held until [finish](Self::finish), when it gains the
+/// assembled tool calls, the counts, and durations timed from when the converter was made:
+/// prompt evaluation until the first token, and evaluation from then on.
+#[derive(Debug)]
+pub struct Responses {
+    api: String,
+    started: Instant,
+    first_token: Option<Instant>,
+    tool_calls: Vec<ToolCall>,
+    usage: Option<serde_json::Value>,
+    last: Option<serde_json::Value>,
+}
+
+impl Responses {
+    /// Convert the responses to a request for `api` that is about to be sent.
+    pub fn new(api: impl Into<String>) -> Self {
+        Self {
+            api: api.into(),
+            started: Instant::now(),
+            first_token: None,
+            tool_calls: vec![],
+            usage: None,
+            last: None,
+        }
+    }
+
+    /// Convert one response or stream chunk, returning the message to pass on now, if any.
+    pub fn push(&mut self, message: serde_json::Value) -> Option<serde_json::Value> {
+        if let Some(usage) = message.get("usage").filter(|u| u.is_object()) {
+            self.usage = Some(usage.clone());
+        }
+        if let Some(serde_json::Value::Array(calls)) =
+            message.pointer("/choices/0/delta/tool_calls")
+        {
+            for call in calls {
+                self.gather(call);
+            }
+        }
+        let resp = response(&self.api, message)?;
+        let text = resp
+            .pointer("/message/content")
+            .or_else(|| resp.get("response"))
+            .and_then(|t| t.as_str());
+        if self.first_token.is_none() && text.is_some_and(|t| !t.is_empty()) {
+            self.first_token = Some(Instant::now());
+        }
+        if resp.get("done").and_then(|d| d.as_bool()) == Some(true) {
+            self.last = Some(resp);
+            return None;
+        }
+        Some(resp)
+    }
+
+    /// The final message, if the response had one, complete with everything gathered.
+    pub fn finish(&mut self) -> Option<serde_json::Value> {
+        let mut last = self.last.take()?;
+        counts(&mut last, self.usage.as_ref());
+        if !self.tool_calls.is_empty() && last.get("message").is_some() {
+            last["message"]["tool_calls"] = self
+                .tool_calls
+                .drain(..)
+                .map(|call| ollama_tool_call(call.id.as_deref(), &call.name, &call.arguments))
+                .collect();
+        }
+        let now = Instant::now();
+        let first_token = self.first_token.unwrap_or(now);
+        let nanos = |d: std::time::Duration| serde_json::Value::from(d.as_nanos() as u64);
+        last["total_duration"] = nanos(now - self.started);
+        last["prompt_eval_duration"] = nanos(first_token - self.started);
+        last["eval_duration"] = nanos(now - first_token);
+        Some(last)
+    }
+
+    fn gather(&mut self, fragment: &serde_json::Value) {
+        let index = fragment
+            .get("index")
+            .and_then(|i| i.as_u64())
+            .map_or(self.tool_calls.len(), |i| i as usize);
+        if self.tool_calls.len() <= index {
+            self.tool_calls.resize_with(index + 1, ToolCall::default);
+        }
+        let call = &mut self.tool_calls[index];
+        let text = |key: &str| fragment.pointer(key).and_then(|v| v.as_str());
+        if let Some(id) = text("/id") {
+            call.id = Some(id.to_string());
+        }
+        call.name.push_str(text("/function/name").unwrap_or(""));
+        call.arguments
+            .push_str(text("/function/arguments").unwrap_or(""));
+    }
+}
+
+//////////////////////////////////////////// SseDecoder ////////////////////////////////////////////
+
+/// Incrementally decode a server-sent event stream into the payloads of its `data:` frames.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: String,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    /// Feed the next chunk of the stream and return every event that it completes.
+    pub fn push(&mut self, chunk: &str) -> Vec<String> {
+        self.buffer.push_str(chunk);
+        let mut events = vec![];
+        while let Some(newline) = self.buffer.find('\n') {
+            let line = self.buffer[..newline].trim_end_matches('\r').to_string();
+            self.buffer.drain(..=newline);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(data) = line.strip_prefix("data:") {
+                self.data
+                    .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+            }
+        }
+        events
+    }
+
+    /// Flush any event that was not terminated by a blank line before the stream ended.
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        if let Some(data) = rest.trim_end_matches('\r').strip_prefix("data:") {
+            self.data
+                .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+        }
+        if self.data.is_empty() {
+            None
+        } else {
+            Some(std::mem::take(&mut self.data).join("\n"))
+        }
+    }
+}
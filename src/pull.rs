@@ -0,0 +1,361 @@
+//! Pulling models with readable progress.
+//!
+//! ollama reports a pull as a stream of status messages, one per chunk of every layer.
+//! [PullProgressAccumulator] condenses that stream into one progress bar per layer on a terminal,
+//! or a summary every few seconds otherwise.  ollama keeps partially downloaded layers, so a pull
+//! that is interrupted resumes where it left off when run again.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use super::{Error, Request, RequestOptions, VecAccumulator};
+
+/// How often to redraw the progress bars on a terminal.
+const REDRAW: Duration = Duration::from_millis(100);
+
+/// How often to summarize progress when not on a terminal.
+const SUMMARY: Duration = Duration::from_secs(5);
+
+/// The width of a progress bar in characters.
+const BAR: usize = 30;
+
+//////////////////////////////////////////// PullOptions ///////////////////////////////////////////
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct PullOptions {
+    #[arrrg(
+        optional,
+        "The name of the ollama model to pull.  More models may follow as arguments."
+    )]
+    pub model: Option<String>,
+    #[arrrg(flag, "Skip models that are already present on the server.")]
+    pub ensure: bool,
+}
+
+impl PullOptions {
+    /// The models named by `--model` and by `free`, in order.
+    pub fn models(&self, free: &[String]) -> Vec<String> {
+        self.model.iter().chain(free.iter()).cloned().collect()
+    }
+}
+
+///////////////////////////////////////// installed models /////////////////////////////////////////
+
+/// The names of the models present on the server, according to `/api/tags`.
+pub async fn installed(options: RequestOptions) -> Result<Vec<String>, Error> {
+    Ok(installed_digests(options)
+        .await?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect())
+}
+
+/// The names and digests of the models present on the server, according to `/api/tags`.
+pub async fn installed_digests(options: RequestOptions) -> Result<Vec<(String, String)>, Error> {
+    let mut tags = vec![];
+    Request::tags(options)?
+        .accumulate(&mut VecAccumulator::new(&mut tags))
+        .await?;
+    Ok(tags
+        .iter()
+        .filter_map(|t| t.get("models").and_then(|m| m.as_array()))
+        .flatten()
+        .filter_map(|m| {
+            let name = m.get("name").or_else(|| m.get("model"))?.as_str()?;
+            let digest = m.get("digest").and_then(|d| d.as_str()).unwrap_or_default();
+            Some((name.to_string(), digest.to_string()))
+        })
+        .collect())
+}
+
+/// True if `model` is among `installed`.  A model without a tag means `latest`.
+pub fn is_installed(model: &str, installed: &[String]) -> bool {
+    let canonical = |name: &str| {
+        if name.rsplit('/').next().unwrap_or(name).contains(':') {
+            name.to_string()
+        } else {
+            format!("{name}:latest")
+        }
+    };
+    let model = canonical(model);
+    installed.iter().any(|i| canonical(i) == model)
+}
+
+////////////////////////////////////// PullProgressAccumulator /////////////////////////////////////
+
+#[derive(Debug)]
+enum Line {
+    Status(String),
+    Layer(Layer),
+}
+
+#[derive(Debug)]
+struct Layer {
+    digest: String,
+    total: u64,
+    completed: u64,
+    initial: u64,
+    started: Instant,
+    finished: Option<Instant>,
+    reported_done: bool,
+}
+
+impl Layer {
+    fn name(&self) -> &str {
+        let digest = self.digest.strip_prefix("sha256:").unwrap_or(&self.digest);
+        &digest[..digest.len().min(12)]
+    }
+
+    fn done(&self) -> bool {
+        self.total > 0 && self.completed >= self.total
+    }
+
+    /// Bytes per second since this pull started fetching the layer.
+    fn rate(&self) -> f64 {
+        let end = self.finished.unwrap_or_else(Instant::now);
+        let elapsed = end.duration_since(self.started).as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        self.completed.saturating_sub(self.initial) as f64 / elapsed
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        let rate = self.rate();
+        if rate <= 0.0 || self.done() {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            self.total.saturating_sub(self.completed) as f64 / rate,
+        ))
+    }
+
+    fn percent(&self) -> u64 {
+        (self.completed * 100).checked_div(self.total).unwrap_or(0)
+    }
+
+    fn bar(&self) -> String {
+        let filled = (self.completed as usize)
+            .saturating_mul(BAR)
+            .checked_div(self.total as usize)
+            .unwrap_or(0)
+            .min(BAR);
+        let mut bar = "=".repeat(filled);
+        if filled < BAR {
+            bar.push('>');
+            bar += &" ".repeat(BAR - filled - 1);
+        }
+        format!(
+            "pulling {} {:>3}% [{bar}] {}/{} {}/s",
+            self.name(),
+            self.percent(),
+            bytes(self.completed),
+            bytes(self.total),
+            bytes(self.rate() as u64),
+        ) + &match self.eta() {
+            Some(eta) => format!(" ETA {}", duration(eta)),
+            None => String::new(),
+        }
+    }
+
+    fn summary(&self) -> String {
+        if self.done() {
+            return format!("pulled {} ({})", self.name(), bytes(self.total));
+        }
+        let mut summary = format!(
+            "pulling {}: {}% ({} of {}, {}/s",
+            self.name(),
+            self.percent(),
+            bytes(self.completed),
+            bytes(self.total),
+            bytes(self.rate() as u64),
+        );
+        if let Some(eta) = self.eta() {
+            summary += &format!(", ETA {}", duration(eta));
+        }
+        summary + ")"
+    }
+}
+
+/// PullProgressAccumulator renders the progress of a pull.
+///
+/// On a terminal, each layer gets a progress bar with bytes, total, rate, and ETA that is redrawn
+/// in place.  Otherwise, status changes are printed as they happen and the progress of each layer
+/// is summarized every few seconds.
+#[derive(Debug)]
+pub struct PullProgressAccumulator<W: Write + std::fmt::Debug> {
+    output: W,
+    tty: bool,
+    lines: Vec<Line>,
+    drawn: usize,
+    last_draw: Option<Instant>,
+    last_summary: Instant,
+}
+
+impl<W: Write + std::fmt::Debug> PullProgressAccumulator<W> {
+    /// Render progress to `output`, drawing bars if `tty`.
+    pub fn new(output: W, tty: bool) -> Self {
+        Self {
+            output,
+            tty,
+            lines: vec![],
+            drawn: 0,
+            last_draw: None,
+            last_summary: Instant::now(),
+        }
+    }
+
+    /// Draw the final state of the progress bars.
+    pub fn finish(&mut self) {
+        if self.tty {
+            self.draw();
+        }
+    }
+
+    fn layer(&mut self, digest: &str) -> Option<&mut Layer> {
+        self.lines.iter_mut().find_map(|line| match line {
+            Line::Layer(layer) if layer.digest == digest => Some(layer),
+            _ => None,
+        })
+    }
+
+    fn draw(&mut self) {
+        let mut out = String::new();
+        if self.drawn > 0 {
+            out += &format!("\x1b[{}F", self.drawn);
+        }
+        for line in self.lines.iter() {
+            let text = match line {
+                Line::Status(status) => status.clone(),
+                Line::Layer(layer) => layer.bar(),
+            };
+            out += &format!("\x1b[2K{text}\n");
+        }
+        let _ = write!(self.output, "{out}");
+        let _ = self.output.flush();
+        self.drawn = self.lines.len();
+        self.last_draw = Some(Instant::now());
+    }
+
+    fn summarize(&mut self) {
+        let mut out = String::new();
+        for line in self.lines.iter_mut() {
+            if let Line::Layer(layer) = line {
+                if !layer.reported_done {
+                    out += &layer.summary();
+                    out += "\n";
+                    layer.reported_done = layer.done();
+                }
+            }
+        }
+        let _ = write!(self.output, "{out}");
+        let _ = self.output.flush();
+        self.last_summary = Instant::now();
+    }
+}
+
+impl<W: Write + std::fmt::Debug> super::Accumulator for PullProgressAccumulator<W> {
+    fn accumulate(&mut self, message: serde_json::Value) -> std::ops::ControlFlow<()> {
+        let status = message
+            .get("status")
+            .and_then(|s| s.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let count = |key: &str| message.get(key).and_then(|c| c.as_u64());
+        let mut changed = false;
+        match message.get("digest").and_then(|d| d.as_str()) {
+            Some(digest) => {
+                let completed = count("completed").unwrap_or(0);
+                let total = count("total").unwrap_or(0);
+                if let Some(layer) = self.layer(digest) {
+                    let was_done = layer.done();
+                    layer.completed = layer.completed.max(completed);
+                    layer.total = layer.total.max(total);
+                    if layer.done() && !was_done {
+                        layer.finished = Some(Instant::now());
+                        changed = true;
+                    }
+                } else {
+                    let layer = Layer {
+                        digest: digest.to_string(),
+                        total,
+                        completed,
+                        initial: completed,
+                        started: Instant::now(),
+                        finished: None,
+                        reported_done: false,
+                    };
+                    // NOTE(rescrv):  ollama picks up a partial layer where the last pull stopped.
+                    if !self.tty && completed > 0 {
+                        let _ = writeln!(
+                            self.output,
+                            "resuming {} at {} of {}",
+                            layer.name(),
+                            bytes(completed),
+                            bytes(total)
+                        );
+                    }
+                    self.lines.push(Line::Layer(layer));
+                    changed = true;
+                }
+            }
+            None if !status.is_empty() => {
+                if let Some(Line::Status(last)) = self.lines.last() {
+                    if *last == status {
+                        return std::ops::ControlFlow::Continue(());
+                    }
+                }
+                if !self.tty {
+                    // NOTE(rescrv):  Layers finishing between summaries would go unreported.
+                    self.summarize();
+                    let _ = writeln!(self.output, "{status}");
+                }
+                self.lines.push(Line::Status(status));
+                changed = true;
+            }
+            None => {}
+        }
+        if self.tty {
+            if changed || self.last_draw.is_none_or(|d| d.elapsed() >= REDRAW) {
+                self.draw();
+            }
+        } else if self.last_summary.elapsed() >= SUMMARY {
+            self.summarize();
+        }
+        std::ops::ControlFlow::Continue(())
+    }
+}
+
+impl<W: Write + std::fmt::Debug> Drop for PullProgressAccumulator<W> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Format a byte count for people, e.g., 1.5 GiB.
+fn bytes(count: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = count as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{count} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Format a duration for people, e.g., 1h02m or 3m04s.
+fn duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
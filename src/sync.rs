@@ -0,0 +1,396 @@
+//! Declarative model sync:  make a server hold the models a manifest lists.
+//!
+//! A manifest is TOML.  It lists models to pull, optionally pinned to a digest, and custom models
+//! to create from a Modelfile:
+//!
+//! ```toml
+//! pull = ["llama3.1:8b", { name = "nomic-embed-text", digest = "0a109f422b47" }]
+//! prune = false
+//!
+//! [[create]]
+//! name = "terse"
+//! file = "terse.Modelfile"
+//! ```
+//!
+//! [plan] compares the manifest to `/api/tags` and returns the actions that would bring the server
+//! in line:  pulling models that are missing or whose digest differs from the pin, creating custom
+//! models that are missing or whose Modelfile changed, and, when pruning, deleting models the
+//! manifest does not list.  [apply] carries the actions out.
+//!
+//! A custom model is current when every parameter, template, system prompt, message, and license
+//! its Modelfile sets matches the model on the server, and when it was built from the same blob as
+//! the model its Modelfile names in FROM.  Paths in a manifest, including those in FROM and
+//! ADAPTER of an inline Modelfile, are relative to the manifest.
+//!
+//! A pinned model is pulled only when the registry serves it at the pinned digest.  [apply] reads
+//! the manifest from the registry before pulling and fails the sync, leaving the installed model
+//! alone, when its digest is not the pin.  A pinned model installed at its pin is never pulled.
+
+use std::io::IsTerminal;
+use std::path::Path;
+
+use super::modelfile::{CreateOptions, Modelfile};
+use super::pull::{installed_digests, is_installed, PullProgressAccumulator};
+use super::{
+    CreateRequest, DeleteRequest, Error, PullRequest, Request, RequestOptions, VecAccumulator,
+};
+
+///////////////////////////////////////////// Manifest /////////////////////////////////////////////
+
+/// A model to pull, by name or by name and digest.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize)]
+#[serde(untagged)]
+pub enum PullEntry {
+    Name(String),
+    Pinned { name: String, digest: String },
+}
+
+impl PullEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Name(name) => name,
+            Self::Pinned { name, .. } => name,
+        }
+    }
+
+    pub fn digest(&self) -> Option<&str> {
+        match self {
+            Self::Name(_) => None,
+            Self::Pinned { digest, .. } => Some(digest),
+        }
+    }
+}
+
+/// The models a server should hold.
+#[derive(Clone, Debug, Default, Eq, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Models to pull from the registry.
+    #[serde(default)]
+    pub pull: Vec<PullEntry>,
+    /// Custom models to create from a Modelfile.
+    #[serde(default)]
+    pub create: Vec<CreateOptions>,
+    /// Delete models the manifest does not list.
+    #[serde(default)]
+    pub prune: bool,
+}
+
+impl Manifest {
+    /// Read the manifest at `path`, resolving the paths in it relative to the manifest.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let mut manifest: Manifest = toml::from_str(&content)
+            .map_err(|err| Error::Message(format!("{}: {err}", path.display())))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let absolute = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+        for create in manifest.create.iter_mut() {
+            if let Some(file) = create.file.as_mut() {
+                *file = dir.join(&*file).display().to_string();
+            }
+            // NOTE(rescrv):  A Modelfile that does not parse is reported when it is created.
+            if let Some(modelfile) = create.modelfile.as_mut() {
+                if let Ok(mut parsed) = modelfile.parse::<Modelfile>() {
+                    parsed.resolve_paths(&absolute);
+                    *modelfile = parsed.to_string();
+                }
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+//////////////////////////////////////////// SyncOptions ///////////////////////////////////////////
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, arrrg_derive::CommandLine)]
+pub struct SyncOptions {
+    #[arrrg(flag, "Print what would change without changing anything.")]
+    pub dry_run: bool,
+    #[arrrg(flag, "Delete models the manifest does not list.")]
+    pub prune: bool,
+    #[arrrg(
+        flag,
+        "Pull every listed model, even those present, to pick up new versions."
+    )]
+    pub update: bool,
+}
+
+////////////////////////////////////////////// Action //////////////////////////////////////////////
+
+/// One step toward the state a manifest describes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Action {
+    Pull {
+        name: String,
+        /// The digest the manifest pins, if any, to check the registry against.
+        digest: Option<String>,
+        reason: String,
+    },
+    Create {
+        request: CreateRequest,
+        reason: String,
+    },
+    Delete {
+        name: String,
+    },
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pull { name, reason, .. } => write!(f, "pull {name} ({reason})"),
+            Self::Create { request, reason } => write!(f, "create {} ({reason})", request.name),
+            Self::Delete { name } => write!(f, "delete {name} (not in manifest)"),
+        }
+    }
+}
+
+/////////////////////////////////////////////// plan ///////////////////////////////////////////////
+
+/// The actions that bring the server in line with `manifest`:  pulls, then creates, then deletes.
+pub async fn plan(
+    options: RequestOptions,
+    manifest: &Manifest,
+    sync: &SyncOptions,
+) -> Result<Vec<Action>, Error> {
+    let installed = installed_digests(options.clone()).await?;
+    let names = installed.iter().map(|(n, _)| n.clone()).collect::<Vec<_>>();
+    let mut actions = vec![];
+    for entry in manifest.pull.iter() {
+        let name = entry.name();
+        let present = installed
+            .iter()
+            .find(|(n, _)| is_installed(name, std::slice::from_ref(n)));
+        let reason = match (present, entry.digest()) {
+            (None, _) => Some("missing".to_string()),
+            (Some((_, have)), Some(want)) if same_digest(have, want) => None,
+            (Some((_, have)), Some(want)) => Some(format!(
+                "digest {} is not the pinned {}",
+                short(have),
+                short(want)
+            )),
+            (Some(_), _) if sync.update => Some("update".to_string()),
+            (Some(_), _) => None,
+        };
+        if let Some(reason) = reason {
+            actions.push(Action::Pull {
+                name: name.to_string(),
+                digest: entry.digest().map(String::from),
+                reason,
+            });
+        }
+    }
+    for create in manifest.create.iter() {
+        let request = create.request()?;
+        let reason = if !is_installed(&create.name, &names) {
+            Some("missing".to_string())
+        } else {
+            let desired = request.modelfile.parse::<Modelfile>()?;
+            stale(options.clone(), &create.name, &desired, &names).await?
+        };
+        if let Some(reason) = reason {
+            actions.push(Action::Create { request, reason });
+        }
+    }
+    if manifest.prune || sync.prune {
+        let listed = manifest
+            .pull
+            .iter()
+            .map(|p| p.name().to_string())
+            .chain(manifest.create.iter().map(|c| c.name.clone()))
+            .collect::<Vec<_>>();
+        for name in names.iter() {
+            if !is_installed(name, &listed) {
+                actions.push(Action::Delete { name: name.clone() });
+            }
+        }
+    }
+    Ok(actions)
+}
+
+/// Why the custom model `name` differs from `desired`, or None if it is current.
+async fn stale(
+    options: RequestOptions,
+    name: &str,
+    desired: &Modelfile,
+    installed: &[String],
+) -> Result<Option<String>, Error> {
+    let actual = shown_modelfile(options.clone(), name).await?;
+    let mut reasons = vec![];
+    let mut params: Vec<&str> = vec![];
+    for (param, _) in desired.parameters.iter() {
+        if !params.contains(&param.as_str()) {
+            params.push(param);
+        }
+    }
+    for param in params {
+        if desired.parameter(param) != actual.parameter(param) {
+            reasons.push(format!("parameter {param}"));
+        }
+    }
+    if desired.template.is_some() && desired.template != actual.template {
+        reasons.push("template".to_string());
+    }
+    if desired.system.is_some() && desired.system != actual.system {
+        reasons.push("system".to_string());
+    }
+    if !desired.messages.is_empty() && desired.messages != actual.messages {
+        reasons.push("messages".to_string());
+    }
+    if !desired.licenses.is_empty() && desired.licenses != actual.licenses {
+        reasons.push("license".to_string());
+    }
+    // NOTE(rescrv):  FROM names a model or a file.  Only a model can be compared by digest.
+    let base = &desired.from;
+    if !base.starts_with(['/', '.', '~']) {
+        if is_installed(base, installed) {
+            let base_mf = shown_modelfile(options, base).await?;
+            if super::diff::digest(&base_mf.from) != super::diff::digest(&actual.from) {
+                reasons.push(format!("base {base} changed"));
+            }
+        } else {
+            reasons.push(format!("base {base} missing"));
+        }
+    }
+    if reasons.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(format!("{} differ", reasons.join(", "))))
+    }
+}
+
+async fn shown_modelfile(options: RequestOptions, name: &str) -> Result<Modelfile, Error> {
+    let shown = super::diff::show(options, name).await?;
+    shown
+        .get("modelfile")
+        .and_then(|m| m.as_str())
+        .unwrap_or_default()
+        .parse::<Modelfile>()
+        .map_err(|err| Error::Message(format!("{name}: {err}")))
+}
+
+fn same_digest(have: &str, want: &str) -> bool {
+    let have = have.strip_prefix("sha256:").unwrap_or(have);
+    let want = want.strip_prefix("sha256:").unwrap_or(want);
+    !want.is_empty() && have.starts_with(want)
+}
+
+fn short(digest: &str) -> &str {
+    let digest = digest.strip_prefix("sha256:").unwrap_or(digest);
+    &digest[..digest.len().min(12)]
+}
+
+///////////////////////////////////////////// registry /////////////////////////////////////////////
+
+/// The URL of the registry manifest for `name`.
+///
+/// Names without a host are on registry.ollama.ai, and names without a namespace are in library,
+/// so `llama3` is `https://registry.ollama.ai/v2/library/llama3/manifests/latest`.
+pub fn manifest_url(name: &str) -> String {
+    let (repository, tag) = match name.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => (repository, tag),
+        _ => (name, "latest"),
+    };
+    let mut parts = repository.split('/').collect::<Vec<_>>();
+    let is_host = |part: &str| part.contains('.') || part.contains(':') || part == "localhost";
+    let host = if parts.len() > 1 && is_host(parts[0]) {
+        parts.remove(0)
+    } else {
+        "registry.ollama.ai"
+    };
+    if parts.len() == 1 {
+        parts.insert(0, "library");
+    }
+    format!("https://{host}/v2/{}/manifests/{tag}", parts.join("/"))
+}
+
+/// The digest the registry serves `name` at:  the sha256 of its manifest, as ollama reports it.
+async fn remote_digest(name: &str) -> Result<String, Error> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()?;
+    let response = client
+        .get(manifest_url(name))
+        .header(
+            reqwest::header::ACCEPT,
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(super::attach::sha256(&response.bytes().await?))
+}
+
+/// Check, before pulling, that the registry serves `name` at the pinned digest `want`.
+async fn check_pin(name: &str, want: &str) -> Result<(), Error> {
+    let have = remote_digest(name).await.map_err(|err| {
+        Error::Message(format!(
+            "{name}: cannot check the registry against the pin: {err}"
+        ))
+    })?;
+    if !same_digest(&have, want) {
+        return Err(Error::Message(format!(
+            "{name}: registry serves {}, manifest pins {}; not pulling",
+            short(&have),
+            short(want)
+        )));
+    }
+    Ok(())
+}
+
+/////////////////////////////////////////////// apply //////////////////////////////////////////////
+
+/// Check that the server now holds `name` at the pinned digest `want`.
+async fn verify(options: RequestOptions, name: &str, want: &str) -> Result<(), Error> {
+    let installed = installed_digests(options).await?;
+    let have = installed
+        .iter()
+        .find(|(n, _)| is_installed(name, std::slice::from_ref(n)))
+        .map(|(_, digest)| digest.as_str())
+        .ok_or_else(|| Error::Message(format!("{name}: not present after pulling")))?;
+    if !same_digest(have, want) {
+        return Err(Error::Message(format!(
+            "{name}: registry serves {}, manifest pins {}",
+            short(have),
+            short(want)
+        )));
+    }
+    Ok(())
+}
+
+/// Carry out `actions` in order, reporting progress on stdout.
+///
+/// A pinned pull checks the registry first and stops the sync without pulling when the registry
+/// serves another digest.  The server is checked against the pin again once the pull completes.
+pub async fn apply(options: RequestOptions, actions: Vec<Action>) -> Result<(), Error> {
+    let tty = std::io::stdout().is_terminal();
+    for action in actions {
+        println!("{action}");
+        match action {
+            Action::Pull { name, digest, .. } => {
+                if let Some(want) = digest.as_ref() {
+                    check_pin(&name, want).await?;
+                }
+                Request::pull(options.clone(), PullRequest::new(name.clone()))?
+                    .accumulate(&mut PullProgressAccumulator::new(std::io::stdout(), tty))
+                    .await?;
+                if let Some(want) = digest {
+                    verify(options.clone(), &name, &want).await?;
+                }
+            }
+            Action::Create { request, .. } => {
+                let mut statuses = vec![];
+                Request::create(options.clone(), request)?
+                    .accumulate(&mut VecAccumulator::new(&mut statuses))
+                    .await?;
+            }
+            Action::Delete { name } => {
+                Request::delete(options.clone(), DeleteRequest::new(name))?
+                    .accumulate(&mut VecAccumulator::new(&mut vec![]))
+                    .await?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! Transports carry requests to the server.
+//!
+//! Most servers listen on TCP and are reached with reqwest, optionally with a custom CA bundle or
+//! client certificate.  Servers bound to a Unix domain socket are reached with hyper over a
+//! `tokio::net::UnixStream`, because reqwest cannot dial a socket path.
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use hyper::body::HttpBody;
+use hyper::client::connect::{Connected, Connection};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::UnixStream;
+
+use super::Error;
+
+///////////////////////////////////////////// Transport ////////////////////////////////////////////
+
+/// The means by which a [Request](crate::Request) reaches the server.
+#[derive(Clone, Debug)]
+pub enum Transport {
+    /// HTTP or HTTPS over TCP.
+    Http(reqwest::Client),
+    /// HTTP over a Unix domain socket, pooling connections to it.
+    Unix(UnixClient),
+}
+
+/// A hyper client that dials a Unix domain socket.
+pub type UnixClient = hyper::Client<UnixConnector, hyper::Body>;
+
+impl Transport {
+    /// A client for the Unix domain socket at `path`.
+    pub(crate) fn unix_client(path: PathBuf) -> UnixClient {
+        hyper::Client::builder().build(UnixConnector { path })
+    }
+
+    pub(crate) async fn send(
+        &self,
+        method: reqwest::Method,
+        endpoint: String,
+        headers: reqwest::header::HeaderMap,
+        body: Option<String>,
+    ) -> Result<Response, Error> {
+        match self {
+            Self::Http(client) => {
+                let mut req = client.request(method, endpoint).headers(headers);
+                if let Some(body) = body {
+                    req = req.body(body);
+                }
+                Ok(Response::Http(req.send().await?))
+            }
+            Self::Unix(client) => {
+                let mut req = hyper::Request::builder().method(method).uri(endpoint);
+                for (name, value) in headers.iter() {
+                    req = req.header(name, value);
+                }
+                let req = req
+                    .body(
+                        body.map(hyper::Body::from)
+                            .unwrap_or_else(hyper::Body::empty),
+                    )
+                    .map_err(|err| Error::Message(format!("could not build request: {err}")))?;
+                Ok(Response::Unix(client.request(req).await?))
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////// Response /////////////////////////////////////////////
+
+/// A response from either transport.
+#[derive(Debug)]
+pub(crate) enum Response {
+    Http(reqwest::Response),
+    Unix(hyper::Response<hyper::Body>),
+}
+
+impl Response {
+    pub(crate) fn status(&self) -> u16 {
+        match self {
+            Self::Http(resp) => resp.status().as_u16(),
+            Self::Unix(resp) => resp.status().as_u16(),
+        }
+    }
+
+    pub(crate) fn content_type(&self) -> Option<&str> {
+        let headers = match self {
+            Self::Http(resp) => resp.headers(),
+            Self::Unix(resp) => resp.headers(),
+        };
+        headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+    }
+
+    pub(crate) async fn chunk(&mut self) -> Result<Option<bytes::Bytes>, Error> {
+        match self {
+            Self::Http(resp) => Ok(resp.chunk().await?),
+            Self::Unix(resp) => Ok(resp.body_mut().data().await.transpose()?),
+        }
+    }
+}
+
+/////////////////////////////////////////// UnixConnector //////////////////////////////////////////
+
+/// Dials the Unix domain socket at a path for every connection hyper makes.
+#[derive(Clone, Debug)]
+pub struct UnixConnector {
+    path: PathBuf,
+}
+
+impl hyper::service::Service<hyper::Uri> for UnixConnector {
+    type Response = UnixConnection;
+    type Error = std::io::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: hyper::Uri) -> Self::Future {
+        let path = self.path.clone();
+        Box::pin(async move { Ok(UnixConnection(UnixStream::connect(path).await?)) })
+    }
+}
+
+/// One connection to a Unix domain socket.
+pub struct UnixConnection(UnixStream);
+
+impl Connection for UnixConnection {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+impl AsyncRead for UnixConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use yammer::attach::{expand, sha256};
+
+fn tree() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for file in [
+        "a.rs",
+        "b.txt",
+        "src/lib.rs",
+        "src/deep/mod.rs",
+        "src/.secret.rs",
+        ".hidden/x.rs",
+    ] {
+        let path = dir.path().join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, file).unwrap();
+    }
+    dir
+}
+
+fn expanded(root: &Path, pattern: &str) -> Vec<PathBuf> {
+    let pattern = format!("{}/{pattern}", root.display());
+    expand(&pattern)
+        .unwrap()
+        .into_iter()
+        .map(|path| path.strip_prefix(root).unwrap().to_path_buf())
+        .collect()
+}
+
+fn paths(paths: &[&str]) -> Vec<PathBuf> {
+    paths.iter().map(PathBuf::from).collect()
+}
+
+////////////////////////////////////////////// sha256 //////////////////////////////////////////////
+
+#[test]
+fn sha256_known_answers() {
+    assert_eq!(
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        sha256(b"")
+    );
+    assert_eq!(
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        sha256(b"abc")
+    );
+    assert_eq!(
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")
+    );
+}
+
+////////////////////////////////////////////// expand //////////////////////////////////////////////
+
+#[test]
+fn star_stays_in_its_directory() {
+    let dir = tree();
+    assert_eq!(paths(&["a.rs"]), expanded(dir.path(), "*.rs"));
+}
+
+#[test]
+fn double_star_crosses_directories() {
+    let dir = tree();
+    assert_eq!(
+        paths(&["a.rs", "src/deep/mod.rs", "src/lib.rs"]),
+        expanded(dir.path(), "**/*.rs")
+    );
+    assert_eq!(
+        paths(&["src/deep/mod.rs", "src/lib.rs"]),
+        expanded(dir.path(), "src/**/*.rs")
+    );
+}
+
+#[test]
+fn question_mark_matches_one_character() {
+    let dir = tree();
+    assert_eq!(paths(&["src/lib.rs"]), expanded(dir.path(), "src/?ib.rs"));
+    assert!(expanded(dir.path(), "src/?lib.rs").is_empty());
+}
+
+#[test]
+fn directories_skip_hidden_files() {
+    let dir = tree();
+    assert_eq!(
+        paths(&["src/deep/mod.rs", "src/lib.rs"]),
+        expanded(dir.path(), "src")
+    );
+}
+
+#[test]
+fn missing_files_are_errors() {
+    let dir = tree();
+    let missing = dir.path().join("missing.rs");
+    assert!(expand(&missing.to_string_lossy()).is_err());
+    assert!(expanded(dir.path(), "*.missing").is_empty());
+}
@@ -0,0 +1,121 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use yammer::batch::{batch, completed, BatchOptions, BatchResult};
+use yammer::RequestOptions;
+
+/////////////////////////////////////////////// server /////////////////////////////////////////////
+
+/// Answer every generate request with its prompt, one connection per request.
+async fn echo(listener: tokio::net::TcpListener) {
+    loop {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = vec![];
+        let (header_end, length) = loop {
+            let mut chunk = [0u8; 4096];
+            let n = stream.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed before the request was read");
+            buf.extend_from_slice(&chunk[..n]);
+            if let Some(idx) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                let headers = String::from_utf8_lossy(&buf[..idx]).to_lowercase();
+                let length = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |value| value.trim().parse().unwrap());
+                break (idx + 4, length);
+            }
+        };
+        while buf.len() < header_end + length {
+            let mut chunk = [0u8; 4096];
+            let n = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        let request: serde_json::Value = serde_json::from_slice(&buf[header_end..]).unwrap();
+        let body = format!(
+            "{}\n",
+            serde_json::json!({"model": "test", "response": request["prompt"], "done": true})
+        );
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+    }
+}
+
+/////////////////////////////////////////////// resume /////////////////////////////////////////////
+
+#[test]
+fn completed_cuts_a_torn_tail() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("out.jsonl");
+    std::fs::write(
+        &output,
+        "{\"index\":0,\"response\":\"a\"}\n{\"index\":1,\"error\":\"refused\"}\n{\"index\":2,\"res",
+    )
+    .unwrap();
+    assert_eq!(
+        vec![0],
+        completed(&output).unwrap().into_iter().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        "{\"index\":0,\"response\":\"a\"}\n{\"index\":1,\"error\":\"refused\"}\n",
+        std::fs::read_to_string(&output).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn resume_after_a_torn_tail() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(echo(listener));
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("in.jsonl");
+    let output = dir.path().join("out.jsonl");
+    std::fs::write(
+        &input,
+        [0, 1, 2]
+            .iter()
+            .map(|idx| format!("{{\"model\":\"test\",\"prompt\":\"p{idx}\"}}\n"))
+            .collect::<String>(),
+    )
+    .unwrap();
+    std::fs::write(
+        &output,
+        "{\"index\":0,\"response\":\"p0\"}\n{\"index\":1,\"resp",
+    )
+    .unwrap();
+    let options = RequestOptions {
+        url: Some(format!("http://127.0.0.1:{port}")),
+        ..Default::default()
+    };
+    let batch_options = BatchOptions {
+        input: input.to_string_lossy().to_string(),
+        output: output.to_string_lossy().to_string(),
+        concurrency: 1,
+    };
+    assert_eq!(
+        0,
+        batch(options.clone(), batch_options.clone()).await.unwrap()
+    );
+    let results = std::fs::read_to_string(&output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<BatchResult>(line).unwrap())
+        .map(|result| (result.index, result.response.unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            (0, "p0".to_string()),
+            (1, "p1".to_string()),
+            (2, "p2".to_string())
+        ],
+        results
+    );
+    // NOTE(rescrv):  A second resume has nothing left to run.
+    let before = std::fs::read_to_string(&output).unwrap();
+    assert_eq!(0, batch(options, batch_options).await.unwrap());
+    assert_eq!(before, std::fs::read_to_string(&output).unwrap());
+    server.abort();
+}
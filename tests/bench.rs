@@ -0,0 +1,200 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use yammer::bench::{bench_model, Distribution};
+use yammer::{Error, RequestOptions};
+
+/////////////////////////////////////////////// server /////////////////////////////////////////////
+
+/// Answer generate requests like ollama, recording every prompt in `prompts`.
+///
+/// The empty prompt that loads a model reports only a load duration; every other prompt reports
+/// one token, 10 tokens generated in a second, and 4 prompt tokens processed in half a second.
+/// Requests for the model "broken" fail.
+async fn server(listener: tokio::net::TcpListener, prompts: Arc<Mutex<Vec<String>>>) {
+    loop {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = vec![];
+        let (header_end, length) = loop {
+            let mut chunk = [0u8; 4096];
+            let n = stream.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed before the request was read");
+            buf.extend_from_slice(&chunk[..n]);
+            if let Some(idx) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                let headers = String::from_utf8_lossy(&buf[..idx]).to_lowercase();
+                let length = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |value| value.trim().parse().unwrap());
+                break (idx + 4, length);
+            }
+        };
+        while buf.len() < header_end + length {
+            let mut chunk = [0u8; 4096];
+            let n = stream.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+        }
+        let request: serde_json::Value = serde_json::from_slice(&buf[header_end..]).unwrap();
+        let prompt = request["prompt"].as_str().unwrap_or_default().to_string();
+        prompts.lock().unwrap().push(prompt.clone());
+        let (status, body) = if request["model"] == "broken" {
+            (
+                "500 Internal Server Error",
+                "{\"error\":\"broken\"}".to_string(),
+            )
+        } else if prompt.is_empty() {
+            (
+                "200 OK",
+                format!(
+                    "{}\n",
+                    serde_json::json!({"model": "m", "response": "", "done": true,
+                        "load_duration": 2_000_000_000u64})
+                ),
+            )
+        } else {
+            (
+                "200 OK",
+                format!(
+                    "{}\n{}\n",
+                    serde_json::json!({"model": "m", "response": "x", "done": false}),
+                    serde_json::json!({"model": "m", "response": "", "done": true,
+                        "load_duration": 1_000_000u64,
+                        "prompt_eval_count": 4, "prompt_eval_duration": 500_000_000u64,
+                        "eval_count": 10, "eval_duration": 1_000_000_000u64})
+                ),
+            )
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/x-ndjson\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+    }
+}
+
+async fn start() -> (RequestOptions, Arc<Mutex<Vec<String>>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let prompts = Arc::new(Mutex::new(vec![]));
+    tokio::spawn(server(listener, Arc::clone(&prompts)));
+    let options = RequestOptions {
+        url: Some(format!("http://127.0.0.1:{port}")),
+        ..RequestOptions::default()
+    };
+    (options, prompts)
+}
+
+/////////////////////////////////////////// Distribution ///////////////////////////////////////////
+
+#[test]
+fn nearest_rank_percentiles() {
+    let ten = (1..=10).rev().map(f64::from).collect::<Vec<_>>();
+    let hundred_and_one = (1..=101).map(f64::from).collect::<Vec<_>>();
+    for (values, expected) in [
+        (vec![], None),
+        (
+            vec![3.0],
+            Some(Distribution {
+                mean: 3.0,
+                p50: 3.0,
+                p90: 3.0,
+                p99: 3.0,
+                max: 3.0,
+            }),
+        ),
+        (
+            vec![4.0, 1.0],
+            Some(Distribution {
+                mean: 2.5,
+                p50: 1.0,
+                p90: 4.0,
+                p99: 4.0,
+                max: 4.0,
+            }),
+        ),
+        (
+            ten,
+            Some(Distribution {
+                mean: 5.5,
+                p50: 5.0,
+                p90: 9.0,
+                p99: 10.0,
+                max: 10.0,
+            }),
+        ),
+        (
+            hundred_and_one,
+            Some(Distribution {
+                mean: 51.0,
+                p50: 51.0,
+                p90: 91.0,
+                p99: 100.0,
+                max: 101.0,
+            }),
+        ),
+    ] {
+        assert_eq!(expected, Distribution::new(values.clone()), "{values:?}");
+    }
+}
+
+#[test]
+fn percentiles_are_observed_values() {
+    let values = vec![0.25, 7.5, 0.5, 100.0, 1.0, 2.0, 3.0];
+    let distribution = Distribution::new(values.clone()).unwrap();
+    for p in [
+        distribution.p50,
+        distribution.p90,
+        distribution.p99,
+        distribution.max,
+    ] {
+        assert!(values.contains(&p), "{p}");
+    }
+}
+
+////////////////////////////////////////////// warm-up /////////////////////////////////////////////
+
+#[tokio::test]
+async fn warm_up_is_not_a_sample() {
+    let (options, prompts) = start().await;
+    let result = bench_model(options, "m", &["a".to_string(), "b".to_string()], 2, 3)
+        .await
+        .unwrap();
+    // NOTE(rescrv):  The warm-up goes first and alone; its load time is the model's.
+    let seen = prompts.lock().unwrap().clone();
+    assert_eq!(7, seen.len());
+    assert_eq!("", seen[0]);
+    assert!(seen[1..].iter().all(|p| !p.is_empty()), "{seen:?}");
+    assert_eq!(Some(2.0), result.load);
+    assert_eq!(6, result.requests);
+    assert_eq!(0, result.errors);
+    assert_eq!(6, result.samples.len());
+    let mut runs = result
+        .samples
+        .iter()
+        .map(|s| (s.run, s.prompt))
+        .collect::<Vec<_>>();
+    runs.sort();
+    assert_eq!(vec![(0, 0), (0, 1), (1, 0), (1, 1), (2, 0), (2, 1)], runs);
+    for sample in result.samples.iter() {
+        assert_eq!(Some(10), sample.tokens);
+        assert_eq!(Some(0.001), sample.load);
+    }
+    let tokens_per_second = result.tokens_per_second.unwrap();
+    assert_eq!(10.0, tokens_per_second.mean);
+    assert_eq!(10.0, tokens_per_second.max);
+    assert_eq!(8.0, result.prompt_tokens_per_second.unwrap().p50);
+    assert!(result.time_to_first_token.is_some());
+}
+
+#[tokio::test]
+async fn failed_warm_up_fails_the_model() {
+    let (options, prompts) = start().await;
+    match bench_model(options, "broken", &["a".to_string()], 1, 3).await {
+        Err(Error::Message(msg)) => assert!(msg.starts_with("warming broken: "), "{msg}"),
+        other => panic!("{other:?}"),
+    }
+    assert_eq!(vec![""], *prompts.lock().unwrap());
+}
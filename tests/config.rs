@@ -0,0 +1,234 @@
+use std::sync::Mutex;
+
+use arrrg::CommandLine;
+
+use yammer::config::{given, resolved, Config, Profile};
+use yammer::{ConversationOptions, Error, RequestOptions};
+
+const CONFIG: &str = r#"
+url = "http://defaults:1"
+model = "base-model"
+
+[profiles.coder]
+url = "http://coder:2"
+backend = "openai"
+insecure = true
+model = "coder-model"
+ps1 = "coder> "
+log = "coder.log"
+markdown = true
+"#;
+
+/// Every variable that takes part in resolution.  Tests that set them hold ENV.
+const VARS: &[&str] = &[
+    "OLLAMA_HOST",
+    "OLLAMA_API_KEY",
+    "YAMMER_BACKEND",
+    "YAMMER_HEADERS",
+    "YAMMER_LOG",
+    "YAMMER_HISTFILE",
+    "YAMMER_PROFILE",
+];
+
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Run `f` with exactly the variables in `env` set, restoring the environment afterwards.
+fn with_env<T>(env: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+    let _guard = ENV.lock().unwrap_or_else(|err| err.into_inner());
+    let saved = VARS
+        .iter()
+        .map(|var| (*var, std::env::var_os(var)))
+        .collect::<Vec<_>>();
+    for var in VARS {
+        std::env::remove_var(var);
+    }
+    for (var, value) in env {
+        std::env::set_var(var, value);
+    }
+    let result = f();
+    for (var, value) in saved {
+        match value {
+            Some(value) => std::env::set_var(var, value),
+            None => std::env::remove_var(var),
+        }
+    }
+    result
+}
+
+/// Resolve `yammer <global> chat <chat>` against CONFIG the way the binary does.
+fn resolve(global: &[&str], chat: &[&str]) -> Result<Profile, Error> {
+    let config: Config = toml::from_str(CONFIG).unwrap();
+    let (mut options, free) = RequestOptions::from_arguments_relaxed("USAGE: test", global);
+    assert!(free.is_empty(), "{free:?}");
+    let profile = config.profile(options.profile.as_deref())?;
+    profile.apply_request(&mut options, &given(global))?;
+    let (mut conversation, free) = ConversationOptions::from_arguments_relaxed("USAGE: test", chat);
+    assert!(free.is_empty(), "{free:?}");
+    profile.apply_conversation(&mut conversation, &given(chat));
+    Ok(resolved(&options, &conversation))
+}
+
+////////////////////////////////////////////// given ///////////////////////////////////////////////
+
+#[test]
+fn given_names_flags() {
+    for (args, expected) in [
+        (vec![], vec![]),
+        (vec!["--model", "m"], vec!["model"]),
+        (vec!["--model=m", "--insecure"], vec!["insecure", "model"]),
+        (vec!["--ps1="], vec!["ps1"]),
+        (vec!["--url", "u", "--", "--model"], vec!["url"]),
+        (vec!["-v", "model", "--markdown"], vec!["markdown"]),
+    ] {
+        let mut flags = given(&args).into_iter().collect::<Vec<_>>();
+        flags.sort();
+        assert_eq!(expected, flags, "{args:?}");
+    }
+}
+
+//////////////////////////////////////////// precedence ////////////////////////////////////////////
+
+struct Case {
+    name: &'static str,
+    env: &'static [(&'static str, &'static str)],
+    global: &'static [&'static str],
+    chat: &'static [&'static str],
+    url: &'static str,
+    backend: &'static str,
+    insecure: bool,
+    model: &'static str,
+    ps1: &'static str,
+    log: Option<&'static str>,
+    markdown: bool,
+}
+
+const DEFAULTS: Case = Case {
+    name: "defaults",
+    env: &[],
+    global: &[],
+    chat: &[],
+    url: "http://defaults:1",
+    backend: "ollama",
+    insecure: false,
+    model: "base-model",
+    ps1: "yammer> ",
+    log: None,
+    markdown: false,
+};
+
+const CODER: Case = Case {
+    name: "profile",
+    global: &["--profile", "coder"],
+    url: "http://coder:2",
+    backend: "openai",
+    insecure: true,
+    model: "coder-model",
+    ps1: "coder> ",
+    log: Some("coder.log"),
+    markdown: true,
+    ..DEFAULTS
+};
+
+const CASES: &[Case] = &[
+    DEFAULTS,
+    CODER,
+    Case {
+        name: "profile from the environment",
+        env: &[("YAMMER_PROFILE", "coder")],
+        global: &[],
+        ..CODER
+    },
+    Case {
+        name: "flags over profile",
+        global: &[
+            "--profile",
+            "coder",
+            "--url",
+            "http://flag:3",
+            "--backend",
+            "ollama",
+        ],
+        chat: &["--model", "flag-model", "--ps1", "flag> "],
+        url: "http://flag:3",
+        backend: "ollama",
+        model: "flag-model",
+        ps1: "flag> ",
+        ..CODER
+    },
+    Case {
+        name: "explicit flags equal to the defaults",
+        global: &["--profile=coder", "--insecure"],
+        chat: &["--model", "mistral-nemo", "--ps1=yammer> ", "--markdown"],
+        model: "mistral-nemo",
+        ps1: "yammer> ",
+        ..CODER
+    },
+    Case {
+        name: "environment over profile",
+        env: &[
+            ("OLLAMA_HOST", "http://env:4"),
+            ("YAMMER_BACKEND", "ollama"),
+            ("YAMMER_LOG", "env.log"),
+        ],
+        url: "http://env:4",
+        backend: "ollama",
+        log: Some("env.log"),
+        ..CODER
+    },
+    Case {
+        name: "environment over defaults",
+        env: &[("OLLAMA_HOST", "http://env:4")],
+        url: "http://env:4",
+        ..DEFAULTS
+    },
+    Case {
+        name: "flags over environment",
+        env: &[("OLLAMA_HOST", "http://env:4"), ("YAMMER_LOG", "env.log")],
+        global: &["--profile", "coder", "--url", "http://flag:3"],
+        chat: &["--log", "flag.log"],
+        url: "http://flag:3",
+        log: Some("flag.log"),
+        ..CODER
+    },
+];
+
+#[test]
+fn flags_over_environment_over_profile_over_defaults() {
+    for case in CASES {
+        let profile = with_env(case.env, || resolve(case.global, case.chat)).unwrap();
+        let name = case.name;
+        assert_eq!(Some(case.url), profile.url.as_deref(), "{name}");
+        assert_eq!(Some(case.backend), profile.backend.as_deref(), "{name}");
+        assert_eq!(Some(case.insecure), profile.insecure, "{name}");
+        assert_eq!(Some(case.model), profile.model.as_deref(), "{name}");
+        assert_eq!(Some(case.ps1), profile.ps1.as_deref(), "{name}");
+        assert_eq!(case.log, profile.log.as_deref(), "{name}");
+        assert_eq!(Some(case.markdown), profile.markdown, "{name}");
+    }
+}
+
+#[test]
+fn unknown_profiles() {
+    for (env, global, expected) in [
+        (&[][..], &["--profile", "nope"][..], Some("nope")),
+        (&[("YAMMER_PROFILE", "nope")][..], &[][..], Some("nope")),
+        (
+            &[("YAMMER_PROFILE", "nope")][..],
+            &["--profile", "coder"][..],
+            None,
+        ),
+        (
+            &[("YAMMER_PROFILE", "coder")][..],
+            &["--profile", "gone"][..],
+            Some("gone"),
+        ),
+    ] {
+        match (with_env(env, || resolve(global, &[])), expected) {
+            (Ok(_), None) => {}
+            (Err(Error::Message(msg)), Some(name)) => {
+                assert_eq!(format!("no such profile: {name}"), msg);
+            }
+            (result, _) => panic!("{env:?} {global:?}: {result:?}"),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::Path;
+
+use yammer::log::{Log, LogRecord, LogWriter};
+use yammer::{ChatMessage, Conversation};
+
+fn message(role: &str, content: &str) -> ChatMessage {
+    ChatMessage {
+        role: role.to_string(),
+        content: content.to_string(),
+        images: None,
+        tool_calls: None,
+    }
+}
+
+/// Append a session to the log at `path` the way the shell does:  a header, every message from
+/// `from` on, and the head.
+fn log_session(path: &Path, conversation: &Conversation, from: usize, resumes: bool) {
+    let header = LogRecord::header("m", "", serde_json::Value::Null, vec![]).with_resumes(resumes);
+    let mut log = LogWriter::open(path, header).unwrap();
+    for (id, node) in conversation.nodes().iter().enumerate().skip(from) {
+        log.append(&LogRecord::message(id, node)).unwrap();
+    }
+    log.append(&LogRecord::Checkout {
+        at: "2024-01-01T00:00:00Z".to_string(),
+        id: conversation.head().unwrap(),
+    })
+    .unwrap();
+}
+
+fn reload(path: &Path) -> Conversation {
+    let mut conversation = Conversation::new();
+    conversation.replay(&Log::load(path).unwrap());
+    conversation
+}
+
+fn parents(conversation: &Conversation) -> Vec<Option<usize>> {
+    conversation.nodes().iter().map(|n| n.parent).collect()
+}
+
+fn path(conversation: &Conversation) -> Vec<usize> {
+    conversation.branch().map(|(id, _)| id).collect()
+}
+
+fn contents(conversation: &Conversation) -> Vec<&str> {
+    conversation
+        .messages()
+        .iter()
+        .map(|m| m.content.as_str())
+        .collect()
+}
+
+/// A conversation with an edit:  a, b, c, d on one branch and a, b, c2, d2 on the other.
+fn edited() -> Conversation {
+    let mut conversation = Conversation::new();
+    for (role, content) in [
+        ("user", "a"),
+        ("assistant", "b"),
+        ("user", "c"),
+        ("assistant", "d"),
+    ] {
+        conversation.push(message(role, content));
+    }
+    conversation.fork(2, "c2").unwrap();
+    conversation.push(message("assistant", "d2"));
+    conversation
+}
+
+#[test]
+fn edits_and_checkouts_survive_a_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("chat.log");
+    let mut conversation = edited();
+    assert_eq!(vec![0, 1, 4, 5], path(&conversation));
+    conversation.checkout(3).unwrap();
+    log_session(&log, &conversation, 0, false);
+
+    let reloaded = reload(&log);
+    assert_eq!(
+        vec![None, Some(0), Some(1), Some(2), Some(1), Some(4)],
+        parents(&reloaded)
+    );
+    assert_eq!(vec![0, 1, 2, 3], path(&reloaded));
+    assert_eq!(vec!["a", "b", "c", "d"], contents(&reloaded));
+    assert_eq!(vec![3, 5], reloaded.branches());
+}
+
+#[test]
+fn a_resumed_log_keeps_its_ids() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("chat.log");
+    let mut first = edited();
+    first.checkout(3).unwrap();
+    log_session(&log, &first, 0, false);
+
+    // NOTE(rescrv):  The second session continues from d, then returns to d2, which the first
+    // session wrote, and continues from there too.
+    let mut second = reload(&log);
+    let resumed_from = second.nodes().len();
+    second.push(message("user", "e"));
+    second.push(message("assistant", "f"));
+    second.checkout(5).unwrap();
+    second.push(message("user", "g"));
+    log_session(&log, &second, resumed_from, true);
+
+    let third = reload(&log);
+    assert_eq!(parents(&second), parents(&third));
+    assert_eq!(
+        vec![
+            None,
+            Some(0),
+            Some(1),
+            Some(2),
+            Some(1),
+            Some(4),
+            Some(3),
+            Some(6),
+            Some(5)
+        ],
+        parents(&third)
+    );
+    assert_eq!(vec![0, 1, 4, 5, 8], path(&third));
+    assert_eq!(vec!["a", "b", "c2", "d2", "g"], contents(&third));
+    assert_eq!(vec![7, 8], third.branches());
+
+    // NOTE(rescrv):  Resuming twice numbers the third session after both of the others.
+    let mut fourth = reload(&log);
+    fourth.checkout(7).unwrap();
+    fourth.push(message("user", "h"));
+    log_session(&log, &fourth, 9, true);
+    let fifth = reload(&log);
+    assert_eq!(parents(&fourth), parents(&fifth));
+    assert_eq!(vec![0, 1, 2, 3, 6, 7, 9], path(&fifth));
+}
+
+#[test]
+fn sessions_that_do_not_resume_number_their_own_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("chat.log");
+    let first = edited();
+    log_session(&log, &first, 0, false);
+    // NOTE(rescrv):  A session that loaded another log records all of it again under new ids.
+    let mut second = Conversation::new();
+    second.push(message("user", "x"));
+    second.push(message("assistant", "y"));
+    log_session(&log, &second, 0, false);
+    let reloaded = reload(&log);
+    assert_eq!(
+        vec![
+            None,
+            Some(0),
+            Some(1),
+            Some(2),
+            Some(1),
+            Some(4),
+            None,
+            Some(6)
+        ],
+        parents(&reloaded)
+    );
+    assert_eq!(vec![6, 7], path(&reloaded));
+}
+
+#[test]
+fn system_prompt_roots_every_branch() {
+    let mut conversation = edited();
+    conversation.checkout(3).unwrap();
+    conversation.insert_system("be brief".to_string());
+    assert_eq!(
+        vec![None, Some(0), Some(1), Some(2), Some(3), Some(2), Some(5)],
+        parents(&conversation)
+    );
+    assert_eq!(vec![0, 1, 2, 3, 4], path(&conversation));
+    assert_eq!(
+        vec!["be brief", "a", "b", "c", "d"],
+        contents(&conversation)
+    );
+    assert_eq!(vec![4, 6], conversation.branches());
+}
+
+#[test]
+fn checkout_and_fork_reject_missing_messages() {
+    let mut conversation = edited();
+    assert!(conversation.checkout(6).is_err());
+    assert!(conversation.fork(4, "nope").is_err());
+    assert_eq!(vec![0, 1, 4, 5], path(&conversation));
+}
@@ -0,0 +1,171 @@
+use serde_json::json;
+
+use yammer::diff::{diff, Change};
+use yammer::Error;
+
+const A: &str = r#"FROM /models/blobs/sha256-aaaaaaaaaaaaaaaaaaaa
+ADAPTER /models/blobs/sha256-cccccccccccccccccccc
+PARAMETER temperature 0.7
+PARAMETER stop "<|user|>"
+PARAMETER stop "<|end|>"
+TEMPLATE """{{ .System }}
+{{ .Prompt }}
+{{ .Response }}"""
+"#;
+
+const B: &str = r#"FROM /models/blobs/sha256-bbbbbbbbbbbbbbbbbbbb
+ADAPTER /models/blobs/sha256-cccccccccccccccccccc
+PARAMETER stop "<|user|>"
+PARAMETER num_ctx 4096
+TEMPLATE """{{ .System }}
+<|user|>{{ .Prompt }}
+{{ .Response }}"""
+"#;
+
+#[test]
+fn identical_models() {
+    let show = json!({"modelfile": A, "details": {"family": "llama"}});
+    let diffed = diff("a", &show, "b", &show).unwrap();
+    assert!(diffed.is_empty());
+    assert_eq!("--- a\n+++ b\nno differences\n", diffed.to_string());
+}
+
+#[test]
+fn parameters_in_order_of_appearance() {
+    let diffed = diff("a", &json!({"modelfile": A}), "b", &json!({"modelfile": B})).unwrap();
+    let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            Change::Parameter {
+                name: "temperature".to_string(),
+                a: strings(&["0.7"]),
+                b: vec![],
+            },
+            Change::Parameter {
+                name: "stop".to_string(),
+                a: strings(&["<|user|>", "<|end|>"]),
+                b: strings(&["<|user|>"]),
+            },
+            Change::Parameter {
+                name: "num_ctx".to_string(),
+                a: vec![],
+                b: strings(&["4096"]),
+            },
+        ],
+        diffed
+            .changes
+            .iter()
+            .filter(|c| matches!(c, Change::Parameter { .. }))
+            .cloned()
+            .collect::<Vec<_>>()
+    );
+    // NOTE(rescrv):  The adapters match, so only the base is reported, shortened.
+    assert_eq!(
+        Some(&Change::Digest {
+            what: "base",
+            a: strings(&["sha256:aaaaaaaaaaaa"]),
+            b: strings(&["sha256:bbbbbbbbbbbb"]),
+        }),
+        diffed.changes.last()
+    );
+}
+
+#[test]
+fn text_fields_prefer_the_show_response() {
+    let a = json!({"modelfile": A, "system": "Be terse."});
+    let b = json!({"modelfile": A, "system": "Be terse.", "template": "{{ .Prompt }}"});
+    let diffed = diff("a", &a, "b", &b).unwrap();
+    assert_eq!(
+        vec![Change::Text {
+            field: "template",
+            a: Some("{{ .System }}\n{{ .Prompt }}\n{{ .Response }}".to_string()),
+            b: Some("{{ .Prompt }}".to_string()),
+        }],
+        diffed.changes
+    );
+}
+
+#[test]
+fn templates_as_a_line_diff() {
+    let diffed = diff("a", &json!({"modelfile": A}), "b", &json!({"modelfile": B})).unwrap();
+    let shown = diffed.to_string();
+    let template = shown
+        .split_once("template:\n")
+        .map(|(_, rest)| rest)
+        .unwrap();
+    assert_eq!(
+        "    {{ .System }}\n  + <|user|>{{ .Prompt }}\n  - {{ .Prompt }}\n    {{ .Response }}\n",
+        template.split_once("base:").unwrap().0
+    );
+}
+
+#[test]
+fn line_diff_of_disjoint_and_empty_texts() {
+    for (a, b, expected) in [
+        ("x\ny", "", "template:\n  - x\n  - y\n"),
+        ("", "x", "template:\n  + x\n"),
+        (
+            "a\nb\nc\nd",
+            "a\nc\nd\ne",
+            "template:\n    a\n  - b\n    c\n    d\n  + e\n",
+        ),
+        ("p\nq", "r\ns", "template:\n  + r\n  + s\n  - p\n  - q\n"),
+    ] {
+        let diffed = diff("a", &json!({"template": a}), "b", &json!({"template": b})).unwrap();
+        assert_eq!(
+            format!("--- a\n+++ b\n{expected}"),
+            diffed.to_string(),
+            "{a:?} {b:?}"
+        );
+    }
+}
+
+#[test]
+fn details_and_selected_model_info() {
+    let a = json!({
+        "details": {"family": "llama", "quantization_level": "Q4_0"},
+        "model_info": {
+            "llama.context_length": 8192,
+            "llama.block_count": 32,
+            "tokenizer.ggml.tokens": ["a"],
+        },
+    });
+    let b = json!({
+        "details": {"family": "llama", "quantization_level": "Q8_0"},
+        "model_info": {
+            "llama.context_length": 131072,
+            "llama.block_count": 32,
+            "tokenizer.ggml.tokens": ["b"],
+        },
+    });
+    let diffed = diff("a", &a, "b", &b).unwrap();
+    assert_eq!(
+        vec![
+            Change::Detail {
+                name: "details.quantization_level".to_string(),
+                a: Some(json!("Q4_0")),
+                b: Some(json!("Q8_0")),
+            },
+            Change::Detail {
+                name: "model_info.llama.context_length".to_string(),
+                a: Some(json!(8192)),
+                b: Some(json!(131072)),
+            },
+        ],
+        diffed.changes
+    );
+    assert_eq!(
+        "--- a\n+++ b\ndetails.quantization_level: Q4_0 -> Q8_0\n\
+         model_info.llama.context_length: 8192 -> 131072\n",
+        diffed.to_string()
+    );
+}
+
+#[test]
+fn unparseable_modelfile_names_the_model() {
+    let bad = json!({"modelfile": "FROM x\nTEMPLATE \"\"\"open"});
+    match diff("a", &json!({}), "broken", &bad) {
+        Err(Error::Message(msg)) => assert!(msg.starts_with("broken: line 2"), "{msg}"),
+        other => panic!("{other:?}"),
+    }
+}
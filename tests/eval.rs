@@ -0,0 +1,173 @@
+use serde_json::json;
+
+use yammer::eval::{validate, Suite};
+
+fn errors(schema: serde_json::Value, value: serde_json::Value) -> Vec<String> {
+    let mut errors = vec![];
+    validate(&schema, &value, "", &mut errors);
+    errors
+}
+
+#[test]
+fn boolean_schemas() {
+    assert!(errors(json!(true), json!({"a": 1})).is_empty());
+    assert_eq!(vec!["/: not allowed"], errors(json!(false), json!(1)));
+}
+
+#[test]
+fn types() {
+    assert!(errors(json!({"type": "integer"}), json!(3)).is_empty());
+    assert!(errors(json!({"type": "integer"}), json!(3.0)).is_empty());
+    assert!(errors(json!({"type": ["string", "null"]}), json!(null)).is_empty());
+    assert_eq!(
+        vec!["/: expected integer, got number"],
+        errors(json!({"type": "integer"}), json!(3.5))
+    );
+    assert_eq!(
+        vec!["/: expected string or null, got array"],
+        errors(json!({"type": ["string", "null"]}), json!([]))
+    );
+}
+
+#[test]
+fn enum_and_const() {
+    let schema = json!({"enum": ["red", "green"]});
+    assert!(errors(schema.clone(), json!("red")).is_empty());
+    assert_eq!(
+        vec!["/: \"blue\" is not one of the allowed values"],
+        errors(schema, json!("blue"))
+    );
+    assert_eq!(
+        vec!["/: expected 1, got 2"],
+        errors(json!({"const": 1}), json!(2))
+    );
+}
+
+#[test]
+fn objects() {
+    let schema = json!({
+        "type": "object",
+        "properties": {"name": {"type": "string"}},
+        "required": ["name", "age"],
+        "additionalProperties": false,
+    });
+    assert_eq!(
+        vec![
+            "/: missing property \"age\"",
+            "/: unexpected property \"extra\"",
+            "/name: expected string, got number",
+        ],
+        errors(schema, json!({"name": 7, "extra": true}))
+    );
+    let schema = json!({"additionalProperties": {"type": "number"}});
+    assert_eq!(
+        vec!["/b: expected number, got string"],
+        errors(schema, json!({"a": 1, "b": "two"}))
+    );
+}
+
+#[test]
+fn arrays() {
+    let schema = json!({"items": {"type": "string"}, "minItems": 2, "maxItems": 3});
+    assert!(errors(schema.clone(), json!(["a", "b"])).is_empty());
+    assert_eq!(
+        vec!["/1: expected string, got number"],
+        errors(schema.clone(), json!(["a", 1]))
+    );
+    assert_eq!(
+        vec!["/: fewer than 2 items"],
+        errors(schema.clone(), json!(["a"]))
+    );
+    assert_eq!(
+        vec!["/: more than 3 items"],
+        errors(schema, json!(["a", "b", "c", "d"]))
+    );
+}
+
+#[test]
+fn strings() {
+    let schema = json!({"minLength": 2, "maxLength": 4, "pattern": "^[a-z]+$"});
+    assert!(errors(schema.clone(), json!("abc")).is_empty());
+    assert_eq!(
+        vec!["/: shorter than 2"],
+        errors(schema.clone(), json!("a"))
+    );
+    assert_eq!(
+        vec!["/: longer than 4"],
+        errors(schema.clone(), json!("abcde"))
+    );
+    assert_eq!(
+        vec!["/: does not match /^[a-z]+$/"],
+        errors(schema, json!("AB"))
+    );
+    // Lengths count characters, not bytes.
+    assert!(errors(json!({"maxLength": 2}), json!("éé")).is_empty());
+}
+
+#[test]
+fn bad_patterns() {
+    let errs = errors(json!({"pattern": "("}), json!("x"));
+    assert_eq!(1, errs.len());
+    assert!(errs[0].starts_with("/: invalid regex /(/"), "{errs:?}");
+    let errs = errors(json!({"pattern": "\\w{1000}{1000}"}), json!("x"));
+    assert_eq!(1, errs.len());
+    assert!(errs[0].contains("size limit"), "{errs:?}");
+}
+
+#[test]
+fn numbers() {
+    let schema = json!({"minimum": 1, "maximum": 10});
+    assert!(errors(schema.clone(), json!(1)).is_empty());
+    assert!(errors(schema.clone(), json!(10)).is_empty());
+    assert_eq!(vec!["/: less than 1"], errors(schema.clone(), json!(0.5)));
+    assert_eq!(vec!["/: greater than 10"], errors(schema, json!(11)));
+    let schema = json!({"exclusiveMinimum": 1, "exclusiveMaximum": 10});
+    assert!(errors(schema.clone(), json!(5)).is_empty());
+    assert_eq!(
+        vec!["/: not greater than 1"],
+        errors(schema.clone(), json!(1))
+    );
+    assert_eq!(vec!["/: not less than 10"], errors(schema, json!(10)));
+}
+
+#[test]
+fn combinators() {
+    let all = json!({"allOf": [{"type": "integer"}, {"minimum": 5}]});
+    assert!(errors(all.clone(), json!(6)).is_empty());
+    assert_eq!(vec!["/: less than 5"], errors(all, json!(4)));
+    let any = json!({"anyOf": [{"type": "string"}, {"type": "null"}]});
+    assert!(errors(any.clone(), json!(null)).is_empty());
+    assert_eq!(vec!["/: matches none of anyOf"], errors(any, json!(1)));
+    let one = json!({"oneOf": [{"type": "number"}, {"type": "integer"}]});
+    assert!(errors(one.clone(), json!(1.5)).is_empty());
+    assert_eq!(
+        vec!["/: matches 2 of oneOf, not exactly 1"],
+        errors(one, json!(1))
+    );
+    let not = json!({"not": {"type": "string"}});
+    assert!(errors(not.clone(), json!(1)).is_empty());
+    assert_eq!(
+        vec!["/: matches the schema under not"],
+        errors(not, json!("s"))
+    );
+}
+
+#[test]
+fn suite_rejects_bad_regex() {
+    let suite: Suite = toml::from_str(
+        r#"
+        models = ["m"]
+
+        [[case]]
+        name = "greeting"
+        prompt = "Say hello."
+        assert = [{ type = "regex", pattern = "(hello" }]
+        "#,
+    )
+    .unwrap();
+    let err = suite.validate().unwrap_err().to_string();
+    assert!(
+        err.contains("case greeting: invalid regex /(hello/"),
+        "{err}"
+    );
+}
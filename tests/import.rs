@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+use yammer::import::{ImportFormat, ImportOptions};
+use yammer::log::{Log, LogRecord};
+use yammer::ChatMessage;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/import")
+        .join(name)
+}
+
+fn read(name: &str) -> String {
+    std::fs::read_to_string(fixture(name)).unwrap()
+}
+
+fn turns(messages: &[ChatMessage]) -> Vec<(&str, &str)> {
+    messages
+        .iter()
+        .map(|m| (m.role.as_str(), m.content.as_str()))
+        .collect()
+}
+
+////////////////////////////////////////////// chatgpt /////////////////////////////////////////////
+
+#[test]
+fn chatgpt_current_branch() {
+    let messages =
+        yammer::import::chatgpt(&read("conversations.json"), Some("Trip planning")).unwrap();
+    assert_eq!(
+        vec![
+            ("user", "Where should I go in May?"),
+            ("assistant", "Try Lisbon.\nIt is mild in May."),
+        ],
+        turns(&messages)
+    );
+}
+
+#[test]
+fn chatgpt_by_id() {
+    let messages = yammer::import::chatgpt(&read("conversations.json"), Some("c-recipes")).unwrap();
+    assert_eq!(vec![("user", "How do I boil an egg?")], turns(&messages));
+}
+
+#[test]
+fn chatgpt_needs_a_selector() {
+    let err = yammer::import::chatgpt(&read("conversations.json"), None).unwrap_err();
+    assert!(err.to_string().contains("Trip planning, Recipes"), "{err}");
+    assert!(yammer::import::chatgpt(&read("conversations.json"), Some("nope")).is_err());
+}
+
+////////////////////////////////////////////// openai //////////////////////////////////////////////
+
+#[test]
+fn openai_messages() {
+    let messages = yammer::import::openai(&read("messages.json")).unwrap();
+    assert_eq!(
+        vec![
+            ("system", "You are terse."),
+            ("user", "What is in this picture?"),
+            ("assistant", ""),
+            ("assistant", "A cat."),
+        ],
+        turns(&messages)
+    );
+    assert_eq!(Some(vec!["aGVsbG8=".to_string()]), messages[1].images);
+    let calls = messages[2].tool_calls.as_ref().unwrap();
+    assert_eq!("describe", calls[0]["function"]["name"]);
+    assert_eq!(
+        serde_json::json!({"detail": "high"}),
+        calls[0]["function"]["arguments"]
+    );
+}
+
+#[test]
+fn openai_bare_array() {
+    let messages = yammer::import::openai(r#"[{"role": "user", "content": "hi"}]"#).unwrap();
+    assert_eq!(vec![("user", "hi")], turns(&messages));
+    assert!(yammer::import::openai(r#"[{"content": "hi"}]"#).is_err());
+}
+
+////////////////////////////////////////////// ollama //////////////////////////////////////////////
+
+#[test]
+fn ollama_history() {
+    let messages = yammer::import::ollama(&read("history"));
+    assert_eq!(
+        vec![("user", "why is the sky blue"), ("user", "write a haiku"),],
+        turns(&messages)
+    );
+}
+
+////////////////////////////////////////////// import //////////////////////////////////////////////
+
+#[test]
+fn import_writes_a_log() {
+    let out = std::env::temp_dir().join(format!("yammer-import-{}.log", std::process::id()));
+    let options = ImportOptions {
+        from: ImportFormat::ChatGpt,
+        out: out.to_string_lossy().to_string(),
+        conversation: Some("c-trip".to_string()),
+    };
+    let count = yammer::import::import(&options, fixture("conversations.json")).unwrap();
+    assert_eq!(2, count);
+    let log = Log::load(&out).unwrap();
+    assert!(!log.legacy);
+    assert!(log.skipped.is_empty());
+    assert!(matches!(log.records[0], LogRecord::Header { .. }));
+    let links = log.records[1..]
+        .iter()
+        .map(|record| match record {
+            LogRecord::Message { id, parent, .. } => (*id, *parent),
+            record => panic!("unexpected record {record:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(vec![(Some(0), None), (Some(1), Some(0))], links);
+    let loaded = yammer::load(&out).unwrap();
+    assert_eq!(
+        vec![
+            ("user", "Where should I go in May?"),
+            ("assistant", "Try Lisbon.\nIt is mild in May."),
+        ],
+        turns(&loaded)
+    );
+    std::fs::remove_file(&out).unwrap();
+}
+
+#[test]
+fn import_refuses_to_overwrite() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("imported.log");
+    let options = ImportOptions {
+        from: ImportFormat::Ollama,
+        out: out.to_string_lossy().to_string(),
+        conversation: None,
+    };
+    assert_eq!(
+        2,
+        yammer::import::import(&options, fixture("history")).unwrap()
+    );
+    let before = std::fs::read_to_string(&out).unwrap();
+    let err = yammer::import::import(&options, fixture("history")).unwrap_err();
+    assert!(err.to_string().ends_with("already exists"), "{err}");
+    assert_eq!(before, std::fs::read_to_string(&out).unwrap());
+}
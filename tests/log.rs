@@ -0,0 +1,154 @@
+use std::path::{Path, PathBuf};
+
+use yammer::log::{Log, LogRecord, LOG_VERSION};
+use yammer::Conversation;
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/log")
+        .join(name)
+}
+
+/// Copy the fixture `name` into `dir`, so that migrating it leaves the fixture alone.
+fn copy(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::copy(fixture(name), &path).unwrap();
+    path
+}
+
+fn branch(log: &Log) -> Vec<(String, String)> {
+    let mut conversation = Conversation::new();
+    conversation.replay(log);
+    conversation
+        .messages()
+        .iter()
+        .map(|m| (m.role.clone(), m.content.clone()))
+        .collect()
+}
+
+fn every_message(log: &Log) -> Vec<serde_json::Value> {
+    log.messages()
+        .map(|m| serde_json::to_value(m).unwrap())
+        .collect()
+}
+
+////////////////////////////////////////////// legacy //////////////////////////////////////////////
+
+#[test]
+fn legacy_log_loads() {
+    let log = Log::load(fixture("legacy.log")).unwrap();
+    assert!(log.legacy);
+    assert!(log.skipped.is_empty());
+    let Some(LogRecord::Header {
+        version,
+        model,
+        host,
+        args,
+        ..
+    }) = log.header()
+    else {
+        panic!("no header in {log:?}");
+    };
+    assert_eq!(0, *version);
+    assert_eq!("llama3", model);
+    assert_eq!("http://localhost:11434", host);
+    assert_eq!("chat", args[1]);
+    assert_eq!(5, log.messages().count());
+    assert_eq!(
+        vec!["system", "user", "assistant", "user", "assistant"],
+        branch(&log)
+            .iter()
+            .map(|(r, _)| r.as_str())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn legacy_log_migrates_losslessly() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = copy(dir.path(), "legacy.log");
+    let before = Log::load(&path).unwrap();
+    let migrated = Log::migrate(&path).unwrap();
+    assert!(migrated.skipped.is_empty());
+    migrated.save(&path).unwrap();
+    let after = Log::load(&path).unwrap();
+    assert!(!after.legacy);
+    assert!(after.skipped.is_empty());
+    let Some(LogRecord::Header {
+        version,
+        model,
+        started_at,
+        ..
+    }) = after.header()
+    else {
+        panic!("no header in {after:?}");
+    };
+    assert_eq!(LOG_VERSION, *version);
+    assert_eq!("llama3", model);
+    assert!(!started_at.is_empty());
+    assert_eq!(every_message(&before), every_message(&after));
+    assert_eq!(branch(&before), branch(&after));
+    // NOTE(rescrv):  A migrated log is current, so migrating it again changes nothing.
+    let saved = std::fs::read_to_string(&path).unwrap();
+    Log::migrate(&path).unwrap().save(&path).unwrap();
+    assert_eq!(saved, std::fs::read_to_string(&path).unwrap());
+}
+
+#[test]
+fn legacy_log_without_arguments_gains_a_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bare.log");
+    std::fs::write(
+        &path,
+        "{\"role\":\"user\",\"content\":\"hi\"}\nnot json\n{\"role\":\"assistant\",\"content\":\"hello\"}\n",
+    )
+    .unwrap();
+    let migrated = Log::migrate(&path).unwrap();
+    assert_eq!(
+        vec![2],
+        migrated
+            .skipped
+            .iter()
+            .map(|(line, _)| *line)
+            .collect::<Vec<_>>()
+    );
+    assert!(matches!(
+        migrated.records.first(),
+        Some(LogRecord::Header {
+            version: LOG_VERSION,
+            ..
+        })
+    ));
+    assert_eq!(
+        vec![
+            ("user".to_string(), "hi".to_string()),
+            ("assistant".to_string(), "hello".to_string())
+        ],
+        branch(&migrated)
+    );
+}
+
+///////////////////////////////////////////// current //////////////////////////////////////////////
+
+#[test]
+fn current_log_migrates_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = copy(dir.path(), "current.log");
+    let original = std::fs::read_to_string(&path).unwrap();
+    let log = Log::load(&path).unwrap();
+    assert!(!log.legacy);
+    assert!(log.skipped.is_empty());
+    assert_eq!(original.lines().count(), log.records.len());
+    Log::migrate(&path).unwrap().save(&path).unwrap();
+    assert_eq!(original, std::fs::read_to_string(&path).unwrap());
+    let reloaded = Log::load(&path).unwrap();
+    assert_eq!(branch(&log), branch(&reloaded));
+    assert_eq!(
+        vec![
+            ("user".to_string(), "What is 2+2?".to_string()),
+            ("assistant".to_string(), "4".to_string()),
+            ("user".to_string(), "And 4+4?".to_string()),
+        ],
+        branch(&reloaded)
+    );
+}
@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use yammer::{code_blocks, segments, Accumulator, CodeBlock, MarkdownAccumulator, Segment};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const KEYWORD: &str = "\x1b[35m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const COMMENT: &str = "\x1b[90m";
+const CODE: &str = "\x1b[36m";
+
+/////////////////////////////////////////////// render /////////////////////////////////////////////
+
+/// Output that can be read while the accumulator still holds it.
+#[derive(Clone, Debug, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Shared {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn send(acc: &mut MarkdownAccumulator<Shared>, chunk: &str) {
+    let _ = acc.accumulate(serde_json::json!({"response": chunk, "done": false}));
+}
+
+/// Render `chunks` as one styled response.
+fn render(chunks: &[&str]) -> String {
+    let output = Shared::default();
+    let mut acc = MarkdownAccumulator::generate(output.clone()).with_color(true);
+    for chunk in chunks {
+        send(&mut acc, chunk);
+    }
+    let _ = acc.accumulate(serde_json::json!({"response": "", "done": true}));
+    output.text()
+}
+
+const DOCUMENT: &str = "# Title\n\
+## Section\n\
+Some *italic*, **bold**, ***both***, and **bold _nested_ bold** text.\n\
+A `code span`, a [link](https://example.com), 2 * 3, and snake_case_name.\n\
+> quoted *words*\n\
+- item one\n\
+* item **two**\n\
++ item three\n\
+12. numbered\n\
+---\n\
+* * *\n\
+```rust\n\
+fn main() { let x = \"hi\"; 42 } // done\n\
+```\n\
+~~~~\n\
+plain code\n\
+~~~~\n\
+An unclosed *marker and [bracket stay literal.\n\
+Tail without a newline";
+
+#[test]
+fn every_split_renders_alike() {
+    let whole = render(&[DOCUMENT]);
+    let bytes = DOCUMENT.char_indices().map(|(idx, _)| idx);
+    for split in bytes {
+        let (head, tail) = DOCUMENT.split_at(split);
+        assert_eq!(whole, render(&[head, tail]), "split at {split}");
+    }
+    let chars = DOCUMENT
+        .char_indices()
+        .map(|(idx, c)| &DOCUMENT[idx..idx + c.len_utf8()])
+        .collect::<Vec<_>>();
+    assert_eq!(whole, render(&chars));
+}
+
+#[test]
+fn prose_streams_before_the_line_ends() {
+    let output = Shared::default();
+    let mut acc = MarkdownAccumulator::generate(output.clone()).with_color(true);
+    send(&mut acc, "Hello, wor");
+    assert_eq!("Hello, wor", output.text());
+    send(&mut acc, "ld\n- it");
+    assert_eq!("Hello, world\n• it", output.text());
+    send(&mut acc, "em *em");
+    assert_eq!("Hello, world\n• item ", output.text());
+    send(&mut acc, "ph* done");
+    assert_eq!(
+        format!("Hello, world\n• item {RESET}{ITALIC}emph{RESET} done"),
+        output.text()
+    );
+}
+
+#[test]
+fn headings_and_rules_wait_for_their_kind() {
+    let output = Shared::default();
+    let mut acc = MarkdownAccumulator::generate(output.clone()).with_color(true);
+    send(&mut acc, "#");
+    assert_eq!("", output.text());
+    send(&mut acc, " Heading  ");
+    assert_eq!(format!("{BOLD}{UNDERLINE}Heading"), output.text());
+    send(&mut acc, "\n-");
+    assert_eq!(format!("{BOLD}{UNDERLINE}Heading{RESET}\n"), output.text());
+    send(&mut acc, "--\n");
+    assert_eq!(
+        format!(
+            "{BOLD}{UNDERLINE}Heading{RESET}\n{DIM}{}{RESET}\n",
+            "─".repeat(40)
+        ),
+        output.text()
+    );
+}
+
+#[test]
+fn nested_emphasis() {
+    assert_eq!(
+        format!(
+            "{RESET}{BOLD}{RESET}{BOLD}{ITALIC}both{RESET}{ITALIC}{RESET} and \
+             {RESET}{BOLD}bold {RESET}{BOLD}{ITALIC}nested{RESET}{BOLD}{RESET}\n"
+        ),
+        render(&["***both*** and **bold _nested_**\n"])
+    );
+    assert_eq!(
+        format!("{CODE}a*b{RESET} and {UNDERLINE}x{RESET}{DIM} (u){RESET}\n"),
+        render(&["`a*b` and [x](u)\n"])
+    );
+}
+
+#[test]
+fn fence_split_across_chunks() {
+    assert_eq!(
+        format!(
+            "{DIM}```rust{RESET}\n{KEYWORD}let{RESET} x = {STRING}\"s\"{RESET}; \
+             {NUMBER}1{RESET} {COMMENT}// c{RESET}\n{DIM}```{RESET}\n\
+             {RESET}{ITALIC}not code{RESET}\n"
+        ),
+        render(&[
+            "``",
+            "`ru",
+            "st\nlet x = \"",
+            "s\"; 1 // c\n``",
+            "`\n*not code*\n",
+        ])
+    );
+}
+
+#[test]
+fn unterminated_fence() {
+    let output = Shared::default();
+    let mut acc = MarkdownAccumulator::generate(output.clone()).with_color(true);
+    send(&mut acc, "```py\nx = 1");
+    assert_eq!(format!("{DIM}```py{RESET}\n"), output.text());
+    acc.finish();
+    assert_eq!(
+        format!("{DIM}```py{RESET}\nx = {NUMBER}1{RESET}"),
+        output.text()
+    );
+    // NOTE(rescrv):  The next response starts outside the fence.
+    send(&mut acc, "*a*\n");
+    assert!(output.text().ends_with(&format!("{ITALIC}a{RESET}\n")));
+}
+
+#[test]
+fn unstyled_passes_through() {
+    let output = Shared::default();
+    let mut acc = MarkdownAccumulator::generate(output.clone()).with_color(false);
+    send(&mut acc, "# **Hi**\n```");
+    assert_eq!("# **Hi**\n```", output.text());
+}
+
+///////////////////////////////////////////// CodeBlock ////////////////////////////////////////////
+
+#[test]
+fn code_blocks_in_order() {
+    let content = "Intro\n```Rust\nfn f() {}\n```\nBetween\n~~~~\n```\nnot a close\n~~~~\n";
+    assert_eq!(
+        vec![
+            CodeBlock {
+                lang: "rust".to_string(),
+                code: "fn f() {}\n".to_string(),
+            },
+            CodeBlock {
+                lang: "".to_string(),
+                code: "```\nnot a close\n".to_string(),
+            },
+        ],
+        code_blocks(content)
+    );
+}
+
+#[test]
+fn code_blocks_in_lists_and_unterminated() {
+    let content = "1. Run:\n   ```sh\n   make\n     indented\n   ```\n2. Then\n```py\nx = 1\n";
+    assert_eq!(
+        vec![
+            CodeBlock {
+                lang: "sh".to_string(),
+                code: "make\n  indented\n".to_string(),
+            },
+            CodeBlock {
+                lang: "py".to_string(),
+                code: "x = 1\n".to_string(),
+            },
+        ],
+        code_blocks(content)
+    );
+}
+
+#[test]
+fn segments_alternate() {
+    assert_eq!(
+        vec![
+            Segment::Text("a\n\nb\n".to_string()),
+            Segment::Code(CodeBlock {
+                lang: "".to_string(),
+                code: "c\n".to_string(),
+            }),
+            Segment::Text("d\n".to_string()),
+        ],
+        segments("a\n\nb\n```\nc\n```\nd")
+    );
+}
@@ -0,0 +1,67 @@
+use yammer::modelfile::{CreateOptions, Modelfile};
+
+/// The shape of `ollama show --modelfile`, with a parameter yammer does not know.
+const SHOWN: &str = r#"# Modelfile generated by "ollama show"
+# To build a new Modelfile based on this, replace FROM with:
+# FROM llama3:latest
+
+FROM /usr/share/ollama/.ollama/models/blobs/sha256-aaaa
+TEMPLATE """{{ if .System }}<|system|>
+{{ .System }}{{ end }}<|user|>
+{{ .Prompt }}"""
+PARAMETER stop "<|user|>"
+PARAMETER stop "<|end|>"
+PARAMETER temperature 0.7
+PARAMETER num_experts_used 2
+SYSTEM "You are terse."
+LICENSE """MIT License
+
+Copyright"""
+"#;
+
+#[test]
+fn shown_modelfile_round_trips() {
+    let modelfile = SHOWN.parse::<Modelfile>().unwrap();
+    assert_eq!(vec!["<|user|>", "<|end|>"], modelfile.parameter("stop"));
+    assert_eq!(vec!["2"], modelfile.parameter("num_experts_used"));
+    let formatted = modelfile.to_string();
+    let reparsed = formatted.parse::<Modelfile>().unwrap();
+    assert_eq!(modelfile, reparsed);
+    assert_eq!(formatted, reparsed.to_string());
+}
+
+#[test]
+fn checked_parse_rejects_unknown_parameters() {
+    let err = Modelfile::parse_checked(SHOWN).unwrap_err();
+    assert!(err.to_string().contains("line 12"), "{err}");
+    assert!(err.to_string().contains("num_experts_used"), "{err}");
+}
+
+#[test]
+fn checked_parse_rejects_mistyped_values() {
+    let err = Modelfile::parse_checked("FROM llama3\nPARAMETER num_ctx lots\n").unwrap_err();
+    assert!(err.to_string().contains("line 2"), "{err}");
+    assert!("FROM llama3\nPARAMETER num_ctx lots\n"
+        .parse::<Modelfile>()
+        .is_ok());
+}
+
+#[test]
+fn create_validates() {
+    let create = CreateOptions {
+        name: "terse".to_string(),
+        modelfile: Some("FROM llama3\nPARAMETER num_experts_used 2\n".to_string()),
+        ..Default::default()
+    };
+    assert!(create.request().is_err());
+    let create = CreateOptions {
+        name: "terse".to_string(),
+        modelfile: Some("FROM llama3\nPARAMETER temperature 0.2\n".to_string()),
+        ..Default::default()
+    };
+    let request = create.request().unwrap();
+    assert_eq!(
+        "FROM llama3\nPARAMETER temperature 0.2\n",
+        request.modelfile
+    );
+}
@@ -0,0 +1,182 @@
+use serde_json::json;
+
+use yammer::openai::{response, Responses, SseDecoder};
+
+//////////////////////////////////////////// SseDecoder ////////////////////////////////////////////
+
+#[test]
+fn sse_events_split_across_chunks() {
+    let mut decoder = SseDecoder::default();
+    assert!(decoder.push("data: {\"a\":").is_empty());
+    assert!(decoder.push("1}\n").is_empty());
+    assert_eq!(vec!["{\"a\":1}"], decoder.push("\ndata: {\"b\""));
+    assert_eq!(vec!["{\"b\":2}"], decoder.push(":2}\n\n"));
+    assert_eq!(None, decoder.finish());
+}
+
+#[test]
+fn sse_crlf_framing() {
+    let mut decoder = SseDecoder::default();
+    let events = decoder.push("event: message\r\ndata: one\r\n\r\n: comment\r\ndata:two\r\n\r");
+    assert_eq!(vec!["one"], events);
+    assert_eq!(vec!["two"], decoder.push("\n"));
+}
+
+#[test]
+fn sse_multiline_data_and_done() {
+    let mut decoder = SseDecoder::default();
+    let events = decoder.push("data: first\ndata: second\n\ndata: [DONE]\n\n");
+    assert_eq!(vec!["first\nsecond", "[DONE]"], events);
+}
+
+#[test]
+fn sse_unterminated_final_event() {
+    let mut decoder = SseDecoder::default();
+    assert!(decoder.push("data: {\"x\":1}\n").is_empty());
+    assert!(decoder.push("data: [DONE]").is_empty());
+    assert_eq!(Some("{\"x\":1}\n[DONE]".to_string()), decoder.finish());
+}
+
+///////////////////////////////////////////// response /////////////////////////////////////////////
+
+#[test]
+fn chat_response_with_usage() {
+    let resp = response(
+        "chat",
+        json!({
+            "model": "m",
+            "created": 0,
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"},
+                    }],
+                },
+                "finish_reason": "tool_calls",
+            }],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 5, "total_tokens": 17},
+        }),
+    )
+    .unwrap();
+    assert_eq!(
+        json!({
+            "model": "m",
+            "created_at": "1970-01-01T00:00:00Z",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{
+                    "id": "call_1",
+                    "function": {"name": "weather", "arguments": {"city": "Paris"}},
+                }],
+            },
+            "done": true,
+            "prompt_eval_count": 12,
+            "eval_count": 5,
+        }),
+        resp
+    );
+}
+
+#[test]
+fn generate_response_has_no_context() {
+    let resp = response(
+        "generate",
+        json!({"model": "m", "created": 0, "choices": [{"text": "hi", "finish_reason": null}]}),
+    )
+    .unwrap();
+    assert_eq!(
+        json!({
+            "model": "m",
+            "created_at": "1970-01-01T00:00:00Z",
+            "response": "hi",
+            "done": false,
+        }),
+        resp
+    );
+}
+
+#[test]
+fn usage_only_chunk_is_not_a_message() {
+    let chunk = json!({"model": "m", "choices": [], "usage": {"prompt_tokens": 1}});
+    assert_eq!(None, response("chat", chunk));
+}
+
+///////////////////////////////////////////// Responses ////////////////////////////////////////////
+
+fn delta(delta: serde_json::Value, finish_reason: Option<&str>) -> serde_json::Value {
+    json!({
+        "model": "m",
+        "created": 0,
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+    })
+}
+
+#[test]
+fn stream_holds_the_final_message_for_usage() {
+    let mut responses = Responses::new("chat");
+    let first = responses
+        .push(delta(json!({"role": "assistant", "content": "Hel"}), None))
+        .unwrap();
+    assert_eq!("Hel", first["message"]["content"]);
+    assert!(responses
+        .push(delta(json!({"content": "lo"}), None))
+        .is_some());
+    assert_eq!(None, responses.push(delta(json!({}), Some("stop"))));
+    assert_eq!(
+        None,
+        responses.push(json!({
+            "model": "m",
+            "choices": [],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 2},
+        }))
+    );
+    let last = responses.finish().unwrap();
+    assert_eq!(true, last["done"]);
+    assert_eq!(9, last["prompt_eval_count"]);
+    assert_eq!(2, last["eval_count"]);
+    for key in ["total_duration", "prompt_eval_duration", "eval_duration"] {
+        assert!(last[key].is_u64(), "{key} in {last}");
+    }
+    assert_eq!(None, responses.finish());
+}
+
+#[test]
+fn stream_assembles_tool_calls_by_index() {
+    let mut responses = Responses::new("chat");
+    for fragment in [
+        json!({"tool_calls": [{"index": 0, "id": "a", "type": "function",
+            "function": {"name": "weather", "arguments": ""}}]}),
+        json!({"tool_calls": [{"index": 1, "id": "b", "type": "function",
+            "function": {"name": "time", "arguments": "{\"tz\":"}}]}),
+        json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"city\":"}}]}),
+        json!({"tool_calls": [{"index": 0, "function": {"arguments": "\"Paris\"}"}}]}),
+        json!({"tool_calls": [{"index": 1, "function": {"arguments": "\"UTC\"}"}}]}),
+    ] {
+        let message = responses.push(delta(fragment, None)).unwrap();
+        assert!(message["message"].get("tool_calls").is_none());
+    }
+    assert_eq!(None, responses.push(delta(json!({}), Some("tool_calls"))));
+    let last = responses.finish().unwrap();
+    assert_eq!(
+        json!([
+            {"id": "a", "function": {"name": "weather", "arguments": {"city": "Paris"}}},
+            {"id": "b", "function": {"name": "time", "arguments": {"tz": "UTC"}}},
+        ]),
+        last["message"]["tool_calls"]
+    );
+}
+
+#[test]
+fn embeddings_pass_straight_through() {
+    let mut responses = Responses::new("embed");
+    let resp = responses
+        .push(json!({"model": "e", "data": [{"embedding": [0.5, 1.0]}]}))
+        .unwrap();
+    assert_eq!(json!({"model": "e", "embeddings": [[0.5, 1.0]]}), resp);
+    assert_eq!(None, responses.finish());
+}
@@ -0,0 +1,181 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use serde_json::json;
+
+use yammer::pull::{is_installed, PullProgressAccumulator};
+use yammer::Accumulator;
+
+const A: &str = "sha256:aaaaaaaaaaaaaaaaaaaaaaaa";
+const B: &str = "sha256:bbbbbbbbbbbbbbbbbbbbbbbb";
+
+/// Output that can be read while the accumulator still holds it.
+#[derive(Clone, Debug, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Shared {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn status(status: &str) -> serde_json::Value {
+    json!({"status": status})
+}
+
+fn layer(digest: &str, completed: u64, total: u64) -> serde_json::Value {
+    json!({"status": "pulling", "digest": digest, "completed": completed, "total": total})
+}
+
+//////////////////////////////////////////// is_installed //////////////////////////////////////////
+
+#[test]
+fn installed_names_default_to_latest() {
+    for (model, installed, expected) in [
+        ("llama3", &["llama3:latest"][..], true),
+        ("llama3:latest", &["llama3"][..], true),
+        ("llama3:8b", &["llama3:8b"][..], true),
+        ("llama3", &["llama3:8b"][..], false),
+        ("llama3:8b", &["llama3"][..], false),
+        ("llama3", &["llama3.1:latest"][..], false),
+        ("lib/other", &["lib/other:latest"][..], true),
+        ("localhost:5000/m", &["localhost:5000/m:latest"][..], true),
+        ("localhost:5000/m:v1", &["localhost:5000/m"][..], false),
+        ("m", &[][..], false),
+        ("m", &["a", "b:latest", "m"][..], true),
+    ] {
+        let installed = installed.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            expected,
+            is_installed(model, &installed),
+            "{model} in {installed:?}"
+        );
+    }
+}
+
+////////////////////////////////////// PullProgressAccumulator /////////////////////////////////////
+
+#[test]
+fn layers_are_summarized_once_each() {
+    let output = Shared::default();
+    let mut acc = PullProgressAccumulator::new(output.clone(), false);
+    for message in [
+        status("pulling manifest"),
+        status("pulling manifest"),
+        layer(A, 0, 100),
+        layer(B, 0, 50),
+        layer(A, 60, 100),
+        layer(B, 50, 50),
+        // NOTE(rescrv):  A message that arrives out of order does not move a layer backwards.
+        layer(A, 40, 100),
+    ] {
+        let _ = acc.accumulate(message);
+    }
+    assert_eq!("pulling manifest\n", output.text());
+    let _ = acc.accumulate(status("verifying sha256 digest"));
+    let text = output.text();
+    let summaries = text
+        .strip_prefix("pulling manifest\n")
+        .unwrap()
+        .lines()
+        .collect::<Vec<_>>();
+    assert_eq!(3, summaries.len(), "{text}");
+    assert!(
+        summaries[0].starts_with("pulling aaaaaaaaaaaa: 60% (60 B of 100 B, "),
+        "{text}"
+    );
+    assert_eq!("pulled bbbbbbbbbbbb (50 B)", summaries[1]);
+    assert_eq!("verifying sha256 digest", summaries[2]);
+    for message in [
+        layer(A, 100, 100),
+        status("verifying sha256 digest"),
+        status("writing manifest"),
+        status("success"),
+    ] {
+        let _ = acc.accumulate(message);
+    }
+    drop(acc);
+    assert!(
+        output.text().ends_with(
+            "verifying sha256 digest\n\
+             pulled aaaaaaaaaaaa (100 B)\n\
+             writing manifest\n\
+             success\n"
+        ),
+        "{}",
+        output.text()
+    );
+}
+
+#[test]
+fn resumed_layers_say_so() {
+    let output = Shared::default();
+    let mut acc = PullProgressAccumulator::new(output.clone(), false);
+    let _ = acc.accumulate(layer(A, 3 << 29, 4 << 30));
+    let _ = acc.accumulate(layer(B, 0, 2048));
+    let _ = acc.accumulate(layer(A, 4 << 30, 4 << 30));
+    let _ = acc.accumulate(layer(B, 2048, 2048));
+    let _ = acc.accumulate(status("success"));
+    assert_eq!(
+        "resuming aaaaaaaaaaaa at 1.5 GiB of 4.0 GiB\n\
+         pulled aaaaaaaaaaaa (4.0 GiB)\n\
+         pulled bbbbbbbbbbbb (2.0 KiB)\n\
+         success\n",
+        output.text()
+    );
+}
+
+#[test]
+fn one_bar_per_layer_on_a_terminal() {
+    let output = Shared::default();
+    let mut acc = PullProgressAccumulator::new(output.clone(), true);
+    for message in [
+        status("pulling manifest"),
+        layer(A, 0, 100),
+        layer(B, 25, 50),
+        layer(A, 50, 100),
+        layer(A, 100, 100),
+        status("verifying sha256 digest"),
+    ] {
+        let _ = acc.accumulate(message);
+    }
+    acc.finish();
+    let text = output.text();
+    // NOTE(rescrv):  Every redraw moves back over the lines it drew last time.
+    let frame = text.rsplit("\x1b[4F").next().unwrap();
+    let lines = frame
+        .split("\x1b[2K")
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    assert_eq!(4, lines.len(), "{text:?}");
+    assert_eq!("pulling manifest\n", lines[0]);
+    assert!(
+        lines[1].starts_with(&format!(
+            "pulling aaaaaaaaaaaa 100% [{}] 100 B/100 B ",
+            "=".repeat(30)
+        )),
+        "{:?}",
+        lines[1]
+    );
+    assert!(
+        lines[2].starts_with(&format!(
+            "pulling bbbbbbbbbbbb  50% [{}>{}] 25 B/50 B ",
+            "=".repeat(15),
+            " ".repeat(14)
+        )),
+        "{:?}",
+        lines[2]
+    );
+    assert_eq!("verifying sha256 digest\n", lines[3]);
+}
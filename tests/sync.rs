@@ -0,0 +1,85 @@
+use yammer::modelfile::Modelfile;
+use yammer::sync::{manifest_url, Manifest, PullEntry};
+
+#[test]
+fn manifest_paths_are_relative_to_the_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("manifest.toml");
+    std::fs::write(
+        &path,
+        r#"
+pull = ["llama3", { name = "nomic-embed-text", digest = "0a109f422b47" }]
+
+[[create]]
+name = "local"
+modelfile = """
+FROM ./weights.gguf
+ADAPTER ../adapter.gguf
+PARAMETER temperature 0.2
+"""
+
+[[create]]
+name = "terse"
+file = "terse.Modelfile"
+"#,
+    )
+    .unwrap();
+    let manifest = Manifest::load(&path).unwrap();
+    assert_eq!(
+        vec![
+            PullEntry::Name("llama3".to_string()),
+            PullEntry::Pinned {
+                name: "nomic-embed-text".to_string(),
+                digest: "0a109f422b47".to_string(),
+            },
+        ],
+        manifest.pull
+    );
+    let inline = manifest.create[0]
+        .modelfile
+        .as_ref()
+        .unwrap()
+        .parse::<Modelfile>()
+        .unwrap();
+    assert_eq!(
+        dir.path().join("weights.gguf").display().to_string(),
+        inline.from
+    );
+    assert_eq!(
+        vec![dir.path().join("../adapter.gguf").display().to_string()],
+        inline.adapters
+    );
+    assert_eq!(vec!["0.2"], inline.parameter("temperature"));
+    assert_eq!(
+        Some(dir.path().join("terse.Modelfile").display().to_string()),
+        manifest.create[1].file
+    );
+}
+
+#[test]
+fn manifest_urls() {
+    for (name, url) in [
+        (
+            "llama3",
+            "https://registry.ollama.ai/v2/library/llama3/manifests/latest",
+        ),
+        (
+            "llama3.1:8b",
+            "https://registry.ollama.ai/v2/library/llama3.1/manifests/8b",
+        ),
+        (
+            "user/model:q4",
+            "https://registry.ollama.ai/v2/user/model/manifests/q4",
+        ),
+        (
+            "hf.co/org/repo:Q8_0",
+            "https://hf.co/v2/org/repo/manifests/Q8_0",
+        ),
+        (
+            "localhost:5000/team/model",
+            "https://localhost:5000/v2/team/model/manifests/latest",
+        ),
+    ] {
+        assert_eq!(url, manifest_url(name), "{name}");
+    }
+}
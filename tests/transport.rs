@@ -0,0 +1,427 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use yammer::{CancellationToken, Error, GenerateRequest, Request, RequestOptions, VecAccumulator};
+
+/////////////////////////////////////////////// server /////////////////////////////////////////////
+
+const RESPONSE: &str = concat!(
+    r#"{"model":"test","response":"Hello","done":false}"#,
+    "\n",
+    r#"{"model":"test","response":" world","done":true}"#,
+    "\n",
+);
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/tls")
+        .join(name)
+}
+
+/// The request line and body of the HTTP request at the start of `buf`, once all of it is there.
+fn parse_request(buf: &[u8]) -> Option<(String, serde_json::Value)> {
+    let header_end = buf.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let headers = String::from_utf8(buf[..header_end].to_vec()).unwrap();
+    let length: usize = headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .map(|(_, value)| value.trim().parse().unwrap())
+        .unwrap_or(0);
+    if buf.len() < header_end + length {
+        return None;
+    }
+    let request_line = headers.lines().next().unwrap_or_default().to_string();
+    let body = serde_json::from_slice(&buf[header_end..header_end + length]).unwrap();
+    Some((request_line, body))
+}
+
+/// Read one HTTP request and return its request line and body.
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> (String, serde_json::Value) {
+    let mut buf = vec![];
+    loop {
+        if let Some(request) = parse_request(&buf) {
+            return request;
+        }
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "connection closed before the request was read");
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// An HTTP response with `status` and an NDJSON `body`.
+fn response(status: &str, body: &str, close: bool) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/x-ndjson\r\nContent-Length: {}\r\n{}\r\n{}",
+        body.len(),
+        if close { "Connection: close\r\n" } else { "" },
+        body
+    )
+}
+
+/// Write one HTTP response with `status` and an NDJSON `body`, then close the connection.
+async fn respond<S: AsyncWrite + Unpin>(stream: &mut S, status: &str, body: &str) {
+    stream
+        .write_all(response(status, body, true).as_bytes())
+        .await
+        .unwrap();
+    stream.shutdown().await.unwrap();
+}
+
+/// Serve one HTTP request with canned NDJSON and return the request line and body it carried.
+async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> (String, serde_json::Value) {
+    let request = read_request(&mut stream).await;
+    respond(&mut stream, "200 OK", RESPONSE).await;
+    request
+}
+
+async fn try_generate(options: RequestOptions) -> Result<Vec<serde_json::Value>, Error> {
+    let generate = GenerateRequest {
+        model: "test".to_string(),
+        prompt: "Say hello.".to_string(),
+        ..Default::default()
+    };
+    let mut messages = vec![];
+    Request::generate(options, generate)?
+        .accumulate(&mut VecAccumulator::new(&mut messages))
+        .await?;
+    Ok(messages)
+}
+
+async fn generate(options: RequestOptions) -> Vec<serde_json::Value> {
+    try_generate(options).await.unwrap()
+}
+
+fn check(messages: &[serde_json::Value], request_line: &str, body: &serde_json::Value) {
+    assert_eq!("POST /api/generate HTTP/1.1", request_line);
+    assert_eq!("test", body["model"]);
+    assert_eq!("Say hello.", body["prompt"]);
+    let responses = messages
+        .iter()
+        .map(|m| m["response"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["Hello", " world"], responses);
+}
+
+//////////////////////////////////////////////// unix //////////////////////////////////////////////
+
+#[tokio::test]
+async fn unix_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("ollama.sock");
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut seen = vec![];
+        for _ in 0..2 {
+            seen.push(read_request(&mut stream).await);
+            stream
+                .write_all(response("200 OK", RESPONSE, false).as_bytes())
+                .await
+                .unwrap();
+        }
+        seen
+    });
+    let options = RequestOptions {
+        url: Some(format!("unix://{}", path.display())),
+        ..Default::default()
+    };
+    let first = generate(options.clone()).await;
+    // NOTE(rescrv):  Nothing can dial the socket once it is removed.  The second request succeeds
+    // only if it reuses the connection pooled by the client that sent the first.
+    std::fs::remove_file(&path).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let second = generate(options).await;
+    let seen = server.await.unwrap();
+    check(&first, &seen[0].0, &seen[0].1);
+    check(&second, &seen[1].0, &seen[1].1);
+}
+
+///////////////////////////////////////////////// tls //////////////////////////////////////////////
+
+/// Accept TLS as localhost, with a certificate signed by the test CA.
+fn acceptor() -> tokio_native_tls::TlsAcceptor {
+    let cert = std::fs::read(fixture("server.pem")).unwrap();
+    let key = std::fs::read(fixture("server.key")).unwrap();
+    let identity = native_tls::Identity::from_pkcs8(&cert, &key).unwrap();
+    native_tls::TlsAcceptor::new(identity).unwrap().into()
+}
+
+/// Serve `connections` HTTPS requests from clients that present the certificate in client.pem.
+///
+/// Each connection yields its request, or None if the client was turned away.  native-tls cannot
+/// ask for a client certificate, so this server uses openssl on a thread of its own.
+fn serve_mutual_tls(
+    listener: std::net::TcpListener,
+    connections: usize,
+) -> std::thread::JoinHandle<Vec<Option<(String, serde_json::Value)>>> {
+    use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+    use std::io::{Read, Write};
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file(fixture("server.key"), SslFiletype::PEM)
+        .unwrap();
+    acceptor
+        .set_certificate_chain_file(fixture("server.pem"))
+        .unwrap();
+    acceptor.set_ca_file(fixture("client.pem")).unwrap();
+    acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let acceptor = acceptor.build();
+    std::thread::spawn(move || {
+        listener
+            .incoming()
+            .take(connections)
+            .map(|stream| {
+                let mut stream = acceptor.accept(stream.unwrap()).ok()?;
+                let mut buf = vec![];
+                let request = loop {
+                    if let Some(request) = parse_request(&buf) {
+                        break request;
+                    }
+                    let mut chunk = [0u8; 4096];
+                    let n = stream.read(&mut chunk).ok().filter(|n| *n > 0)?;
+                    buf.extend_from_slice(&chunk[..n]);
+                };
+                stream
+                    .write_all(response("200 OK", RESPONSE, true).as_bytes())
+                    .unwrap();
+                let _ = stream.shutdown();
+                Some(request)
+            })
+            .collect()
+    })
+}
+
+#[tokio::test]
+async fn tls_with_ca_cert() {
+    let acceptor = acceptor();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let mut seen = vec![];
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            seen.push(serve(stream).await);
+        }
+        seen
+    });
+    // NOTE(rescrv):  The CA is copied so that it can be removed between requests.  The second
+    // request succeeds only if it reuses the client built by the first.
+    let ca_cert = std::env::temp_dir().join(format!("yammer-ca-{}.pem", std::process::id()));
+    std::fs::copy(fixture("ca.pem"), &ca_cert).unwrap();
+    let options = RequestOptions {
+        url: Some(format!("https://localhost:{port}")),
+        ca_cert: Some(ca_cert.to_string_lossy().to_string()),
+        ..Default::default()
+    };
+    let first = generate(options.clone()).await;
+    std::fs::remove_file(&ca_cert).unwrap();
+    let second = generate(options).await;
+    let seen = server.await.unwrap();
+    check(&first, &seen[0].0, &seen[0].1);
+    check(&second, &seen[1].0, &seen[1].1);
+}
+
+#[tokio::test]
+async fn tls_with_client_cert() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = serve_mutual_tls(listener, 2);
+    let options = RequestOptions {
+        url: Some(format!("https://localhost:{port}")),
+        ca_cert: Some(fixture("ca.pem").to_string_lossy().to_string()),
+        ..Default::default()
+    };
+    let client_cert = Some(fixture("client.pem").to_string_lossy().to_string());
+    let client_key = Some(fixture("client.key").to_string_lossy().to_string());
+    let messages = generate(RequestOptions {
+        client_cert: client_cert.clone(),
+        client_key,
+        ..options.clone()
+    })
+    .await;
+    assert!(try_generate(options.clone()).await.is_err());
+    let seen = server.join().unwrap();
+    let (request_line, body) = seen[0].as_ref().unwrap();
+    check(&messages, request_line, body);
+    assert!(seen[1].is_none());
+    match try_generate(RequestOptions {
+        client_cert,
+        ..options
+    })
+    .await
+    {
+        Err(Error::Message(err)) => assert!(err.contains("--client-key"), "{err}"),
+        other => panic!("{other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn tls_insecure() {
+    let acceptor = acceptor();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let mut seen = vec![];
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().await.unwrap();
+            seen.push(match acceptor.accept(stream).await {
+                Ok(stream) => Some(serve(stream).await),
+                Err(_) => None,
+            });
+        }
+        seen
+    });
+    // NOTE(rescrv):  The test CA is in no trust store, so only an insecure client connects.
+    let options = RequestOptions {
+        url: Some(format!("https://localhost:{port}")),
+        ..Default::default()
+    };
+    assert!(try_generate(options.clone()).await.is_err());
+    let messages = generate(RequestOptions {
+        insecure: true,
+        ..options
+    })
+    .await;
+    let seen = server.await.unwrap();
+    assert!(seen[0].is_none());
+    let (request_line, body) = seen[1].as_ref().unwrap();
+    check(&messages, request_line, body);
+}
+
+///////////////////////////////////////////// auto-pull ////////////////////////////////////////////
+
+const MISSING: &str = r#"{"error":"model \"test\" not found, try pulling it first"}"#;
+
+/// How the stub server answers a generate request after the model has been pulled.
+#[derive(Clone, Copy)]
+enum Retry {
+    Succeed,
+    StillMissing,
+    Stall,
+}
+
+/// Serve ollama with the model "test" missing until it is pulled, recording every request.
+///
+/// A stalled retry cancels `cancel` and then never answers.
+async fn pulling(
+    listener: tokio::net::TcpListener,
+    retry: Retry,
+    cancel: CancellationToken,
+    seen: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+) {
+    let mut pulled = false;
+    loop {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let (request_line, body) = read_request(&mut stream).await;
+        seen.lock().unwrap().push((request_line.clone(), body));
+        match (request_line.as_str(), pulled, retry) {
+            ("POST /api/pull HTTP/1.1", _, _) => {
+                pulled = true;
+                let body = concat!(
+                    r#"{"status":"pulling manifest"}"#,
+                    "\n",
+                    r#"{"status":"success"}"#,
+                    "\n",
+                );
+                respond(&mut stream, "200 OK", body).await;
+            }
+            (_, false, _) | (_, true, Retry::StillMissing) => {
+                respond(&mut stream, "404 Not Found", MISSING).await;
+            }
+            (_, true, Retry::Succeed) => {
+                respond(&mut stream, "200 OK", RESPONSE).await;
+            }
+            (_, true, Retry::Stall) => {
+                cancel.cancel();
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            }
+        }
+    }
+}
+
+/// Generate with auto-pull against a [pulling] server and return the result and the requests.
+async fn auto_pull(
+    retry: Retry,
+) -> (
+    Result<Vec<serde_json::Value>, Error>,
+    Vec<(String, serde_json::Value)>,
+) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let cancel = CancellationToken::new();
+    let seen = Arc::new(Mutex::new(vec![]));
+    let server = tokio::spawn(pulling(listener, retry, cancel.clone(), Arc::clone(&seen)));
+    let options = RequestOptions {
+        url: Some(format!("http://127.0.0.1:{port}")),
+        auto_pull: true,
+        ..Default::default()
+    };
+    let generate = GenerateRequest {
+        model: "test".to_string(),
+        prompt: "Say hello.".to_string(),
+        ..Default::default()
+    };
+    let mut messages = vec![];
+    let result = Request::generate(options, generate)
+        .unwrap()
+        .with_cancellation(cancel)
+        .accumulate(&mut VecAccumulator::new(&mut messages))
+        .await;
+    server.abort();
+    let seen = seen.lock().unwrap().clone();
+    (result.map(|()| messages), seen)
+}
+
+fn request_lines(seen: &[(String, serde_json::Value)]) -> Vec<&str> {
+    seen.iter().map(|(line, _)| line.as_str()).collect()
+}
+
+#[tokio::test]
+async fn missing_model_pulls_once_and_retries_once() {
+    let (messages, seen) = auto_pull(Retry::Succeed).await;
+    assert_eq!(
+        vec![
+            "POST /api/generate HTTP/1.1",
+            "POST /api/pull HTTP/1.1",
+            "POST /api/generate HTTP/1.1",
+        ],
+        request_lines(&seen)
+    );
+    assert_eq!("test", seen[1].1["model"]);
+    assert_eq!(seen[0].1, seen[2].1);
+    // NOTE(rescrv):  Only the retry reaches the accumulator; the pull's progress goes elsewhere.
+    let (request_line, body) = &seen[2];
+    check(&messages.unwrap(), request_line, body);
+}
+
+#[tokio::test]
+async fn missing_model_after_the_pull_is_an_error() {
+    let (result, seen) = auto_pull(Retry::StillMissing).await;
+    match result {
+        Err(Error::Message(err)) => assert!(err.contains("not found"), "{err}"),
+        other => panic!("{other:?}"),
+    }
+    assert_eq!(
+        vec![
+            "POST /api/generate HTTP/1.1",
+            "POST /api/pull HTTP/1.1",
+            "POST /api/generate HTTP/1.1",
+        ],
+        request_lines(&seen)
+    );
+}
+
+#[tokio::test]
+async fn retry_after_the_pull_can_be_cancelled() {
+    let (result, seen) =
+        tokio::time::timeout(std::time::Duration::from_secs(10), auto_pull(Retry::Stall))
+            .await
+            .expect("the cancelled retry should return at once");
+    assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+    assert_eq!(3, seen.len());
+}